@@ -0,0 +1,93 @@
+//! Time taken per performed instruction in tight loops.
+
+use bookmark_language::{
+    asm,
+    instruction::DefaultLoader,
+    limits::{Fuel, Limits},
+    program::Program,
+    value::Value,
+    Error,
+};
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+const STEPS: u64 = 30_000;
+
+/// Increments a variable `STEPS / 3` times, one instruction at a time.
+fn straight_line() -> Program {
+    let mut src = "rw n = 0\n".to_owned();
+    for _ in 0..STEPS / 3 {
+        src.push_str("take n\nop add 1\nassign n\n");
+    }
+    asm::parse(&src).expect("valid program")
+}
+
+/// Increments a variable forever by performing itself, stopped after `STEPS` steps.
+fn recursion() -> Program {
+    asm::parse(
+        "rw n = 0
+        ro l = instr { take n; op add 1; assign n; clone l; perform none }
+        clone l
+        perform none",
+    )
+    .expect("valid program")
+}
+
+fn instructions(c: &mut Criterion) {
+    let mut group = c.benchmark_group("instructions");
+    group.throughput(Throughput::Elements(STEPS));
+
+    let program = straight_line();
+    group.bench_function("straight_line", |b| {
+        b.iter(|| {
+            let result = program
+                .clone()
+                .run_to_completion(black_box(Value::None), &DefaultLoader);
+            assert_eq!(result, Ok(Value::None));
+        });
+    });
+
+    let bytecode = program.compile();
+    group.bench_function("straight_line_bytecode", |b| {
+        b.iter(|| {
+            let result = bytecode.run_to_completion(black_box(Value::None), &DefaultLoader);
+            assert_eq!(result, Ok(Value::None));
+        });
+    });
+
+    let program = recursion();
+    group.bench_function("recursion", |b| {
+        b.iter(|| {
+            let mut limits = Limits {
+                fuel: Fuel::new(STEPS),
+                ..Limits::new()
+            };
+            let result = program.clone().run_to_completion_with_limits(
+                black_box(Value::None),
+                &DefaultLoader,
+                &mut limits,
+            );
+            assert_eq!(result, Err(Error::OutOfFuel));
+        });
+    });
+
+    let bytecode = program.compile();
+    group.bench_function("recursion_bytecode", |b| {
+        b.iter(|| {
+            let mut limits = Limits {
+                fuel: Fuel::new(STEPS),
+                ..Limits::new()
+            };
+            let result = bytecode.run_to_completion_with_limits(
+                black_box(Value::None),
+                &DefaultLoader,
+                &mut limits,
+            );
+            assert_eq!(result, Err(Error::OutOfFuel));
+        });
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, instructions);
+criterion_main!(benches);
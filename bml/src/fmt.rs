@@ -0,0 +1,61 @@
+//! Formatting program files.
+
+use crate::Error;
+use bookmark_language::asm;
+use clap::Args;
+use std::{
+    fs,
+    io::{self, Read},
+    path::PathBuf,
+    process::ExitCode,
+};
+
+#[derive(Args)]
+pub struct FmtArgs {
+    /// Files in the text format to format in place, if there are none stdin is formatted to
+    /// stdout.
+    files: Vec<PathBuf>,
+
+    /// Only print the files that are not formatted, and exit with status 1 if there are any.
+    #[arg(long)]
+    check: bool,
+}
+
+pub fn run(args: FmtArgs) -> Result<ExitCode, Error> {
+    let FmtArgs { files, check } = args;
+
+    if files.is_empty() {
+        let mut src = String::new();
+        io::stdin().read_to_string(&mut src)?;
+        let formatted = asm::format(&src).map_err(|err| Error::Parse("stdin".into(), err))?;
+        if !check {
+            print!("{formatted}");
+        } else if formatted != src {
+            println!("stdin");
+            return Ok(ExitCode::FAILURE);
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let mut is_formatted = true;
+    for file in files {
+        let src = fs::read_to_string(&file).map_err(|err| Error::Read(file.clone(), err))?;
+        let formatted = asm::format(&src).map_err(|err| Error::Parse(file.clone(), err))?;
+        if formatted == src {
+            continue;
+        }
+
+        if check {
+            println!("{}", file.display());
+            is_formatted = false;
+        } else {
+            fs::write(&file, formatted).map_err(|err| Error::Write(file, err))?;
+        }
+    }
+
+    Ok(if is_formatted {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
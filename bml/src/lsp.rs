@@ -0,0 +1,398 @@
+//! Language server for the text format, speaking the language server protocol over stdio.
+//!
+//! Documents are analyzed with [`asm::analyze`] whenever they change, which provides the
+//! diagnostics, hovers and definitions. Only full document synchronization is supported.
+
+use crate::Error;
+use bookmark_language::{
+    asm::{self, Analysis, Span},
+    instruction::Instruction,
+};
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::{
+    notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+        Notification as NotificationTrait, PublishDiagnostics,
+    },
+    request::{Completion, GotoDefinition, HoverRequest, Request as RequestTrait},
+    CompletionItem, CompletionItemKind, CompletionOptions, Diagnostic, DiagnosticSeverity,
+    GotoDefinitionResponse, Hover, HoverContents, HoverProviderCapability, Location, MarkupContent,
+    MarkupKind, OneOf, Position, PublishDiagnosticsParams, Range, ServerCapabilities,
+    TextDocumentSyncCapability, TextDocumentSyncKind, Url,
+};
+use std::collections::HashMap;
+
+struct Document {
+    text: String,
+    analysis: Analysis,
+}
+
+impl Document {
+    fn new(text: String) -> Self {
+        let analysis = asm::analyze(&text);
+        Self { text, analysis }
+    }
+
+    /// The protocol counts lines from 0 and columns in UTF-16 code units.
+    fn position(&self, position: asm::Position) -> Position {
+        let line = self.text.lines().nth(position.line - 1).unwrap_or_default();
+        let character: usize = line
+            .chars()
+            .take(position.column - 1)
+            .map(char::len_utf16)
+            .sum();
+        Position::new(saturating_u32(position.line - 1), saturating_u32(character))
+    }
+
+    fn asm_position(&self, position: Position) -> asm::Position {
+        let line = self
+            .text
+            .lines()
+            .nth(position.line as usize)
+            .unwrap_or_default();
+        let mut units = 0;
+        let column = line
+            .chars()
+            .take_while(|c| {
+                units += c.len_utf16();
+                units <= position.character as usize
+            })
+            .count();
+        asm::Position {
+            line: position.line as usize + 1,
+            column: column + 1,
+        }
+    }
+
+    fn range(&self, span: Span) -> Range {
+        Range::new(self.position(span.start), self.position(span.end))
+    }
+
+    fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.analysis
+            .diagnostics
+            .iter()
+            .map(|err| Diagnostic {
+                range: self.range(err.span),
+                severity: Some(DiagnosticSeverity::ERROR),
+                source: Some("bml".to_owned()),
+                message: err.message.clone(),
+                ..Diagnostic::default()
+            })
+            .collect()
+    }
+
+    fn hover(&self, position: Position) -> Option<Hover> {
+        let position = self.asm_position(position);
+        let symbol = self.analysis.symbol_at(position)?;
+        let span = std::iter::once(&symbol.declaration)
+            .chain(&symbol.references)
+            .find(|span| span.contains(position))?;
+
+        let kind = if symbol.id.is_read_only() { "ro" } else { "rw" };
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: format!("```\n{kind} {} = {:#}\n```", symbol.name, symbol.value),
+            }),
+            range: Some(self.range(*span)),
+        })
+    }
+
+    fn definition(&self, uri: Url, position: Position) -> Option<GotoDefinitionResponse> {
+        let symbol = self.analysis.symbol_at(self.asm_position(position))?;
+        Some(GotoDefinitionResponse::Scalar(Location::new(
+            uri,
+            self.range(symbol.declaration),
+        )))
+    }
+}
+
+fn saturating_u32(value: usize) -> u32 {
+    u32::try_from(value).unwrap_or(u32::MAX)
+}
+
+fn completions() -> Vec<CompletionItem> {
+    Instruction::NAMES
+        .iter()
+        .map(|name| CompletionItem {
+            label: (*name).to_owned(),
+            kind: Some(CompletionItemKind::KEYWORD),
+            detail: Some("instruction".to_owned()),
+            ..CompletionItem::default()
+        })
+        .collect()
+}
+
+/// Serve requests on stdin and stdout until the client shuts the server down.
+pub fn run() -> Result<(), Error> {
+    let (connection, io_threads) = Connection::stdio();
+    serve(&connection)?;
+    drop(connection);
+    io_threads.join()?;
+    Ok(())
+}
+
+fn serve(connection: &Connection) -> Result<(), Error> {
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        completion_provider: Some(CompletionOptions::default()),
+        ..ServerCapabilities::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+
+    let mut documents = HashMap::new();
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    break;
+                }
+                let response = respond(&documents, request)?;
+                send(connection, response)?;
+            }
+            Message::Notification(notification) => {
+                if let Some(diagnostics) = update(&mut documents, notification)? {
+                    send(connection, diagnostics)?;
+                }
+            }
+            Message::Response(_) => (),
+        }
+    }
+    Ok(())
+}
+
+fn send(connection: &Connection, message: impl Into<Message>) -> Result<(), Error> {
+    connection
+        .sender
+        .send(message.into())
+        .map_err(|_| Error::Disconnected)
+}
+
+fn respond(documents: &HashMap<Url, Document>, request: Request) -> Result<Response, Error> {
+    let id = request.id.clone();
+    let result = match request.method.as_str() {
+        HoverRequest::METHOD => {
+            let params = match params::<HoverRequest>(request) {
+                Ok(params) => params.text_document_position_params,
+                Err(response) => return Ok(response),
+            };
+            let hover = documents
+                .get(&params.text_document.uri)
+                .and_then(|document| document.hover(params.position));
+            serde_json::to_value(hover)?
+        }
+        GotoDefinition::METHOD => {
+            let params = match params::<GotoDefinition>(request) {
+                Ok(params) => params.text_document_position_params,
+                Err(response) => return Ok(response),
+            };
+            let definition = documents
+                .get(&params.text_document.uri)
+                .and_then(|document| {
+                    document.definition(params.text_document.uri, params.position)
+                });
+            serde_json::to_value(definition)?
+        }
+        Completion::METHOD => serde_json::to_value(completions())?,
+        method => {
+            return Ok(Response::new_err(
+                id,
+                ErrorCode::MethodNotFound as i32,
+                format!("unsupported request `{method}`"),
+            ))
+        }
+    };
+    Ok(Response::new_ok(id, result))
+}
+
+/// Parameters of the request, or the error response to send if they are malformed.
+fn params<R: RequestTrait>(request: Request) -> Result<R::Params, Response> {
+    let id = request.id.clone();
+    request
+        .extract(R::METHOD)
+        .map(|(_, params)| params)
+        .map_err(|err| Response::new_err(id, ErrorCode::InvalidParams as i32, err.to_string()))
+}
+
+/// Apply a change to the open documents, returning the diagnostics to publish.
+fn update(
+    documents: &mut HashMap<Url, Document>,
+    notification: Notification,
+) -> Result<Option<Notification>, Error> {
+    let (uri, diagnostics) = match notification.method.as_str() {
+        DidOpenTextDocument::METHOD => {
+            let params = extract_notification::<DidOpenTextDocument>(notification)?;
+            let document = Document::new(params.text_document.text);
+            let diagnostics = document.diagnostics();
+            documents.insert(params.text_document.uri.clone(), document);
+            (params.text_document.uri, diagnostics)
+        }
+        DidChangeTextDocument::METHOD => {
+            let params = extract_notification::<DidChangeTextDocument>(notification)?;
+            let Some(change) = params.content_changes.into_iter().last() else {
+                return Ok(None);
+            };
+            let document = Document::new(change.text);
+            let diagnostics = document.diagnostics();
+            documents.insert(params.text_document.uri.clone(), document);
+            (params.text_document.uri, diagnostics)
+        }
+        DidCloseTextDocument::METHOD => {
+            let params = extract_notification::<DidCloseTextDocument>(notification)?;
+            documents.remove(&params.text_document.uri);
+            (params.text_document.uri, Vec::new())
+        }
+        _ => return Ok(None),
+    };
+
+    Ok(Some(Notification::new(
+        PublishDiagnostics::METHOD.to_owned(),
+        PublishDiagnosticsParams::new(uri, diagnostics, None),
+    )))
+}
+
+fn extract_notification<N: NotificationTrait>(
+    notification: Notification,
+) -> Result<N::Params, Error> {
+    notification
+        .extract(N::METHOD)
+        .map_err(|err| Error::Lsp(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lsp_types::{
+        request::{Initialize, Shutdown},
+        HoverParams, InitializeParams, TextDocumentIdentifier, TextDocumentPositionParams,
+    };
+    use std::thread;
+
+    #[test]
+    fn session() {
+        let (server, client) = Connection::memory();
+        let server = thread::spawn(move || serve(&server));
+
+        let request = |id: i32, method: &str, params: serde_json::Value| {
+            client
+                .sender
+                .send(Request::new(id.into(), method.to_owned(), params).into())
+                .expect("server is running");
+            match client.receiver.recv().expect("server is running") {
+                Message::Response(response) => response.result,
+                message => panic!("expected a response, got {message:?}"),
+            }
+        };
+        let notify = |method: &str, params: serde_json::Value| {
+            client
+                .sender
+                .send(Notification::new(method.to_owned(), params).into())
+                .expect("server is running");
+        };
+
+        request(
+            1,
+            Initialize::METHOD,
+            serde_json::json!(InitializeParams::default()),
+        );
+        notify("initialized", serde_json::json!({}));
+
+        let uri = Url::parse("file:///fib.bml").expect("valid url");
+        notify(
+            DidOpenTextDocument::METHOD,
+            serde_json::json!({
+                "textDocument": {
+                    "uri": uri,
+                    "languageId": "bml",
+                    "version": 1,
+                    "text": "ro l = [1, \"ü\"]\ntake l\n",
+                }
+            }),
+        );
+        let Ok(Message::Notification(published)) = client.receiver.recv() else {
+            panic!("expected diagnostics");
+        };
+        let published: PublishDiagnosticsParams =
+            serde_json::from_value(published.params).expect("valid diagnostics");
+        assert_eq!(published.diagnostics.len(), 1);
+        assert_eq!(
+            published.diagnostics[0].message,
+            "`take` cannot be used with read-only variable `l`"
+        );
+        assert_eq!(
+            published.diagnostics[0].range,
+            Range::new(Position::new(1, 5), Position::new(1, 6))
+        );
+
+        let hover = request(
+            2,
+            HoverRequest::METHOD,
+            serde_json::json!(HoverParams {
+                text_document_position_params: TextDocumentPositionParams::new(
+                    TextDocumentIdentifier::new(uri),
+                    Position::new(1, 5),
+                ),
+                work_done_progress_params: lsp_types::WorkDoneProgressParams::default(),
+            }),
+        );
+        assert_eq!(
+            hover.expect("hover")["contents"]["value"],
+            "```\nro l = [1, \"ü\"]\n```"
+        );
+
+        assert_eq!(
+            request(3, Shutdown::METHOD, serde_json::Value::Null),
+            Some(serde_json::Value::Null)
+        );
+        notify("exit", serde_json::Value::Null);
+        assert!(server.join().expect("server thread").is_ok());
+    }
+
+    #[test]
+    fn malformed_params() {
+        let (server, client) = Connection::memory();
+        let server = thread::spawn(move || serve(&server));
+
+        let request = |id: i32, method: &str, params: serde_json::Value| {
+            client
+                .sender
+                .send(Request::new(id.into(), method.to_owned(), params).into())
+                .expect("server is running");
+            match client.receiver.recv().expect("server is running") {
+                Message::Response(response) => response,
+                message => panic!("expected a response, got {message:?}"),
+            }
+        };
+
+        request(
+            1,
+            Initialize::METHOD,
+            serde_json::json!(InitializeParams::default()),
+        );
+        client
+            .sender
+            .send(Notification::new("initialized".to_owned(), serde_json::json!({})).into())
+            .expect("server is running");
+
+        let hover = request(
+            2,
+            HoverRequest::METHOD,
+            serde_json::json!({ "textDocument": 1 }),
+        );
+        assert_eq!(
+            hover.error.map(|err| err.code),
+            Some(ErrorCode::InvalidParams as i32)
+        );
+
+        let shutdown = request(3, Shutdown::METHOD, serde_json::Value::Null);
+        assert_eq!(shutdown.result, Some(serde_json::Value::Null));
+        client
+            .sender
+            .send(Notification::new("exit".to_owned(), serde_json::Value::Null).into())
+            .expect("server is running");
+        assert!(server.join().expect("server thread").is_ok());
+    }
+}
@@ -0,0 +1,76 @@
+//! Command line tools for bookmark-language programs.
+
+#![warn(missing_copy_implementations, clippy::unwrap_used, clippy::pedantic)]
+
+use bookmark_language::asm;
+use clap::{Parser, Subcommand};
+use std::{io, path::PathBuf, process::ExitCode};
+use thiserror::Error;
+
+mod fmt;
+mod lsp;
+mod repl;
+mod run;
+
+#[derive(Parser)]
+#[command(name = "bml", about = "Tools for bookmark-language programs")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Evaluate instructions interactively, this is the default.
+    Repl,
+    /// Run a program and print the result.
+    ///
+    /// Exits with status 1 if the program fails and 2 if it could not be run at all.
+    Run(run::RunArgs),
+    /// Format programs written in the text format.
+    ///
+    /// Exits with status 1 if --check is given and a file is not formatted.
+    Fmt(fmt::FmtArgs),
+    /// Start a language server for the text format, communicating over stdin and stdout.
+    Lsp,
+}
+
+#[derive(Error, Debug)]
+enum Error {
+    #[error(transparent)]
+    IO(#[from] io::Error),
+    #[error("{}: {1}", .0.display())]
+    Read(PathBuf, io::Error),
+    #[error("{}: {1}", .0.display())]
+    Write(PathBuf, io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error("{}:{1}", .0.display())]
+    Parse(PathBuf, asm::ParseError),
+    #[error("invalid value for --{0}: {1}")]
+    Argument(&'static str, String),
+    #[error(transparent)]
+    Protocol(#[from] lsp_server::ProtocolError),
+    #[error("invalid message from the client: {0}")]
+    Lsp(String),
+    #[error("connection to the client was closed")]
+    Disconnected,
+}
+
+fn main() -> ExitCode {
+    let Cli { command } = Cli::parse();
+
+    let result = match command.unwrap_or(Command::Repl) {
+        Command::Repl => repl::run(io::stdin().lock(), io::stdout().lock())
+            .map(|()| ExitCode::SUCCESS)
+            .map_err(Error::from),
+        Command::Run(args) => run::run(args),
+        Command::Fmt(args) => fmt::run(args),
+        Command::Lsp => lsp::run().map(|()| ExitCode::SUCCESS),
+    };
+
+    result.unwrap_or_else(|err| {
+        eprintln!("error: {err}");
+        ExitCode::from(2)
+    })
+}
@@ -0,0 +1,284 @@
+//! Interactive evaluation of instructions against a persistent set of variables.
+
+use bookmark_language::{
+    asm::Scope,
+    instruction::{DefaultLoader, Stack},
+    program::Running,
+    value::Value,
+    variable::{self, MapBuilder},
+};
+use std::{
+    fmt::Display,
+    io::{self, BufRead, Write},
+    mem,
+};
+
+const HELP: &str = "\
+<instruction>          push an instruction and run until the stack is empty
+:push <instruction>    push an instruction without running it
+:step [n]              perform the next n pending instructions (default 1)
+:run                   perform pending instructions until the stack is empty
+:clear                 drop all pending instructions
+:stack                 show pending instructions, next to be performed first
+:vars                  show all variables
+:value                 show the current return value
+:rw <name> [= value]   allocate a read-write variable
+:ro <name> [= value]   allocate a read-only variable
+:help                  show this message
+:quit                  exit";
+
+/// Run the repl until `input` is exhausted or `:quit` is entered.
+pub fn run(input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    let mut repl = Repl::default();
+    let mut lines = input.lines();
+    let mut pending = String::new();
+
+    loop {
+        write!(
+            output,
+            "{}",
+            if pending.is_empty() { "bml> " } else { "...> " }
+        )?;
+        output.flush()?;
+
+        let Some(line) = lines.next().transpose()? else {
+            break;
+        };
+        pending.push_str(&line);
+        pending.push('\n');
+
+        // blocks, lists and maps may span several lines
+        if open_delimiters(&pending) > 0 {
+            continue;
+        }
+
+        match repl.eval(mem::take(&mut pending).trim()) {
+            Flow::Continue(message) => {
+                if !message.is_empty() {
+                    writeln!(output, "{message}")?;
+                }
+            }
+            Flow::Quit => break,
+        }
+    }
+
+    Ok(())
+}
+
+/// Count unclosed `{` and `[` outside of strings and comments.
+fn open_delimiters(src: &str) -> isize {
+    let mut depth = 0;
+    let mut chars = src.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' | '[' => depth += 1,
+            '}' | ']' => depth -= 1,
+            '"' => {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '"' => break,
+                        _ => (),
+                    }
+                }
+            }
+            '#' => {
+                chars.by_ref().find(|&c| c == '\n');
+            }
+            _ => (),
+        }
+    }
+    depth
+}
+
+enum Flow {
+    Continue(String),
+    Quit,
+}
+
+#[derive(Debug, Default)]
+struct Repl {
+    variables: variable::Map,
+    stack: Stack,
+    value: Value,
+    scope: Scope,
+}
+
+impl Repl {
+    fn eval(&mut self, line: &str) -> Flow {
+        let (command, rest) = match line.strip_prefix(':') {
+            Some(command) => command
+                .split_once(char::is_whitespace)
+                .map_or((command, ""), |(command, rest)| (command, rest.trim())),
+            None if line.is_empty() => return Flow::Continue(String::new()),
+            None => ("", line),
+        };
+
+        let message = match command {
+            "" => self
+                .push(rest)
+                .and_then(|()| self.progress(None))
+                .map(|_| format!("= {}", self.value)),
+            "push" => self.push(rest).map(|()| String::new()),
+            "step" => if rest.is_empty() {
+                Ok(1)
+            } else {
+                rest.parse()
+                    .map_err(|_| format!("{rest:?} is not a number of steps"))
+            }
+            .and_then(|steps| self.progress(Some(steps)))
+            .map(|steps| format!("performed {steps} instructions\n= {}", self.value)),
+            "run" => self
+                .progress(None)
+                .map(|steps| format!("performed {steps} instructions\n= {}", self.value)),
+            "clear" => {
+                self.stack = Stack::new();
+                Ok(String::new())
+            }
+            "stack" => Ok(self.show_stack()),
+            "vars" => Ok(self.show_variables()),
+            "value" => Ok(format!("= {}", self.value)),
+            "rw" | "ro" => self.allocate(command == "ro", rest).map(|()| String::new()),
+            "help" => Ok(HELP.to_owned()),
+            "quit" | "q" => return Flow::Quit,
+            _ => Err(format!("unknown command :{command}, see :help")),
+        };
+
+        Flow::Continue(message.unwrap_or_else(|err| format!("error: {err}")))
+    }
+
+    fn push(&mut self, src: &str) -> Result<(), String> {
+        let instruction = self.scope.parse_instruction(src).map_err(display)?;
+        self.stack.push(instruction);
+        Ok(())
+    }
+
+    /// Perform pending instructions until the stack is empty or `limit` instructions have been
+    /// performed. If an instruction fails every change made is rolled back.
+    fn progress(&mut self, limit: Option<usize>) -> Result<usize, String> {
+        let snapshot = (
+            self.variables.clone(),
+            self.stack.clone(),
+            self.value.clone(),
+        );
+        let mut running = Running::Active {
+            variables: mem::take(&mut self.variables),
+            stack: mem::take(&mut self.stack),
+            return_value: mem::take(&mut self.value),
+        };
+
+        let mut steps = 0;
+        loop {
+            match running {
+                Running::Active { ref stack, .. }
+                    if stack.is_empty() || limit.is_some_and(|limit| steps >= limit) =>
+                {
+                    break
+                }
+                Running::Active { .. } => {
+                    running = running.progress(&DefaultLoader);
+                    steps += 1;
+                }
+                Running::Finished(result) => {
+                    (self.variables, self.stack, self.value) = snapshot;
+                    return Err(match result {
+                        // paths of instructions entered in the repl mean nothing to the user
+                        Err(err) => display(err.unlocated()),
+                        Ok(_) => "program finished unexpectedly".to_owned(),
+                    });
+                }
+            }
+        }
+
+        if let Running::Active {
+            variables,
+            stack,
+            return_value,
+        } = running
+        {
+            (self.variables, self.stack, self.value) = (variables, stack, return_value);
+        }
+        Ok(steps)
+    }
+
+    fn allocate(&mut self, is_read_only: bool, src: &str) -> Result<(), String> {
+        let (name, init) = src
+            .split_once('=')
+            .map_or((src, None), |(name, init)| (name, Some(init)));
+        let name = name.trim();
+
+        let mut builder = MapBuilder::from(self.variables.clone());
+        let id = if is_read_only {
+            builder.reserve_ro()
+        } else {
+            builder.reserve_rw()
+        };
+
+        let mut scope = self.scope.clone();
+        scope.declare(name, id).map_err(display)?;
+
+        if let Some(init) = init {
+            let value = scope.parse_value(init).map_err(display)?;
+            builder.set(id, value).map_err(display)?;
+        }
+
+        self.variables = builder.build();
+        self.scope = scope;
+        Ok(())
+    }
+
+    fn name_of(&self, id: variable::Id) -> String {
+        self.scope
+            .iter()
+            .find(|&(_, named)| named == id)
+            .map_or_else(|| id.to_string(), |(name, _)| name.to_owned())
+    }
+
+    fn show_stack(&self) -> String {
+        if self.stack.is_empty() {
+            return "stack is empty".to_owned();
+        }
+        self.stack
+            .iter()
+            .enumerate()
+            .map(|(i, instruction)| format!("{i:>3}: {instruction}"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn show_variables(&self) -> String {
+        if self.variables.iter().next().is_none() {
+            return "no variables".to_owned();
+        }
+        self.variables
+            .iter()
+            .map(|(id, value)| {
+                let kind = if id.is_read_only() { "ro" } else { "rw" };
+                format!("{kind} {} = {value}", self.name_of(id))
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+fn display(value: impl Display) -> String {
+    value.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn persistent_variables() {
+        let input = ":rw a = 1\n:ro l = instr {\n  take a\n  op add 2\n  assign a\n}\nclone l\nperform none\n:vars\n:push put 5\n:stack\n";
+        let mut output = Vec::new();
+        run(input.as_bytes(), &mut output).expect("writing to a vec cannot fail");
+
+        let output = String::from_utf8_lossy(&output);
+        assert!(output.contains("rw a = 3\nro l = instr { take @rw0; op add 2; assign @rw0 }"));
+        assert!(output.contains("  0: put 5"));
+    }
+}
@@ -0,0 +1,253 @@
+//! Running programs stored in files.
+
+use crate::Error;
+use bookmark_language::{
+    asm,
+    instruction::{loading, traits::Loader, DefaultLoader, Instruction},
+    limits::{Fuel, Limits},
+    observer::JsonTrace,
+    profiler::Profiler,
+    program::Program,
+    script,
+    value::Value,
+};
+use clap::{Args, ValueEnum};
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+#[derive(Args)]
+pub struct RunArgs {
+    /// Program to run.
+    file: PathBuf,
+
+    /// Format of the program, by default decided by the file extension.
+    #[arg(long, short)]
+    format: Option<Format>,
+
+    /// Input value given to the program.
+    #[arg(long, short, conflicts_with = "stdin")]
+    input: Option<String>,
+
+    /// Read the input value from stdin.
+    #[arg(long)]
+    stdin: bool,
+
+    /// Format of the input value.
+    #[arg(long, default_value = "text")]
+    input_format: Format,
+
+    /// Format used to print the result.
+    #[arg(long, default_value = "text")]
+    output_format: Format,
+
+    /// What the load instruction is able to load.
+    #[arg(long, short, default_value = "none")]
+    loader: LoaderKind,
+
+    /// Fail once the program, including the programs it runs, has taken this many steps.
+    #[arg(long)]
+    fuel: Option<u64>,
+
+    /// Fail once the program has run for this many seconds.
+    #[arg(long)]
+    timeout: Option<f64>,
+
+    /// Fail once the values of the program take up more than this many bytes.
+    #[arg(long)]
+    memory: Option<usize>,
+
+    /// Write a line of JSON for every performed instruction to this file.
+    #[arg(long)]
+    trace: Option<PathBuf>,
+
+    /// Write the time spent in every instruction to this file as folded stacks, and print a
+    /// summary to stderr.
+    #[arg(long)]
+    profile: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Format {
+    /// The text format, files with any extension other than .json and .bms.
+    Text,
+    /// Serialized programs and values, files with the .json extension.
+    Json,
+    /// Scripts compiled to programs, files with the .bms extension.
+    Script,
+}
+
+impl Format {
+    fn of(path: &Path) -> Self {
+        match path.extension() {
+            Some(extension) if extension == "json" => Self::Json,
+            Some(extension) if extension == "bms" => Self::Script,
+            _ => Self::Text,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LoaderKind {
+    /// Every load fails.
+    None,
+    /// Strings are paths of programs which are loaded as program instructions.
+    File,
+}
+
+/// Loads programs from paths relative to the working directory.
+#[derive(Clone, Copy, Debug)]
+pub struct FileLoader;
+impl Loader for FileLoader {
+    fn load(&self, value: Value) -> bookmark_language::Result<Value> {
+        let Value::String(path) = &value else {
+            return Err(bookmark_language::Error::UnloadableValue(value));
+        };
+
+        let path = Path::new(&**path);
+        match read_program(path, Format::of(path)) {
+            Ok(program) => Ok(Instruction::from(loading::Program(Arc::from(program))).into()),
+            Err(err) => {
+                eprintln!("failed to load {}: {err}", path.display());
+                Err(bookmark_language::Error::UnloadableValue(value))
+            }
+        }
+    }
+}
+
+pub fn read_program(path: &Path, format: Format) -> Result<Program, Error> {
+    let src = fs::read_to_string(path).map_err(|err| Error::Read(path.to_owned(), err))?;
+    match format {
+        Format::Text => asm::parse_named(&src, &path.display().to_string())
+            .map_err(|err| Error::Parse(path.to_owned(), err)),
+        Format::Json => Ok(serde_json::from_str(&src)?),
+        Format::Script => script::compile_named(&src, &path.display().to_string())
+            .map_err(|err| Error::Parse(path.to_owned(), err)),
+    }
+}
+
+fn create(path: &Path) -> Result<File, Error> {
+    File::create(path).map_err(|err| Error::Write(path.to_owned(), err))
+}
+
+fn read_value(src: &str, format: Format) -> Result<Value, Error> {
+    match format {
+        // a script is a program, values are written the same way in both formats
+        Format::Text | Format::Script => {
+            asm::parse_value(src).map_err(|err| Error::Parse("input".into(), err))
+        }
+        Format::Json => Ok(serde_json::from_str(src)?),
+    }
+}
+
+/// Point in time `seconds` from now.
+fn deadline(seconds: f64) -> Result<Instant, Error> {
+    let duration = Duration::try_from_secs_f64(seconds)
+        .map_err(|err| Error::Argument("timeout", err.to_string()))?;
+    Instant::now().checked_add(duration).ok_or_else(|| {
+        Error::Argument(
+            "timeout",
+            format!("{seconds} seconds is too far in the future"),
+        )
+    })
+}
+
+pub fn run(args: RunArgs) -> Result<ExitCode, Error> {
+    let RunArgs {
+        file,
+        format,
+        input,
+        stdin,
+        input_format,
+        output_format,
+        loader,
+        fuel,
+        timeout,
+        memory,
+        trace,
+        profile,
+    } = args;
+
+    let program = read_program(&file, format.unwrap_or_else(|| Format::of(&file)))?;
+
+    let input = if stdin {
+        let mut src = String::new();
+        io::stdin().read_to_string(&mut src)?;
+        read_value(&src, input_format)?
+    } else if let Some(src) = input {
+        read_value(&src, input_format)?
+    } else {
+        Value::None
+    };
+
+    let loader: &dyn Loader = match loader {
+        LoaderKind::None => &DefaultLoader,
+        LoaderKind::File => &FileLoader,
+    };
+
+    let mut limits = Limits {
+        fuel: fuel.map_or_else(Fuel::unlimited, Fuel::new),
+        deadline: timeout.map(deadline).transpose()?,
+        cancellation: None,
+        memory,
+    };
+    let result = if trace.is_none() && profile.is_none() {
+        program.run_to_completion_with_limits(input, loader, &mut limits)
+    } else {
+        let json_trace = match &trace {
+            Some(path) => Some(JsonTrace::new(BufWriter::new(create(path)?))),
+            None => None,
+        };
+        let mut observer = (json_trace, profile.as_ref().map(|_| Profiler::new()));
+        let result = program.run_to_completion_observed(input, loader, &mut limits, &mut observer);
+
+        let (json_trace, profiler) = observer;
+        if let (Some(json_trace), Some(path)) = (json_trace, trace) {
+            json_trace.finish().map_err(|err| Error::Write(path, err))?;
+        }
+        if let (Some(profiler), Some(path)) = (profiler, profile) {
+            let profile = profiler.into_profile();
+            let mut file = BufWriter::new(create(&path)?);
+            profile
+                .write_folded(&mut file)
+                .and_then(|()| file.flush())
+                .map_err(|err| Error::Write(path, err))?;
+            eprint!("{profile}");
+        }
+        result
+    };
+    match result {
+        Ok(value) => {
+            match output_format {
+                Format::Text | Format::Script => println!("{value:#}"),
+                Format::Json => println!("{}", serde_json::to_string_pretty(&value)?),
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        Err(err) => {
+            eprintln!("error: {err}");
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn timeouts() {
+        assert!(deadline(0.5).is_ok_and(|deadline| deadline > Instant::now()));
+        for seconds in [-1.0, f64::NAN, 1e30, 1e19] {
+            assert!(
+                matches!(deadline(seconds), Err(Error::Argument("timeout", _))),
+                "{seconds}"
+            );
+        }
+    }
+}
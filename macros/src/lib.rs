@@ -0,0 +1,620 @@
+//! Procedural macros for bookmark-language, re-exported by the main crate.
+
+#![warn(missing_copy_implementations, clippy::unwrap_used, clippy::pedantic)]
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use std::collections::HashMap;
+use syn::{
+    braced, bracketed,
+    ext::IdentExt,
+    parenthesized,
+    parse::{ParseStream, Parser},
+    token, Ident, LitFloat, LitInt, LitStr, Token,
+};
+
+/// Words with a meaning in value position, these cannot be used as variable names.
+const RESERVED: &[&str] = &["none", "true", "false", "nan", "inf", "type", "instr"];
+
+/// Build a [Program] from instructions written like the [text format], expands to
+/// [`program::Builder`] calls.
+///
+/// Items are separated by `;` instead of line breaks, and values may be given as Rust expressions
+/// in parentheses, which are converted using [`Value::from`]. Every variable has to be declared
+/// before it is used, and instructions writing to variables may not be used with read-only ones,
+/// so neither [`Error::UnknownVariable`] nor [`Error::WriteToReadOnly`] can happen when the
+/// program is run.
+///
+/// ```
+/// use bookmark_language::{bml, instruction::DefaultLoader, value::Value};
+///
+/// let limit = 100;
+/// let program = bml! {
+///     rw a = 1;
+///     rw b = 1;
+///     ro l = instr {
+///         take a;
+///         op_clone add b;
+///         swap b;
+///         swap a;
+///         clone a;
+///         op lt (limit);
+///         cond instr { clone l; perform none } instr noop;
+///         perform none;
+///     };
+///
+///     clone l;
+///     perform none;
+///     clone a;
+/// };
+///
+/// assert_eq!(program.run_to_completion(Value::None, &DefaultLoader), Ok(Value::Int(144)));
+/// ```
+///
+/// ```compile_fail
+/// # use bookmark_language::bml;
+/// let program = bml! {
+///     rw a = 1;
+///     clone b;
+/// };
+/// ```
+///
+/// ```compile_fail
+/// # use bookmark_language::bml;
+/// let program = bml! {
+///     ro a = 1;
+///     put 2;
+///     assign a;
+/// };
+/// ```
+///
+/// [Program]: ../bookmark_language/program/struct.Program.html
+/// [`program::Builder`]: ../bookmark_language/program/struct.Builder.html
+/// [text format]: ../bookmark_language/asm/index.html
+/// [`Value::from`]: ../bookmark_language/value/enum.Value.html
+/// [`Error::UnknownVariable`]: ../bookmark_language/enum.Error.html#variant.UnknownVariable
+/// [`Error::WriteToReadOnly`]: ../bookmark_language/enum.Error.html#variant.WriteToReadOnly
+#[proc_macro]
+pub fn bml(input: TokenStream) -> TokenStream {
+    let mut compiler = Compiler::default();
+    match (|input: ParseStream| compiler.program(input)).parse(input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Variable {
+    index: usize,
+    is_read_only: bool,
+}
+
+#[derive(Default)]
+struct Compiler {
+    /// Variables of every program being compiled, nested programs cannot see the variables of
+    /// the program they are a part of.
+    scopes: Vec<HashMap<String, Variable>>,
+}
+
+/// Names used in the expansion, not visible to expressions given by the caller.
+fn internal(name: &str) -> Ident {
+    Ident::new(name, Span::mixed_site())
+}
+
+fn krate() -> TokenStream2 {
+    quote!(::bookmark_language)
+}
+
+impl Compiler {
+    fn scope(&mut self) -> &mut HashMap<String, Variable> {
+        self.scopes.last_mut().expect("a program is being compiled")
+    }
+
+    fn variable_ident(&self, variable: Variable) -> Ident {
+        format_ident!(
+            "variable_{}_{}",
+            self.scopes.len(),
+            variable.index,
+            span = Span::mixed_site()
+        )
+    }
+
+    fn program(&mut self, input: ParseStream) -> syn::Result<TokenStream2> {
+        let krate = krate();
+        let (variables, builder) = (internal("variables"), internal("builder"));
+
+        self.scopes.push(HashMap::new());
+        let mut statements = Vec::new();
+        while !input.is_empty() {
+            let fork = input.fork();
+            let name = Ident::parse_any(&fork).map(|ident| ident.to_string());
+            match name.as_deref() {
+                Ok("rw" | "ro") => statements.push(self.declaration(input)?),
+                Ok("fallible") => {
+                    Ident::parse_any(input)?;
+                    statements.push(quote!(#builder.is_fallible(true);));
+                }
+                _ => {
+                    let instruction = self.instruction(input)?;
+                    statements.push(quote!(#builder.push_instruction(#instruction);));
+                }
+            }
+
+            if !input.is_empty() {
+                input.parse::<Token![;]>()?;
+            }
+        }
+        self.scopes.pop();
+
+        Ok(quote!({
+            let mut #variables = #krate::variable::MapBuilder::new();
+            let mut #builder = #krate::program::Builder::new();
+            #(#statements)*
+            #builder.build(#variables.build())
+        }))
+    }
+
+    fn declaration(&mut self, input: ParseStream) -> syn::Result<TokenStream2> {
+        let variables = internal("variables");
+        let kind = Ident::parse_any(input)?;
+        let name = Ident::parse_any(input)?;
+        let key = name.to_string();
+
+        if RESERVED.contains(&key.as_str()) {
+            return Err(syn::Error::new(
+                name.span(),
+                format!("`{name}` cannot be used as a variable name"),
+            ));
+        }
+        if self.scope().contains_key(&key) {
+            return Err(syn::Error::new(
+                name.span(),
+                format!("`{name}` is already declared"),
+            ));
+        }
+
+        let variable = Variable {
+            index: self.scope().len(),
+            is_read_only: kind == "ro",
+        };
+        // declared before the initial value is parsed so that it may refer to itself
+        self.scope().insert(key, variable);
+
+        let ident = self.variable_ident(variable);
+        let reserve = if variable.is_read_only {
+            quote!(reserve_ro)
+        } else {
+            quote!(reserve_rw)
+        };
+        let mut tokens = quote!(let #ident = #variables.#reserve(););
+
+        if input.peek(Token![=]) {
+            input.parse::<Token![=]>()?;
+            let value = self.value(input)?;
+            tokens.extend(quote! {
+                #variables
+                    .set(#ident, #value)
+                    .expect("id was just reserved in the same builder");
+            });
+        }
+
+        Ok(tokens)
+    }
+
+    fn lookup(&self, name: &Ident) -> syn::Result<Variable> {
+        match self
+            .scopes
+            .last()
+            .and_then(|scope| scope.get(&name.to_string()))
+        {
+            Some(&variable) => Ok(variable),
+            None => Err(syn::Error::new(
+                name.span(),
+                format!("unknown variable `{name}`"),
+            )),
+        }
+    }
+
+    fn read(&mut self, input: ParseStream) -> syn::Result<TokenStream2> {
+        let name = Ident::parse_any(input)?;
+        let variable = self.lookup(&name)?;
+        let ident = self.variable_ident(variable);
+        Ok(quote!(#ident))
+    }
+
+    /// Variables written by the instruction, between brackets and separated by commas.
+    fn writes(
+        &mut self,
+        input: ParseStream,
+        instruction: &Ident,
+    ) -> syn::Result<Vec<TokenStream2>> {
+        let content;
+        bracketed!(content in input);
+        let mut ids = Vec::new();
+        while !content.is_empty() {
+            ids.push(self.write(&content, instruction)?);
+            if !content.is_empty() {
+                content.parse::<Token![,]>()?;
+            }
+        }
+        Ok(ids)
+    }
+
+    fn write(&mut self, input: ParseStream, instruction: &Ident) -> syn::Result<TokenStream2> {
+        let name = Ident::parse_any(input)?;
+        let variable = self.lookup(&name)?;
+        if variable.is_read_only {
+            return Err(syn::Error::new(
+                name.span(),
+                format!("`{instruction}` cannot be used with read-only variable `{name}`"),
+            ));
+        }
+        let ident = self.variable_ident(variable);
+        Ok(quote!(#ident))
+    }
+
+    fn block(&mut self, input: ParseStream) -> syn::Result<TokenStream2> {
+        let krate = krate();
+        let content;
+        braced!(content in input);
+
+        let mut instructions = Vec::new();
+        while !content.is_empty() {
+            if content.peek(Ident::peek_any) {
+                let fork = content.fork();
+                let name = Ident::parse_any(&fork)?;
+                if name == "rw" || name == "ro" {
+                    return Err(syn::Error::new(
+                        name.span(),
+                        "variables can only be declared at program level",
+                    ));
+                }
+            }
+            instructions.push(self.instruction(&content)?);
+            if !content.is_empty() {
+                content.parse::<Token![;]>()?;
+            }
+        }
+
+        Ok(quote!(#krate::instruction::meta::List(::std::vec![#(#instructions),*])))
+    }
+
+    // one arm per instruction
+    #[allow(clippy::too_many_lines)]
+    fn instruction(&mut self, input: ParseStream) -> syn::Result<TokenStream2> {
+        let krate = krate();
+        let i = quote!(#krate::instruction);
+
+        if input.peek(token::Brace) {
+            let list = self.block(input)?;
+            return Ok(quote!(#i::Instruction::from(#list)));
+        }
+
+        let name = Ident::parse_any(input)?;
+        let instruction = match name.to_string().as_str() {
+            "noop" => quote!(#i::Instruction::Noop),
+
+            "sleep" => quote!(#i::pure::Sleep),
+            "debug" => quote!(#i::pure::Debug),
+            "cond" => {
+                let (if_true, if_false) = (self.value(input)?, self.value(input)?);
+                quote!(#i::pure::Cond { if_true: #if_true, if_false: #if_false })
+            }
+            "put" => {
+                let value = self.value(input)?;
+                quote!(#i::pure::Put(#value))
+            }
+            "coerce" => {
+                let ty = Self::ty(input)?;
+                quote!(#i::pure::Coerce(#ty))
+            }
+            "parse" => {
+                let ty = Self::ty(input)?;
+                quote!(#i::pure::Parse(#ty))
+            }
+            "op" => {
+                let (operation, value) = (Self::operation(input)?, self.value(input)?);
+                quote!(#i::pure::Op(#operation, #value))
+            }
+            "to_fallible" => quote!(#i::pure::ToFallible),
+            "to_infallible" => quote!(#i::pure::ToInfallible),
+            "not" => quote!(#i::pure::Not),
+            "throw" => quote!(#i::pure::Throw),
+
+            "clone" => {
+                let id = self.read(input)?;
+                quote!(#i::reading::Clone(#id))
+            }
+            "get_clone" => {
+                let id = self.read(input)?;
+                quote!(#i::reading::GetClone(#id))
+            }
+            "op_clone" => {
+                let (operation, id) = (Self::operation(input)?, self.read(input)?);
+                quote!(#i::reading::OpClone(#operation, #id))
+            }
+
+            "take" => {
+                let id = self.write(input, &name)?;
+                quote!(#i::mutating::Take(#id))
+            }
+            "assign" => {
+                let id = self.write(input, &name)?;
+                quote!(#i::mutating::Assign(#id))
+            }
+            "swap" => {
+                let id = self.write(input, &name)?;
+                quote!(#i::mutating::Swap(#id))
+            }
+            "get_take" => {
+                let id = self.write(input, &name)?;
+                quote!(#i::mutating::GetTake(#id))
+            }
+            "map_assign" => {
+                let (map, key) = (self.write(input, &name)?, self.value(input)?);
+                quote!(#i::mutating::MapAssign { map: #map, key: #key })
+            }
+            "op_take" => {
+                let (operation, id) = (Self::operation(input)?, self.write(input, &name)?);
+                quote!(#i::mutating::OpTake(#operation, #id))
+            }
+
+            "list" => self.block(input)?,
+            "return" => quote!(#i::meta::Return),
+            "loop" => {
+                let body = self.instruction(input)?;
+                quote!(#i::meta::Loop(::std::boxed::Box::new(#body)))
+            }
+            "while" => {
+                let (condition, body) = (self.instruction(input)?, self.instruction(input)?);
+                quote!(#i::meta::While {
+                    condition: ::std::boxed::Box::new(#condition),
+                    body: ::std::boxed::Box::new(#body),
+                })
+            }
+            "if" => {
+                let (if_true, if_false) = (self.instruction(input)?, self.otherwise(input)?);
+                quote!(#i::meta::If {
+                    if_true: ::std::boxed::Box::new(#if_true),
+                    if_false: ::std::boxed::Box::new(#if_false),
+                })
+            }
+            "switch" => {
+                let content;
+                braced!(content in input);
+                let mut cases = Vec::new();
+                while !content.is_empty() {
+                    let value = self.value(&content)?;
+                    content.parse::<Token![:]>()?;
+                    let instruction = self.instruction(&content)?;
+                    cases.push(quote!((#value, #instruction)));
+                    if !content.is_empty() {
+                        content.parse::<Token![;]>()?;
+                    }
+                }
+                let default = self.otherwise(input)?;
+                quote!(#i::meta::Switch {
+                    cases: ::std::vec![#(#cases),*],
+                    default: ::std::boxed::Box::new(#default),
+                })
+            }
+            "try" => {
+                let body = self.instruction(input)?;
+                let catch = Ident::parse_any(input)?;
+                if catch != "catch" {
+                    return Err(syn::Error::new(catch.span(), "expected `catch`"));
+                }
+                let handler = self.instruction(input)?;
+                quote!(#i::meta::Try {
+                    body: ::std::boxed::Box::new(#body),
+                    handler: ::std::boxed::Box::new(#handler),
+                })
+            }
+            "break" => quote!(#i::meta::Break),
+            "continue" => quote!(#i::meta::Continue),
+            "perform" => {
+                let value = self.value(input)?;
+                quote!(#i::meta::Perform(#value))
+            }
+            "perform_clone" => {
+                let id = self.read(input)?;
+                quote!(#i::meta::PerformClone(#id))
+            }
+            "perform_take" => {
+                let id = self.write(input, &name)?;
+                quote!(#i::meta::PerformTake(#id))
+            }
+            "function" => {
+                let (parameters, locals) = (self.writes(input, &name)?, self.writes(input, &name)?);
+                let body = self.instruction(input)?;
+                quote!(#i::meta::Function {
+                    parameters: ::std::vec![#(#parameters),*],
+                    locals: ::std::vec![#(#locals),*],
+                    body: ::std::boxed::Box::new(#body),
+                })
+            }
+            "call" => {
+                let id = self.read(input)?;
+                quote!(#i::meta::Call(#id))
+            }
+
+            "program" => {
+                let content;
+                braced!(content in input);
+                let program = self.program(&content)?;
+                quote!(#i::loading::Program(::std::sync::Arc::from(#program)))
+            }
+            "load" => quote!(#i::loading::Load),
+
+            _ => {
+                return Err(syn::Error::new(
+                    name.span(),
+                    format!("unknown instruction `{name}`"),
+                ))
+            }
+        };
+
+        Ok(quote!(#i::Instruction::from(#instruction)))
+    }
+
+    fn ty(input: ParseStream) -> syn::Result<TokenStream2> {
+        let krate = krate();
+        let name = Ident::parse_any(input)?;
+        let variant = match name.to_string().as_str() {
+            "bool" => "Bool",
+            "int" => "Int",
+            "float" => "Float",
+            "string" => "String",
+            "id" => "Id",
+            "instruction" => "Instruction",
+            "list" => "List",
+            "map" => "Map",
+            "type" => "Type",
+            "none" => "None",
+            _ => {
+                return Err(syn::Error::new(
+                    name.span(),
+                    format!("unknown type `{name}`"),
+                ))
+            }
+        };
+        let variant = Ident::new(variant, name.span());
+        Ok(quote!(#krate::value::Type::#variant))
+    }
+
+    fn operation(input: ParseStream) -> syn::Result<TokenStream2> {
+        let krate = krate();
+        let name = Ident::parse_any(input)?;
+        let variant = match name.to_string().as_str() {
+            "add" => "Add",
+            "sub" => "Sub",
+            "mul" => "Mul",
+            "div" => "Div",
+            "eq" => "Eq",
+            "lt" => "Lt",
+            "le" => "Le",
+            "gt" => "Gt",
+            "ge" => "Ge",
+            "and" => "And",
+            "or" => "Or",
+            _ => {
+                return Err(syn::Error::new(
+                    name.span(),
+                    format!("unknown operation `{name}`"),
+                ))
+            }
+        };
+        let variant = Ident::new(variant, name.span());
+        Ok(quote!(#krate::value::Operation::#variant))
+    }
+
+    /// Instruction after `else`, or noop without it.
+    fn otherwise(&mut self, input: ParseStream) -> syn::Result<TokenStream2> {
+        if input.peek(Token![else]) {
+            input.parse::<Token![else]>()?;
+            self.instruction(input)
+        } else {
+            let krate = krate();
+            Ok(quote!(#krate::instruction::Instruction::Noop))
+        }
+    }
+
+    fn value(&mut self, input: ParseStream) -> syn::Result<TokenStream2> {
+        let krate = krate();
+        let v = quote!(#krate::value::Value);
+
+        if input.peek(LitInt) {
+            let value = input.parse::<LitInt>()?.base10_parse::<i64>()?;
+            return Ok(quote!(#v::Int(#value)));
+        }
+        if input.peek(LitFloat) {
+            let value = input.parse::<LitFloat>()?.base10_parse::<f64>()?;
+            return Ok(quote!(#v::Float(#value)));
+        }
+        if input.peek(LitStr) {
+            let value = input.parse::<LitStr>()?.value();
+            return Ok(quote!(#v::string(#value)));
+        }
+        if input.peek(Token![-]) {
+            return Self::negative(input);
+        }
+        if input.peek(token::Bracket) {
+            let content;
+            bracketed!(content in input);
+            let mut items = Vec::new();
+            while !content.is_empty() {
+                items.push(self.value(&content)?);
+                if !content.is_empty() {
+                    content.parse::<Token![,]>()?;
+                }
+            }
+            return Ok(quote!(#v::List(::std::vec![#(#items),*])));
+        }
+        if input.peek(token::Brace) {
+            let content;
+            braced!(content in input);
+            let mut entries = Vec::new();
+            while !content.is_empty() {
+                let key = content.parse::<LitStr>()?.value();
+                content.parse::<Token![:]>()?;
+                let value = self.value(&content)?;
+                entries.push(quote!((::std::sync::Arc::<str>::from(#key), #value)));
+                if !content.is_empty() {
+                    content.parse::<Token![,]>()?;
+                }
+            }
+            return Ok(quote!(#v::Map(::std::collections::BTreeMap::from([#(#entries),*]))));
+        }
+        if input.peek(token::Paren) {
+            let content;
+            parenthesized!(content in input);
+            let expr = content.parse::<TokenStream2>()?;
+            return Ok(quote!(#v::from(#expr)));
+        }
+
+        let name = Ident::parse_any(input)?;
+        Ok(match name.to_string().as_str() {
+            "none" => quote!(#v::None),
+            "true" => quote!(#v::Bool(true)),
+            "false" => quote!(#v::Bool(false)),
+            "nan" => quote!(#v::Float(f64::NAN)),
+            "inf" => quote!(#v::Float(f64::INFINITY)),
+            "type" => {
+                let ty = Self::ty(input)?;
+                quote!(#v::Type(#ty))
+            }
+            "instr" => {
+                let instruction = self.instruction(input)?;
+                quote!(#v::from(#instruction))
+            }
+            _ => {
+                let variable = self.lookup(&name)?;
+                let ident = self.variable_ident(variable);
+                quote!(#v::Id(#ident))
+            }
+        })
+    }
+
+    fn negative(input: ParseStream) -> syn::Result<TokenStream2> {
+        let krate = krate();
+        let v = quote!(#krate::value::Value);
+        let minus = input.parse::<Token![-]>()?;
+
+        if input.peek(LitInt) {
+            let digits = input.parse::<LitInt>()?;
+            let value = format!("-{}", digits.base10_digits())
+                .parse::<i64>()
+                .map_err(|err| syn::Error::new(digits.span(), err))?;
+            Ok(quote!(#v::Int(#value)))
+        } else if input.peek(LitFloat) {
+            let value = -input.parse::<LitFloat>()?.base10_parse::<f64>()?;
+            Ok(quote!(#v::Float(#value)))
+        } else if input.peek(Ident) && input.fork().parse::<Ident>()? == "inf" {
+            input.parse::<Ident>()?;
+            Ok(quote!(#v::Float(f64::NEG_INFINITY)))
+        } else {
+            Err(syn::Error::new(minus.span, "expected a number after `-`"))
+        }
+    }
+}
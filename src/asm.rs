@@ -0,0 +1,120 @@
+//! Text format for programs.
+//!
+//! A program is written as one instruction per line, instructions are spelled as in
+//! [`Instruction::NAMES`][crate::instruction::Instruction::NAMES] and followed by their
+//! arguments. Variables are declared with `rw name = value` or `ro name = value` and referred to
+//! by name, `{ ... }` blocks are [lists][crate::instruction::meta::List] and `#` starts a comment.
+//!
+//! Every [Program][crate::program::Program], [Instruction][crate::instruction::Instruction] and
+//! [Value][crate::value::Value] can be printed back into this format using
+//! [Display][std::fmt::Display].
+//!
+//! ```text
+//! rw a = 1
+//! rw b = 1
+//! ro l = instr {
+//!     take a
+//!     op_clone add b
+//!     debug
+//!     swap b
+//!     swap a
+//!     clone l
+//!     perform none
+//! }
+//! fallible
+//!
+//! clone l
+//! perform none
+//! ```
+
+use crate::{value::Value, variable};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use thiserror::Error;
+
+mod formatter;
+mod lexer;
+mod parser;
+mod printer;
+
+pub use formatter::format;
+pub use parser::{analyze, parse, parse_named, parse_value, Scope};
+
+/// A position in source text, lines and columns start at 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Serialize, Deserialize)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// A range of source text, `end` is exclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    /// Whether `position` is inside of the span, or directly after it.
+    #[must_use]
+    pub fn contains(&self, position: Position) -> bool {
+        self.start <= position && position <= self.end
+    }
+}
+
+/// Error produced when source text is not a valid program.
+#[derive(Debug, Error, Clone, PartialEq)]
+#[error("{}: {message}", span.start)]
+pub struct ParseError {
+    pub span: Span,
+    pub message: String,
+}
+
+impl ParseError {
+    pub(crate) fn new(span: Span, message: impl Into<String>) -> Self {
+        Self {
+            span,
+            message: message.into(),
+        }
+    }
+}
+
+/// What is known about source text without running it, produced by [analyze].
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Analysis {
+    /// Problems ordered by position. If the source is not a valid program the parse error is
+    /// among them, and everything after it is missing from the analysis.
+    pub diagnostics: Vec<ParseError>,
+    /// Declared variables in order of declaration, including those of nested programs.
+    pub symbols: Vec<Symbol>,
+}
+
+impl Analysis {
+    /// The variable declared or referred to at `position`.
+    #[must_use]
+    pub fn symbol_at(&self, position: Position) -> Option<&Symbol> {
+        self.symbols.iter().find(|symbol| {
+            symbol.declaration.contains(position)
+                || symbol.references.iter().any(|span| span.contains(position))
+        })
+    }
+}
+
+/// A declared variable.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub id: variable::Id,
+    /// Span of the name in the declaration.
+    pub declaration: Span,
+    /// Initial value, `none` if the declaration has none.
+    pub value: Value,
+    /// Spans of the names referring to the variable.
+    pub references: Vec<Span>,
+}
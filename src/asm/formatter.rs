@@ -0,0 +1,231 @@
+//! Canonical layout of source text.
+//!
+//! Formatting works on tokens rather than on the parsed program so that names and comments are
+//! kept. Line breaks are kept as they are written, except that runs of empty lines are collapsed.
+
+use super::{
+    lexer::{Lexer, Token},
+    parser::parse,
+    printer::FloatLiteral,
+    ParseError,
+};
+use std::{fmt::Write as _, mem};
+
+const INDENT: &str = "    ";
+
+/// Format a program written in the text format.
+///
+/// - Lines are indented by one level for every `{` and `[` they are nested in.
+/// - Tokens are separated by a single space, except inside of `[]` and before `,`, `:` and `;`.
+/// - The `=` of consecutive declarations are aligned.
+/// - Numbers and strings are spelled the way they are [printed][std::fmt::Display].
+///
+/// Only valid programs are formatted, and formatting never changes the parsed program.
+pub fn format(src: &str) -> Result<String, ParseError> {
+    parse(src)?;
+    let tokens = Lexer::new(src).tokenize()?;
+
+    let mut lines = Vec::new();
+    let mut line = Line::default();
+    let mut depth = 0_usize;
+    for spanned in tokens {
+        match spanned.token {
+            Token::Newline => lines.push(mem::replace(
+                &mut line,
+                Line {
+                    depth,
+                    tokens: Vec::new(),
+                },
+            )),
+            Token::Eof => break,
+            token => {
+                match token {
+                    Token::LBrace | Token::LBracket => depth += 1,
+                    Token::RBrace | Token::RBracket => {
+                        depth = depth.saturating_sub(1);
+                        if line.tokens.iter().all(Token::is_closing) {
+                            line.depth = depth;
+                        }
+                    }
+                    _ => (),
+                }
+                line.tokens.push(token);
+            }
+        }
+    }
+    lines.push(line);
+
+    let widths = declaration_widths(&lines);
+    let mut formatted = String::new();
+    let mut is_blank = true;
+    for (line, width) in lines.iter().zip(widths) {
+        if line.tokens.is_empty() {
+            if !is_blank {
+                formatted.push('\n');
+            }
+            is_blank = true;
+            continue;
+        }
+        is_blank = false;
+
+        for _ in 0..line.depth {
+            formatted.push_str(INDENT);
+        }
+        let mut previous: Option<&Token> = None;
+        for (i, token) in line.tokens.iter().enumerate() {
+            if previous.is_some_and(|previous| needs_space(previous, token)) {
+                formatted.push(' ');
+            }
+            write_token(&mut formatted, token);
+            if i == 1 && width > 0 {
+                if let Token::Ident(name) = token {
+                    // names are ascii, their length is their width
+                    for _ in name.len()..width {
+                        formatted.push(' ');
+                    }
+                }
+            }
+            previous = Some(token);
+        }
+        formatted.push('\n');
+    }
+
+    let len = formatted.trim_end().len();
+    formatted.truncate(len);
+    if !formatted.is_empty() {
+        formatted.push('\n');
+    }
+    Ok(formatted)
+}
+
+#[derive(Default)]
+struct Line<'src> {
+    depth: usize,
+    tokens: Vec<Token<'src>>,
+}
+
+impl Line<'_> {
+    /// Length of the declared name, if the line starts with a declaration that has a value.
+    fn declared_name(&self) -> Option<usize> {
+        match self.tokens.as_slice() {
+            [Token::Ident("rw" | "ro"), Token::Ident(name), Token::Equals, ..] => Some(name.len()),
+            _ => None,
+        }
+    }
+}
+
+impl Token<'_> {
+    fn is_closing(&self) -> bool {
+        matches!(self, Token::RBrace | Token::RBracket)
+    }
+}
+
+/// Width to pad the declared name of each line to, `0` for lines that are not aligned.
+///
+/// Declarations on consecutive lines with the same indentation form a group, a declaration with a
+/// value continuing on the following lines is not part of any group.
+fn declaration_widths(lines: &[Line]) -> Vec<usize> {
+    let is_aligned = |i: usize| {
+        let line = &lines[i];
+        line.declared_name().is_some()
+            && lines.get(i + 1).is_none_or(|next| next.depth <= line.depth)
+    };
+
+    let mut widths = vec![0; lines.len()];
+    let mut start = 0;
+    while start < lines.len() {
+        let mut end = start;
+        while end < lines.len() && lines[end].depth == lines[start].depth && is_aligned(end) {
+            end += 1;
+        }
+        if end == start {
+            start += 1;
+            continue;
+        }
+
+        let width = lines[start..end]
+            .iter()
+            .filter_map(Line::declared_name)
+            .max()
+            .unwrap_or_default();
+        widths[start..end].fill(width);
+        start = end;
+    }
+    widths
+}
+
+fn needs_space(previous: &Token, next: &Token) -> bool {
+    !matches!(
+        (previous, next),
+        (Token::LBracket, _)
+            | (
+                _,
+                Token::RBracket | Token::Comma | Token::Colon | Token::Semicolon
+            )
+            | (Token::LBrace, Token::RBrace)
+    )
+}
+
+fn write_token(formatted: &mut String, token: &Token) {
+    // writing to a string cannot fail
+    let _ = match token {
+        Token::Ident(ident) => write!(formatted, "{ident}"),
+        Token::RawId(id) => write!(formatted, "{id}"),
+        Token::Int(value) => write!(formatted, "{value}"),
+        Token::Float(value) => write!(formatted, "{}", FloatLiteral(*value)),
+        Token::Str(value) => write!(formatted, "{value:?}"),
+        Token::Comment(comment) => write!(formatted, "{}", comment.trim_end()),
+        Token::LBrace => write!(formatted, "{{"),
+        Token::RBrace => write!(formatted, "}}"),
+        Token::LBracket => write!(formatted, "["),
+        Token::RBracket => write!(formatted, "]"),
+        Token::Colon => write!(formatted, ":"),
+        Token::Comma => write!(formatted, ","),
+        Token::Equals => write!(formatted, "="),
+        Token::Semicolon => write!(formatted, ";"),
+        Token::Newline | Token::Eof => Ok(()),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn formatting() -> Result<(), ParseError> {
+        let src = "
+
+rw a=1   # first
+rw long_name  = 1.50
+ro l = instr {
+take a
+  op_clone add long_name; swap long_name
+      {put [ 1,2 ,{ \"k\":1e3 }]}
+debug
+    }
+fallible
+
+
+  clone l
+perform   none
+";
+        let formatted = "\
+rw a         = 1 # first
+rw long_name = 1.5
+ro l = instr {
+    take a
+    op_clone add long_name; swap long_name
+    { put [1, 2, { \"k\": 1000.0 }] }
+    debug
+}
+fallible
+
+clone l
+perform none
+";
+        assert_eq!(format(src)?, formatted);
+        assert_eq!(format(formatted)?, formatted);
+        assert_eq!(parse(formatted)?, parse(src)?);
+        Ok(())
+    }
+}
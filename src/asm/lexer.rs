@@ -0,0 +1,274 @@
+use super::{ParseError, Position, Span};
+use crate::variable;
+use std::{fmt, iter::Peekable, str::CharIndices};
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Token<'src> {
+    Ident(&'src str),
+    RawId(variable::Id),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    LBrace,
+    RBrace,
+    LBracket,
+    RBracket,
+    Colon,
+    Comma,
+    Equals,
+    Semicolon,
+    Newline,
+    Comment(&'src str),
+    Eof,
+}
+
+impl fmt::Display for Token<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(ident) => write!(f, "`{ident}`"),
+            Token::RawId(_) => write!(f, "variable id"),
+            Token::Int(value) => write!(f, "`{value}`"),
+            Token::Float(value) => write!(f, "`{value:?}`"),
+            Token::Str(value) => write!(f, "{value:?}"),
+            Token::LBrace => write!(f, "`{{`"),
+            Token::RBrace => write!(f, "`}}`"),
+            Token::LBracket => write!(f, "`[`"),
+            Token::RBracket => write!(f, "`]`"),
+            Token::Colon => write!(f, "`:`"),
+            Token::Comma => write!(f, "`,`"),
+            Token::Equals => write!(f, "`=`"),
+            Token::Semicolon => write!(f, "`;`"),
+            Token::Newline => write!(f, "end of line"),
+            Token::Comment(_) => write!(f, "comment"),
+            Token::Eof => write!(f, "end of input"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Spanned<'src> {
+    pub token: Token<'src>,
+    pub span: Span,
+}
+
+pub(crate) struct Lexer<'src> {
+    src: &'src str,
+    chars: Peekable<CharIndices<'src>>,
+    position: Position,
+}
+
+impl<'src> Lexer<'src> {
+    pub fn new(src: &'src str) -> Self {
+        Self {
+            src,
+            chars: src.char_indices().peekable(),
+            position: Position { line: 1, column: 1 },
+        }
+    }
+
+    /// Split the whole source into tokens, the last token is always [`Token::Eof`].
+    pub fn tokenize(mut self) -> Result<Vec<Spanned<'src>>, ParseError> {
+        let mut tokens = Vec::new();
+        loop {
+            let spanned = self.next_token()?;
+            let is_eof = spanned.token == Token::Eof;
+            tokens.push(spanned);
+            if is_eof {
+                break Ok(tokens);
+            }
+        }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let (_, c) = self.chars.next()?;
+        if c == '\n' {
+            self.position.line += 1;
+            self.position.column = 1;
+        } else {
+            self.position.column += 1;
+        }
+        Some(c)
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|&(_, c)| c)
+    }
+
+    fn offset(&mut self) -> usize {
+        self.chars.peek().map_or(self.src.len(), |&(i, _)| i)
+    }
+
+    fn eat_while(&mut self, pred: impl Fn(char) -> bool) -> &'src str {
+        let start = self.offset();
+        while self.peek().is_some_and(&pred) {
+            self.bump();
+        }
+        &self.src[start..self.offset()]
+    }
+
+    fn error(&self, start: Position, message: impl Into<String>) -> ParseError {
+        ParseError::new(
+            Span {
+                start,
+                end: self.position,
+            },
+            message,
+        )
+    }
+
+    fn next_token(&mut self) -> Result<Spanned<'src>, ParseError> {
+        self.eat_while(|c| c.is_whitespace() && c != '\n');
+
+        let start = self.position;
+        let Some(c) = self.peek() else {
+            return Ok(Spanned {
+                token: Token::Eof,
+                span: Span { start, end: start },
+            });
+        };
+
+        let token = match c {
+            '#' => Token::Comment(self.eat_while(|c| c != '\n')),
+            '\n' | '{' | '}' | '[' | ']' | ':' | ',' | '=' | ';' => {
+                self.bump();
+                match c {
+                    '\n' => Token::Newline,
+                    '{' => Token::LBrace,
+                    '}' => Token::RBrace,
+                    '[' => Token::LBracket,
+                    ']' => Token::RBracket,
+                    ':' => Token::Colon,
+                    ',' => Token::Comma,
+                    '=' => Token::Equals,
+                    _ => Token::Semicolon,
+                }
+            }
+            '"' => self.string(start)?,
+            '@' => self.raw_id(start)?,
+            '-' | '0'..='9' => self.number(start)?,
+            c if is_ident_start(c) => Token::Ident(self.eat_while(is_ident_continue)),
+            c => {
+                self.bump();
+                return Err(self.error(start, format!("unexpected character {c:?}")));
+            }
+        };
+
+        Ok(Spanned {
+            token,
+            span: Span {
+                start,
+                end: self.position,
+            },
+        })
+    }
+
+    fn string(&mut self, start: Position) -> Result<Token<'src>, ParseError> {
+        self.bump();
+        let mut value = String::new();
+        loop {
+            match self.bump() {
+                None | Some('\n') => return Err(self.error(start, "unterminated string")),
+                Some('"') => break Ok(Token::Str(value)),
+                Some('\\') => value.push(self.escape(start)?),
+                Some(c) => value.push(c),
+            }
+        }
+    }
+
+    fn escape(&mut self, start: Position) -> Result<char, ParseError> {
+        Ok(match self.bump() {
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('0') => '\0',
+            Some(c @ ('\\' | '"' | '\'')) => c,
+            Some('u') => {
+                if self.bump() != Some('{') {
+                    return Err(self.error(start, "expected `{` after `\\u`"));
+                }
+                let digits = self.eat_while(|c| c.is_ascii_hexdigit());
+                if self.bump() != Some('}') {
+                    return Err(self.error(start, "expected `}` after unicode escape"));
+                }
+                u32::from_str_radix(digits, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| self.error(start, "invalid unicode escape"))?
+            }
+            Some(c) => return Err(self.error(start, format!("unknown escape `\\{c}`"))),
+            None => return Err(self.error(start, "unterminated string")),
+        })
+    }
+
+    fn raw_id(&mut self, start: Position) -> Result<Token<'src>, ParseError> {
+        self.bump();
+        let ident = self.eat_while(is_ident_continue);
+        let (kind, index) = ident.split_at(ident.len().min(2));
+
+        let index = index
+            .parse()
+            .map_err(|_| self.error(start, format!("invalid variable id `@{ident}`")))?;
+
+        match kind {
+            "rw" => Ok(Token::RawId(variable::Id::rw(index))),
+            "ro" => Ok(Token::RawId(variable::Id::ro(index))),
+            _ => Err(self.error(start, format!("invalid variable id `@{ident}`"))),
+        }
+    }
+
+    fn number(&mut self, start: Position) -> Result<Token<'src>, ParseError> {
+        let offset = self.offset();
+        if self.peek() == Some('-') {
+            self.bump();
+            if self.peek().is_some_and(is_ident_start) {
+                return match self.eat_while(is_ident_continue) {
+                    "inf" => Ok(Token::Float(f64::NEG_INFINITY)),
+                    _ => Err(self.error(start, "expected a number after `-`")),
+                };
+            }
+        }
+
+        let mut is_float = false;
+        if self.eat_while(|c| c.is_ascii_digit()).is_empty() {
+            return Err(self.error(start, "expected a number"));
+        }
+        if self.peek() == Some('.') {
+            is_float = true;
+            self.bump();
+            self.eat_while(|c| c.is_ascii_digit());
+        }
+        if let Some('e' | 'E') = self.peek() {
+            is_float = true;
+            self.bump();
+            if let Some('+' | '-') = self.peek() {
+                self.bump();
+            }
+            if self.eat_while(|c| c.is_ascii_digit()).is_empty() {
+                return Err(self.error(start, "expected exponent digits"));
+            }
+        }
+        if self.peek().is_some_and(is_ident_continue) {
+            self.eat_while(is_ident_continue);
+            return Err(self.error(start, "invalid number literal"));
+        }
+
+        let text = &self.src[offset..self.offset()];
+        if is_float {
+            text.parse()
+                .map(Token::Float)
+                .map_err(|_| self.error(start, "invalid float literal"))
+        } else {
+            text.parse()
+                .map(Token::Int)
+                .map_err(|_| self.error(start, "integer literal out of range"))
+        }
+    }
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_ident_continue(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
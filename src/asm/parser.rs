@@ -0,0 +1,869 @@
+use super::{
+    lexer::{Lexer, Spanned, Token},
+    Analysis, ParseError, Position, Span, Symbol,
+};
+use crate::{
+    instruction::{loading, meta, mutating, pure, reading, Instruction},
+    location::SourceMap,
+    program::{self, Program},
+    value::{Operation, Type, Value},
+    variable::{self, MapBuilder},
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    mem,
+    sync::Arc,
+};
+
+/// Words with a meaning in value position, these cannot be used as variable names.
+const RESERVED: &[&str] = &["none", "true", "false", "nan", "inf", "type", "instr"];
+
+/// Parse source text into a [Program].
+pub fn parse(src: &str) -> Result<Program, ParseError> {
+    let mut parser = Parser::new(src)?;
+    let program = parser.program(false)?;
+    parser.expect(&Token::Eof)?;
+    Ok(program)
+}
+
+/// Parse source text read from `file` into a [Program], the name of the file is included in the
+/// locations of runtime errors.
+pub fn parse_named(src: &str, file: &str) -> Result<Program, ParseError> {
+    let mut parser = Parser::new(src)?;
+    parser.file = Some(file.into());
+    let program = parser.program(false)?;
+    parser.expect(&Token::Eof)?;
+    Ok(program)
+}
+
+/// Parse source text and collect what editors need to know about it, see [Analysis].
+///
+/// Unlike [parse] this also checks for instructions that write to read-only variables, and
+/// reports the variables declared before a parse error.
+#[must_use]
+pub fn analyze(src: &str) -> Analysis {
+    let mut parser = match Parser::new(src) {
+        Ok(parser) => parser,
+        Err(err) => {
+            return Analysis {
+                diagnostics: vec![err],
+                symbols: Vec::new(),
+            }
+        }
+    };
+    let result = parser
+        .program(false)
+        .and_then(|_| parser.expect(&Token::Eof));
+
+    let mut diagnostics = parser.problems;
+    diagnostics.extend(result.err());
+    diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+    Analysis {
+        diagnostics,
+        symbols: parser.symbols,
+    }
+}
+
+/// Parse source text consisting of a single value.
+///
+/// Since no variables are declared, ids can only be written in their raw form, such as `@rw0`.
+pub fn parse_value(src: &str) -> Result<Value, ParseError> {
+    Scope::new().parse_value(src)
+}
+
+/// Names of declared variables, used to parse text referring to variables that already exist.
+#[derive(Debug, Clone, Default)]
+pub struct Scope(HashMap<String, variable::Id>);
+
+impl Scope {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<variable::Id> {
+        self.0.get(name).copied()
+    }
+
+    /// Iterate over all declared names and the ids they refer to.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, variable::Id)> {
+        self.0.iter().map(|(name, id)| (name.as_str(), *id))
+    }
+
+    /// Give a name to a variable.
+    pub fn declare(&mut self, name: &str, id: variable::Id) -> Result<(), ParseError> {
+        self.check_name(name, Span::default())?;
+        self.0.insert(name.to_owned(), id);
+        Ok(())
+    }
+
+    fn check_name(&self, name: &str, span: Span) -> Result<(), ParseError> {
+        let is_ident = name
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+        if !is_ident || RESERVED.contains(&name) {
+            Err(ParseError::new(
+                span,
+                format!("`{name}` cannot be used as a variable name"),
+            ))
+        } else if self.0.contains_key(name) {
+            Err(ParseError::new(
+                span,
+                format!("`{name}` is already declared"),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Parse source text consisting of a single instruction, which may refer to variables in
+    /// scope by name.
+    pub fn parse_instruction(&self, src: &str) -> Result<Instruction, ParseError> {
+        let mut parser = Parser::new(src)?;
+        parser.skip_separators();
+        let instruction = parser.instruction(self)?;
+        parser.skip_separators();
+        parser.expect(&Token::Eof)?;
+        Ok(instruction)
+    }
+
+    /// Parse source text consisting of a single value, which may refer to variables in scope by
+    /// name.
+    pub fn parse_value(&self, src: &str) -> Result<Value, ParseError> {
+        let mut parser = Parser::new(src)?;
+        parser.skip_separators();
+        let value = parser.value(self)?;
+        parser.skip_separators();
+        parser.expect(&Token::Eof)?;
+        Ok(value)
+    }
+}
+
+/// Spans of instructions by their paths.
+type Spans = Vec<(Vec<usize>, Span)>;
+
+struct Parser<'src> {
+    tokens: Vec<Spanned<'src>>,
+    pos: usize,
+    file: Option<Arc<str>>,
+    /// Path of the instruction being parsed, relative to the program it is part of.
+    path: Vec<usize>,
+    /// Spans of the instructions parsed so far in the current program.
+    spans: Spans,
+    /// Instructions written as values in the current program, with the spans of their
+    /// instructions relative to them.
+    values: Vec<(Instruction, Spans)>,
+    /// Every declared variable, including those of nested programs.
+    symbols: Vec<Symbol>,
+    /// Indices into `symbols` by name, for each program being parsed.
+    visible: Vec<HashMap<&'src str, usize>>,
+    /// Problems that do not stop parsing.
+    problems: Vec<ParseError>,
+}
+
+impl<'src> Parser<'src> {
+    fn new(src: &'src str) -> Result<Self, ParseError> {
+        let tokens = Lexer::new(src)
+            .tokenize()?
+            .into_iter()
+            .filter(|spanned| !matches!(spanned.token, Token::Comment(_)))
+            .collect();
+
+        Ok(Self {
+            tokens,
+            pos: 0,
+            file: None,
+            path: Vec::new(),
+            spans: Vec::new(),
+            values: Vec::new(),
+            symbols: Vec::new(),
+            visible: Vec::new(),
+            problems: Vec::new(),
+        })
+    }
+
+    fn peek(&self) -> &Spanned<'src> {
+        &self.tokens[self.pos.min(self.tokens.len() - 1)]
+    }
+
+    fn next(&mut self) -> Spanned<'src> {
+        let spanned = self.peek().clone();
+        if spanned.token != Token::Eof {
+            self.pos += 1;
+        }
+        spanned
+    }
+
+    /// Span from `start` to the end of the last consumed token.
+    fn span_from(&self, start: Position) -> Span {
+        let end = self
+            .pos
+            .checked_sub(1)
+            .map_or(start, |last| self.tokens[last].span.end);
+        Span { start, end }
+    }
+
+    fn error(span: Span, message: impl Into<String>) -> ParseError {
+        ParseError::new(span, message)
+    }
+
+    fn unexpected(spanned: &Spanned, expected: &str) -> ParseError {
+        Self::error(
+            spanned.span,
+            format!("expected {expected}, found {}", spanned.token),
+        )
+    }
+
+    fn expect(&mut self, token: &Token) -> Result<Span, ParseError> {
+        let spanned = self.next();
+        if &spanned.token == token {
+            Ok(spanned.span)
+        } else {
+            Err(Self::unexpected(&spanned, &token.to_string()))
+        }
+    }
+
+    fn skip_separators(&mut self) {
+        while let Token::Newline | Token::Semicolon = self.peek().token {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while let Token::Newline = self.peek().token {
+            self.pos += 1;
+        }
+    }
+
+    /// Every declaration and instruction has to be followed by a line break, a `;`, or the end of
+    /// the enclosing block.
+    fn end_of_item(&mut self) -> Result<(), ParseError> {
+        match self.peek().token {
+            Token::Newline | Token::Semicolon => {
+                self.pos += 1;
+                Ok(())
+            }
+            Token::RBrace | Token::Eof => Ok(()),
+            _ => Err(Self::unexpected(self.peek(), "end of line")),
+        }
+    }
+
+    fn ident(&mut self, expected: &str) -> Result<(&'src str, Span), ParseError> {
+        let spanned = self.next();
+        match spanned.token {
+            Token::Ident(ident) => Ok((ident, spanned.span)),
+            _ => Err(Self::unexpected(&spanned, expected)),
+        }
+    }
+
+    fn program(&mut self, is_nested: bool) -> Result<Program, ParseError> {
+        let mut variables = MapBuilder::new();
+        let mut scope = Scope::default();
+        let mut builder = program::Builder::new();
+        let mut count = 0;
+
+        let outer_path = mem::take(&mut self.path);
+        let outer_spans = mem::take(&mut self.spans);
+        let outer_values = mem::take(&mut self.values);
+        self.visible.push(HashMap::new());
+
+        loop {
+            self.skip_separators();
+            match self.peek().token {
+                Token::RBrace if is_nested => break,
+                Token::Eof if !is_nested => break,
+                Token::Ident("rw" | "ro") => self.declaration(&mut variables, &mut scope)?,
+                Token::Ident("fallible") => {
+                    self.pos += 1;
+                    builder.is_fallible(true);
+                }
+                _ => {
+                    self.path = vec![count];
+                    builder.push_instruction(self.instruction(&scope)?);
+                    count += 1;
+                }
+            }
+            self.end_of_item()?;
+        }
+
+        // a single instruction is not wrapped in a list by the builder
+        let mut source_map = SourceMap::new(self.file.clone());
+        for (path, span) in mem::replace(&mut self.spans, outer_spans) {
+            let path = if count == 1 { path[1..].to_vec() } else { path };
+            source_map.insert(path, span);
+        }
+        for (instruction, spans) in mem::replace(&mut self.values, outer_values) {
+            source_map.insert_value(instruction, spans);
+        }
+        self.path = outer_path;
+        self.visible.pop();
+
+        builder.source_map(source_map);
+        Ok(builder.build(variables.build()))
+    }
+
+    fn declaration(
+        &mut self,
+        variables: &mut MapBuilder,
+        scope: &mut Scope,
+    ) -> Result<(), ParseError> {
+        let (kind, _) = self.ident("a declaration")?;
+        let (name, span) = self.ident("a variable name")?;
+
+        scope.check_name(name, span)?;
+
+        let id = if kind == "rw" {
+            variables.reserve_rw()
+        } else {
+            variables.reserve_ro()
+        };
+        // declared before the initial value is parsed so that it may refer to itself
+        scope.0.insert(name.to_owned(), id);
+        let symbol = self.symbols.len();
+        self.symbols.push(Symbol {
+            name: name.to_owned(),
+            id,
+            declaration: span,
+            value: Value::None,
+            references: Vec::new(),
+        });
+        if let Some(visible) = self.visible.last_mut() {
+            visible.insert(name, symbol);
+        }
+
+        if self.peek().token == Token::Equals {
+            self.pos += 1;
+            let value = self.value(scope)?;
+            self.symbols[symbol].value = value.clone();
+            variables
+                .set(id, value)
+                .expect("id was just reserved in the same builder");
+        }
+
+        Ok(())
+    }
+
+    fn block(&mut self, scope: &Scope) -> Result<Instruction, ParseError> {
+        let mut instructions = Vec::new();
+        loop {
+            self.skip_separators();
+            if let Token::RBrace = self.peek().token {
+                self.pos += 1;
+                break;
+            }
+            if let Token::Ident("rw" | "ro") = self.peek().token {
+                return Err(Self::error(
+                    self.peek().span,
+                    "variables can only be declared at program level",
+                ));
+            }
+            self.path.push(instructions.len());
+            let instruction = self.instruction(scope);
+            self.path.pop();
+            instructions.push(instruction?);
+            self.end_of_item()?;
+        }
+        Ok(meta::List(instructions).into())
+    }
+
+    fn instruction(&mut self, scope: &Scope) -> Result<Instruction, ParseError> {
+        let start = self.peek().span.start;
+        let instruction = self.bare_instruction(scope)?;
+        self.spans.push((self.path.clone(), self.span_from(start)));
+        Ok(instruction)
+    }
+
+    /// Instruction pushed by the instruction being parsed, as its child with the index.
+    fn child_instruction(
+        &mut self,
+        scope: &Scope,
+        index: usize,
+    ) -> Result<Instruction, ParseError> {
+        self.path.push(index);
+        let instruction = self.instruction(scope);
+        self.path.pop();
+        instruction
+    }
+
+    /// Child instruction after `else`, or noop without it.
+    fn otherwise(&mut self, scope: &Scope, index: usize) -> Result<Instruction, ParseError> {
+        if let Token::Ident("else") = self.peek().token {
+            self.pos += 1;
+            self.child_instruction(scope, index)
+        } else {
+            Ok(Instruction::Noop)
+        }
+    }
+
+    /// Cases of a switch, `value: instruction` on every line until the closing brace.
+    fn cases(&mut self, scope: &Scope) -> Result<Vec<(Value, Instruction)>, ParseError> {
+        let mut cases = Vec::new();
+        loop {
+            self.skip_separators();
+            if let Token::RBrace = self.peek().token {
+                self.pos += 1;
+                break;
+            }
+            let value = self.value(scope)?;
+            self.expect(&Token::Colon)?;
+            let instruction = self.child_instruction(scope, cases.len())?;
+            cases.push((value, instruction));
+            self.end_of_item()?;
+        }
+        Ok(cases)
+    }
+
+    /// Instructions used as values are not part of the instruction tree, the paths of their
+    /// instructions are relative to them.
+    fn detached_instruction(&mut self, scope: &Scope) -> Result<Instruction, ParseError> {
+        let path = mem::take(&mut self.path);
+        let spans = mem::take(&mut self.spans);
+        let instruction = self.instruction(scope);
+        self.path = path;
+        let spans = mem::replace(&mut self.spans, spans);
+        let instruction = instruction?;
+        self.values.push((instruction.clone(), spans));
+        Ok(instruction)
+    }
+
+    fn bare_instruction(&mut self, scope: &Scope) -> Result<Instruction, ParseError> {
+        let spanned = self.next();
+        let name = match spanned.token {
+            Token::LBrace => return self.block(scope),
+            Token::Ident(name) => name,
+            _ => return Err(Self::unexpected(&spanned, "an instruction")),
+        };
+
+        Ok(match name {
+            "noop" => Instruction::Noop,
+
+            "sleep" => pure::Sleep.into(),
+            "debug" => pure::Debug.into(),
+            "cond" => pure::Cond {
+                if_true: self.value(scope)?,
+                if_false: self.value(scope)?,
+            }
+            .into(),
+            "put" => pure::Put(self.value(scope)?).into(),
+            "coerce" => pure::Coerce(self.ty()?).into(),
+            "parse" => pure::Parse(self.ty()?).into(),
+            "op" => pure::Op(self.operation()?, self.value(scope)?).into(),
+            "to_fallible" => pure::ToFallible.into(),
+            "to_infallible" => pure::ToInfallible.into(),
+            "not" => pure::Not.into(),
+            "throw" => pure::Throw.into(),
+
+            "clone" => reading::Clone(self.variable(scope)?).into(),
+            "get_clone" => reading::GetClone(self.variable(scope)?).into(),
+            "op_clone" => reading::OpClone(self.operation()?, self.variable(scope)?).into(),
+
+            "take" => mutating::Take(self.written_variable(scope, name)?).into(),
+            "assign" => mutating::Assign(self.written_variable(scope, name)?).into(),
+            "swap" => mutating::Swap(self.written_variable(scope, name)?).into(),
+            "get_take" => mutating::GetTake(self.written_variable(scope, name)?).into(),
+            "map_assign" => mutating::MapAssign {
+                map: self.written_variable(scope, name)?,
+                key: self.value(scope)?,
+            }
+            .into(),
+            "op_take" => {
+                mutating::OpTake(self.operation()?, self.written_variable(scope, name)?).into()
+            }
+
+            "list" => {
+                self.expect(&Token::LBrace)?;
+                self.block(scope)?
+            }
+            "return" => meta::Return.into(),
+            "loop" => meta::Loop(Box::new(self.child_instruction(scope, 0)?)).into(),
+            "while" => meta::While {
+                condition: Box::new(self.child_instruction(scope, 0)?),
+                body: Box::new(self.child_instruction(scope, 1)?),
+            }
+            .into(),
+            "if" => meta::If {
+                if_true: Box::new(self.child_instruction(scope, 0)?),
+                if_false: Box::new(self.otherwise(scope, 1)?),
+            }
+            .into(),
+            "switch" => {
+                self.expect(&Token::LBrace)?;
+                let cases = self.cases(scope)?;
+                let default = Box::new(self.otherwise(scope, cases.len())?);
+                meta::Switch { cases, default }.into()
+            }
+            "try" => {
+                let body = Box::new(self.child_instruction(scope, 0)?);
+                self.expect(&Token::Ident("catch"))?;
+                let handler = Box::new(self.child_instruction(scope, 1)?);
+                meta::Try { body, handler }.into()
+            }
+            "break" => meta::Break.into(),
+            "continue" => meta::Continue.into(),
+            "function" => meta::Function {
+                parameters: self.variables(scope, name)?,
+                locals: self.variables(scope, name)?,
+                body: Box::new(self.child_instruction(scope, 0)?),
+            }
+            .into(),
+            "call" => meta::Call(self.variable(scope)?).into(),
+            "perform" => meta::Perform(self.value(scope)?).into(),
+            "perform_clone" => meta::PerformClone(self.variable(scope)?).into(),
+            "perform_take" => meta::PerformTake(self.written_variable(scope, name)?).into(),
+
+            "program" => {
+                self.expect(&Token::LBrace)?;
+                let program = self.program(true)?;
+                self.expect(&Token::RBrace)?;
+                loading::Program(Arc::from(program)).into()
+            }
+            "load" => loading::Load.into(),
+
+            _ => {
+                return Err(Self::error(
+                    spanned.span,
+                    format!("unknown instruction `{name}`"),
+                ))
+            }
+        })
+    }
+
+    fn variable(&mut self, scope: &Scope) -> Result<variable::Id, ParseError> {
+        let spanned = self.next();
+        match spanned.token {
+            Token::RawId(id) => Ok(id),
+            Token::Ident(name) => self.named_variable(scope, name, spanned.span),
+            _ => Err(Self::unexpected(&spanned, "a variable")),
+        }
+    }
+
+    /// Variables written by the instruction, between brackets and separated by commas.
+    fn variables(
+        &mut self,
+        scope: &Scope,
+        instruction: &str,
+    ) -> Result<Vec<variable::Id>, ParseError> {
+        self.expect(&Token::LBracket)?;
+        let mut ids = Vec::new();
+        while self.peek().token != Token::RBracket {
+            ids.push(self.written_variable(scope, instruction)?);
+            if self.peek().token != Token::Comma {
+                break;
+            }
+            self.pos += 1;
+        }
+        self.expect(&Token::RBracket)?;
+        Ok(ids)
+    }
+
+    /// A variable the instruction takes out of or writes to. Such an instruction always fails
+    /// with a read-only variable, this is reported without stopping the parser.
+    fn written_variable(
+        &mut self,
+        scope: &Scope,
+        instruction: &str,
+    ) -> Result<variable::Id, ParseError> {
+        let spanned = self.peek().clone();
+        let id = self.variable(scope)?;
+        if id.is_read_only() {
+            let variable = match spanned.token {
+                Token::Ident(name) => format!("`{name}`"),
+                _ => format!("`{}`", Value::Id(id)),
+            };
+            self.problems.push(Self::error(
+                spanned.span,
+                format!("`{instruction}` cannot be used with read-only variable {variable}"),
+            ));
+        }
+        Ok(id)
+    }
+
+    fn named_variable(
+        &mut self,
+        scope: &Scope,
+        name: &str,
+        span: Span,
+    ) -> Result<variable::Id, ParseError> {
+        let id = scope
+            .get(name)
+            .ok_or_else(|| Self::error(span, format!("unknown variable `{name}`")))?;
+        let symbol = self
+            .visible
+            .last()
+            .and_then(|visible| visible.get(name).copied());
+        if let Some(symbol) = symbol {
+            self.symbols[symbol].references.push(span);
+        }
+        Ok(id)
+    }
+
+    fn ty(&mut self) -> Result<Type, ParseError> {
+        let (name, span) = self.ident("a type")?;
+        Ok(match name {
+            "bool" => Type::Bool,
+            "int" => Type::Int,
+            "float" => Type::Float,
+            "string" => Type::String,
+            "id" => Type::Id,
+            "instruction" => Type::Instruction,
+            "list" => Type::List,
+            "map" => Type::Map,
+            "type" => Type::Type,
+            "none" => Type::None,
+            _ => return Err(Self::error(span, format!("unknown type `{name}`"))),
+        })
+    }
+
+    fn operation(&mut self) -> Result<Operation, ParseError> {
+        let (name, span) = self.ident("an operation")?;
+        Ok(match name {
+            "add" => Operation::Add,
+            "sub" => Operation::Sub,
+            "mul" => Operation::Mul,
+            "div" => Operation::Div,
+            "eq" => Operation::Eq,
+            "lt" => Operation::Lt,
+            "le" => Operation::Le,
+            "gt" => Operation::Gt,
+            "ge" => Operation::Ge,
+            "and" => Operation::And,
+            "or" => Operation::Or,
+            _ => return Err(Self::error(span, format!("unknown operation `{name}`"))),
+        })
+    }
+
+    fn value(&mut self, scope: &Scope) -> Result<Value, ParseError> {
+        let spanned = self.next();
+        Ok(match spanned.token {
+            Token::Int(value) => Value::Int(value),
+            Token::Float(value) => Value::Float(value),
+            Token::Str(value) => Value::string(value),
+            Token::RawId(id) => Value::Id(id),
+            Token::LBracket => self.list(scope)?,
+            Token::LBrace => self.map(scope)?,
+            Token::Ident("none") => Value::None,
+            Token::Ident("true") => Value::Bool(true),
+            Token::Ident("false") => Value::Bool(false),
+            Token::Ident("nan") => Value::Float(f64::NAN),
+            Token::Ident("inf") => Value::Float(f64::INFINITY),
+            Token::Ident("type") => Value::Type(self.ty()?),
+            Token::Ident("instr") => self.detached_instruction(scope)?.into(),
+            Token::Ident(name) => Value::Id(self.named_variable(scope, name, spanned.span)?),
+            _ => return Err(Self::unexpected(&spanned, "a value")),
+        })
+    }
+
+    fn list(&mut self, scope: &Scope) -> Result<Value, ParseError> {
+        let mut list = Vec::new();
+        loop {
+            self.skip_newlines();
+            if let Token::RBracket = self.peek().token {
+                self.pos += 1;
+                break;
+            }
+            list.push(self.value(scope)?);
+            self.skip_newlines();
+            match self.next() {
+                Spanned {
+                    token: Token::Comma,
+                    ..
+                } => (),
+                Spanned {
+                    token: Token::RBracket,
+                    ..
+                } => break,
+                spanned => return Err(Self::unexpected(&spanned, "`,` or `]`")),
+            }
+        }
+        Ok(Value::List(list))
+    }
+
+    fn map(&mut self, scope: &Scope) -> Result<Value, ParseError> {
+        let mut map = BTreeMap::new();
+        loop {
+            self.skip_newlines();
+            let spanned = self.next();
+            let key: Arc<str> = match spanned.token {
+                Token::RBrace => break,
+                Token::Str(key) => key.into(),
+                _ => return Err(Self::unexpected(&spanned, "a string key or `}`")),
+            };
+            self.skip_newlines();
+            self.expect(&Token::Colon)?;
+            self.skip_newlines();
+            map.insert(key, self.value(scope)?);
+            self.skip_newlines();
+            match self.next() {
+                Spanned {
+                    token: Token::Comma,
+                    ..
+                } => (),
+                Spanned {
+                    token: Token::RBrace,
+                    ..
+                } => break,
+                spanned => return Err(Self::unexpected(&spanned, "`,` or `}`")),
+            }
+        }
+        Ok(Value::Map(map))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{instruction_list, program::Builder};
+
+    /// The program of the fib example, built without the text format.
+    fn fib() -> Result<Program, crate::Error> {
+        let mut v_builder = MapBuilder::new();
+        let a = v_builder.insert_rw(1.into());
+        let b = v_builder.insert_rw(1.into());
+        let l = v_builder.reserve_ro();
+        v_builder.set(
+            l,
+            instruction_list![
+                mutating::Take(a),
+                reading::add_clone(b),
+                mutating::Swap(b),
+                mutating::Swap(a),
+                reading::Clone(l),
+                meta::Perform(Value::None),
+            ]
+            .into(),
+        )?;
+
+        let mut p_builder = Builder::new();
+        p_builder
+            .push_instruction(pure::put("starting seq").into())
+            .push_instruction(reading::Clone(l).into())
+            .push_instruction(meta::Perform(Value::None).into())
+            .is_fallible(true);
+
+        Ok(p_builder.build(v_builder.build()))
+    }
+
+    #[test]
+    pub fn parse_fib() -> Result<(), Box<dyn std::error::Error>> {
+        let src = r#"
+            # classic accumulator loop
+            rw a = 1
+            rw b = 1
+            ro l = instr {
+                take a
+                op_clone add b
+                swap b; swap a
+                clone l
+                perform none
+            }
+            fallible
+
+            put "starting seq"
+            clone l
+            perform none
+        "#;
+
+        assert_eq!(parse(src)?, fib()?);
+        Ok(())
+    }
+
+    #[test]
+    pub fn bml_macro() -> Result<(), crate::Error> {
+        let program = crate::bml! {
+            rw a = 1;
+            rw b = 1;
+            ro l = instr {
+                take a;
+                op_clone add b;
+                swap b;
+                swap a;
+                clone l;
+                perform none;
+            };
+            fallible;
+
+            put "starting seq";
+            clone l;
+            perform none;
+        };
+
+        assert_eq!(program, fib()?);
+        Ok(())
+    }
+
+    #[test]
+    pub fn parse_values() -> Result<(), ParseError> {
+        assert_eq!(
+            parse_value(r#"[1, -2.5, "a\n", { "k": [none, true] }, type int, @ro2]"#)?,
+            Value::List(vec![
+                Value::Int(1),
+                Value::Float(-2.5),
+                Value::string("a\n"),
+                Value::Map(BTreeMap::from([(
+                    Arc::from("k"),
+                    Value::List(vec![Value::None, Value::Bool(true)])
+                )])),
+                Value::Type(Type::Int),
+                Value::Id(variable::Id::ro(2)),
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    pub fn parse_errors() {
+        let message = |src| parse(src).map_err(|err| err.to_string());
+
+        assert_eq!(
+            message("rw a\nput 1\nassign b\n"),
+            Err("3:8: unknown variable `b`".to_owned())
+        );
+        assert_eq!(
+            message("{\n    put 1 2\n}"),
+            Err("2:11: expected end of line, found `2`".to_owned())
+        );
+        assert_eq!(
+            message("frobnicate"),
+            Err("1:1: unknown instruction `frobnicate`".to_owned())
+        );
+    }
+
+    #[test]
+    pub fn analysis() {
+        let analysis = analyze("rw a = 1\nro l = instr { clone a }\ntake l\nclone a\nput b");
+
+        let messages: Vec<_> = analysis
+            .diagnostics
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "3:6: `take` cannot be used with read-only variable `l`",
+                "5:5: unknown variable `b`",
+            ]
+        );
+
+        let position = |line, column| Position { line, column };
+        let a = analysis.symbol_at(position(4, 8));
+        assert_eq!(
+            a.map(|a| (a.id, &a.value)),
+            Some((variable::Id::rw(0), &Value::Int(1)))
+        );
+        assert_eq!(
+            a.map(|a| a.references.iter().map(|span| span.start).collect()),
+            Some(vec![position(2, 22), position(4, 7)])
+        );
+        assert_eq!(
+            analysis
+                .symbol_at(position(3, 6))
+                .map(|l| l.declaration.start),
+            Some(position(2, 4))
+        );
+    }
+}
@@ -0,0 +1,512 @@
+//! [Display][fmt::Display] implementations producing the text format.
+//!
+//! Values and instructions are printed on a single line by default and indented over several
+//! lines when the alternate flag (`{:#}`) is used. Programs are always printed the way they would
+//! be written in a file.
+
+use crate::{
+    instruction::{loading, meta, mutating, pure, reading, Instruction, Loading, Meta, Mutating},
+    instruction::{Pure, Reading},
+    program::Program,
+    value::{Operation, Type, Value},
+    variable,
+};
+use std::fmt::{self, Display, Write as _};
+
+const INDENT: &str = "    ";
+
+struct Printer<'a, 'f> {
+    f: &'a mut fmt::Formatter<'f>,
+    is_pretty: bool,
+    depth: usize,
+    /// Variables that have been declared with a name by the program being printed.
+    scope: Option<&'a variable::Map>,
+}
+
+impl<'a, 'f> Printer<'a, 'f> {
+    fn new(f: &'a mut fmt::Formatter<'f>) -> Self {
+        let is_pretty = f.alternate();
+        Self {
+            f,
+            is_pretty,
+            depth: 0,
+            scope: None,
+        }
+    }
+
+    /// Separate two items of a block, either by a line break or a `;`.
+    fn separator(&mut self) -> fmt::Result {
+        if self.is_pretty {
+            self.line_break()
+        } else {
+            self.f.write_str("; ")
+        }
+    }
+
+    fn line_break(&mut self) -> fmt::Result {
+        self.f.write_char('\n')?;
+        for _ in 0..self.depth {
+            self.f.write_str(INDENT)?;
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, items: &'a [Instruction]) -> fmt::Result {
+        self.braced(items, Self::instruction)
+    }
+
+    /// Write the items between braces, separated by [separator][Self::separator].
+    fn braced<T>(
+        &mut self,
+        items: &'a [T],
+        mut item: impl FnMut(&mut Self, &'a T) -> fmt::Result,
+    ) -> fmt::Result {
+        if items.is_empty() {
+            return self.f.write_str("{}");
+        }
+
+        self.f.write_char('{')?;
+        self.depth += 1;
+        if self.is_pretty {
+            self.line_break()?;
+        } else {
+            self.f.write_char(' ')?;
+        }
+        for (i, instr) in items.iter().enumerate() {
+            if i != 0 {
+                self.separator()?;
+            }
+            item(self, instr)?;
+        }
+        self.depth -= 1;
+        if self.is_pretty {
+            self.line_break()?;
+        } else {
+            self.f.write_char(' ')?;
+        }
+        self.f.write_char('}')
+    }
+
+    /// Write the declarations and instructions of a program separated by [separator][Self::separator].
+    fn program_body(&mut self, program: &'a Program) -> fmt::Result {
+        let outer_scope = self.scope.replace(program.variables());
+        let mut is_first = true;
+        let mut separate = |printer: &mut Self| {
+            if is_first {
+                is_first = false;
+                Ok(())
+            } else {
+                printer.separator()
+            }
+        };
+
+        for (id, value) in program.variables().iter() {
+            separate(self)?;
+            self.declaration(id, value)?;
+        }
+        if program.is_fallible() {
+            separate(self)?;
+            self.f.write_str("fallible")?;
+        }
+
+        match program.instruction() {
+            Instruction::Noop => (),
+            Instruction::Meta(Meta::List(meta::List(list))) if list.len() > 1 => {
+                for instr in list {
+                    separate(self)?;
+                    self.instruction(instr)?;
+                }
+            }
+            instr => {
+                separate(self)?;
+                self.instruction(instr)?;
+            }
+        }
+
+        self.scope = outer_scope;
+        Ok(())
+    }
+
+    fn declaration(&mut self, id: variable::Id, value: &'a Value) -> fmt::Result {
+        self.f
+            .write_str(if id.is_read_only() { "ro " } else { "rw " })?;
+        self.id(id)?;
+        if !value.is_none() {
+            self.f.write_str(" = ")?;
+            self.value(value)?;
+        }
+        Ok(())
+    }
+
+    fn id(&mut self, id: variable::Id) -> fmt::Result {
+        let kind = if id.is_read_only() { "ro" } else { "rw" };
+        if self.scope.is_some_and(|scope| scope.read(id).is_ok()) {
+            write!(self.f, "{kind}{}", id.index())
+        } else {
+            write!(self.f, "@{kind}{}", id.index())
+        }
+    }
+
+    fn instruction(&mut self, instr: &'a Instruction) -> fmt::Result {
+        match instr {
+            Instruction::Meta(Meta::List(meta::List(list))) => return self.block(list),
+            Instruction::Loading(Loading::Program(loading::Program(program))) => {
+                self.f.write_str("program ")?;
+                if program.variables().iter().next().is_none()
+                    && !program.is_fallible()
+                    && program.instruction().is_noop()
+                {
+                    return self.f.write_str("{}");
+                }
+
+                self.f.write_char('{')?;
+                self.depth += 1;
+                if self.is_pretty {
+                    self.line_break()?;
+                } else {
+                    self.f.write_char(' ')?;
+                }
+                self.program_body(program)?;
+                self.depth -= 1;
+                if self.is_pretty {
+                    self.line_break()?;
+                } else {
+                    self.f.write_char(' ')?;
+                }
+                return self.f.write_char('}');
+            }
+            _ => (),
+        }
+
+        self.f.write_str(instr.name())?;
+        match instr {
+            Instruction::Pure(instr) => match instr {
+                Pure::Cond(pure::Cond { if_true, if_false }) => {
+                    self.f.write_char(' ')?;
+                    self.value(if_true)?;
+                    self.f.write_char(' ')?;
+                    self.value(if_false)
+                }
+                Pure::Put(pure::Put(value)) => {
+                    self.f.write_char(' ')?;
+                    self.value(value)
+                }
+                Pure::Coerce(pure::Coerce(ty)) | Pure::Parse(pure::Parse(ty)) => {
+                    write!(self.f, " {ty}")
+                }
+                Pure::Op(pure::Op(op, value)) => {
+                    write!(self.f, " {op} ")?;
+                    self.value(value)
+                }
+                Pure::Sleep(_)
+                | Pure::Debug(_)
+                | Pure::ToFallible(_)
+                | Pure::ToInfallible(_)
+                | Pure::Not(_)
+                | Pure::Throw(_) => Ok(()),
+            },
+            Instruction::Reading(instr) => match instr {
+                Reading::Clone(reading::Clone(id)) | Reading::GetClone(reading::GetClone(id)) => {
+                    self.f.write_char(' ')?;
+                    self.id(*id)
+                }
+                Reading::OpClone(reading::OpClone(op, id)) => {
+                    write!(self.f, " {op} ")?;
+                    self.id(*id)
+                }
+            },
+            Instruction::Mutating(instr) => match instr {
+                Mutating::Take(mutating::Take(id))
+                | Mutating::Assign(mutating::Assign(id))
+                | Mutating::Swap(mutating::Swap(id))
+                | Mutating::GetTake(mutating::GetTake(id)) => {
+                    self.f.write_char(' ')?;
+                    self.id(*id)
+                }
+                Mutating::MapAssign(mutating::MapAssign { map, key }) => {
+                    self.f.write_char(' ')?;
+                    self.id(*map)?;
+                    self.f.write_char(' ')?;
+                    self.value(key)
+                }
+                Mutating::OpTake(mutating::OpTake(op, id)) => {
+                    write!(self.f, " {op} ")?;
+                    self.id(*id)
+                }
+            },
+            Instruction::Meta(instr) => self.meta_arguments(instr),
+            Instruction::Noop | Instruction::Loading(_) => Ok(()),
+            Instruction::External(external) => write!(self.f, " {external:?}"),
+        }
+    }
+
+    /// Write the arguments following the name of a meta instruction.
+    fn meta_arguments(&mut self, instr: &'a Meta) -> fmt::Result {
+        match instr {
+            Meta::Perform(meta::Perform(value)) => {
+                self.f.write_char(' ')?;
+                self.value(value)
+            }
+            Meta::PerformClone(meta::PerformClone(id))
+            | Meta::PerformTake(meta::PerformTake(id))
+            | Meta::Call(meta::Call(id)) => {
+                self.f.write_char(' ')?;
+                self.id(*id)
+            }
+            Meta::Loop(meta::Loop(body)) => {
+                self.f.write_char(' ')?;
+                self.instruction(body)
+            }
+            Meta::While(meta::While { condition, body }) => {
+                self.f.write_char(' ')?;
+                self.instruction(condition)?;
+                self.f.write_char(' ')?;
+                self.instruction(body)
+            }
+            Meta::If(meta::If { if_true, if_false }) => {
+                self.f.write_char(' ')?;
+                self.instruction(if_true)?;
+                self.otherwise(if_false)
+            }
+            Meta::Function(meta::Function {
+                parameters,
+                locals,
+                body,
+            }) => {
+                self.f.write_char(' ')?;
+                self.ids(parameters)?;
+                self.f.write_char(' ')?;
+                self.ids(locals)?;
+                self.f.write_char(' ')?;
+                self.instruction(body)
+            }
+            Meta::Try(meta::Try { body, handler }) => {
+                self.f.write_char(' ')?;
+                self.instruction(body)?;
+                self.f.write_str(" catch ")?;
+                self.instruction(handler)
+            }
+            Meta::Switch(meta::Switch { cases, default }) => {
+                self.f.write_char(' ')?;
+                self.braced(cases, |printer, (value, instr)| {
+                    printer.value(value)?;
+                    printer.f.write_str(": ")?;
+                    printer.instruction(instr)
+                })?;
+                self.otherwise(default)
+            }
+            Meta::List(_) | Meta::Return(_) | Meta::Break(_) | Meta::Continue(_) => Ok(()),
+        }
+    }
+
+    fn ids(&mut self, ids: &[variable::Id]) -> fmt::Result {
+        self.f.write_char('[')?;
+        for (i, id) in ids.iter().enumerate() {
+            if i != 0 {
+                self.f.write_str(", ")?;
+            }
+            self.id(*id)?;
+        }
+        self.f.write_char(']')
+    }
+
+    /// Write the `else` of an instruction, left out when it is noop.
+    fn otherwise(&mut self, instr: &'a Instruction) -> fmt::Result {
+        if instr.is_noop() {
+            return Ok(());
+        }
+        self.f.write_str(" else ")?;
+        self.instruction(instr)
+    }
+
+    fn value(&mut self, value: &'a Value) -> fmt::Result {
+        match value {
+            Value::Bool(value) => write!(self.f, "{value}"),
+            Value::Int(value) => write!(self.f, "{value}"),
+            Value::Float(value) => write!(self.f, "{}", FloatLiteral(*value)),
+            Value::String(value) => write!(self.f, "{value:?}"),
+            Value::Id(id) => self.id(*id),
+            Value::Instruction(instr) => {
+                self.f.write_str("instr ")?;
+                self.instruction(instr)
+            }
+            Value::List(list) => {
+                self.f.write_char('[')?;
+                for (i, value) in list.iter().enumerate() {
+                    if i != 0 {
+                        self.f.write_str(", ")?;
+                    }
+                    self.value(value)?;
+                }
+                self.f.write_char(']')
+            }
+            Value::Map(map) => {
+                if map.is_empty() {
+                    return self.f.write_str("{}");
+                }
+                self.f.write_str("{ ")?;
+                for (i, (key, value)) in map.iter().enumerate() {
+                    if i != 0 {
+                        self.f.write_str(", ")?;
+                    }
+                    write!(self.f, "{key:?}: ")?;
+                    self.value(value)?;
+                }
+                self.f.write_str(" }")
+            }
+            Value::Type(ty) => write!(self.f, "type {ty}"),
+            Value::None => self.f.write_str("none"),
+        }
+    }
+}
+
+/// Floats are always printed with a decimal point or an exponent so that they are read back as
+/// floats.
+pub(crate) struct FloatLiteral(pub f64);
+
+impl Display for FloatLiteral {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self(value) = *self;
+        if value.is_nan() {
+            f.write_str("nan")
+        } else if value.is_infinite() {
+            f.write_str(if value > 0.0 { "inf" } else { "-inf" })
+        } else {
+            write!(f, "{value:?}")
+        }
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Printer::new(f).instruction(self)
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Printer::new(f).value(self)
+    }
+}
+
+impl Display for variable::Id {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Printer::new(f).id(*self)
+    }
+}
+
+/// One declaration per line, in the same form as in a program.
+impl Display for variable::Map {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut printer = Printer::new(f);
+        printer.is_pretty = true;
+        printer.scope = Some(self);
+        for (i, (id, value)) in self.iter().enumerate() {
+            if i != 0 {
+                printer.line_break()?;
+            }
+            printer.declaration(id, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut printer = Printer::new(f);
+        printer.is_pretty = true;
+        printer.program_body(self)?;
+        printer.f.write_char('\n')
+    }
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Operation::Add => "add",
+            Operation::Sub => "sub",
+            Operation::Mul => "mul",
+            Operation::Div => "div",
+            Operation::Eq => "eq",
+            Operation::Lt => "lt",
+            Operation::Le => "le",
+            Operation::Gt => "gt",
+            Operation::Ge => "ge",
+            Operation::And => "and",
+            Operation::Or => "or",
+        })
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Type::Bool => "bool",
+            Type::Int => "int",
+            Type::Float => "float",
+            Type::String => "string",
+            Type::Id => "id",
+            Type::Instruction => "instruction",
+            Type::List => "list",
+            Type::Map => "map",
+            Type::Type => "type",
+            Type::None => "none",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{asm::parse, instruction::pure, instruction_list, value::Value};
+
+    #[test]
+    pub fn print_round_trip() -> Result<(), crate::asm::ParseError> {
+        let src = r#"
+            rw a = 1
+            rw b = [1.0, -inf, "x\ty", { "k": type float }]
+            ro l = instr {
+                take a
+                op_clone add b
+                cond instr put 1 instr {}
+                program {
+                    rw c = @rw3
+                    fallible
+                    clone c
+                }
+                clone l
+                perform none
+            }
+            rw x
+            ro f = instr function [a, x] [] { clone a; assign x; return }
+            fallible
+
+            map_assign b 2
+            while { clone a; op lt 3 } { loop { break }; continue }
+            if { put 1 } else if put 2
+            switch {
+                1: put "one"
+                "x": { clone a }
+            } else put none
+            switch {}
+            try { put 1; op div 0 } catch { put "failed"; throw }
+            put [1]
+            call f
+            clone l
+            perform none
+        "#;
+
+        let program = parse(src)?;
+        assert_eq!(parse(&program.to_string())?, program);
+        Ok(())
+    }
+
+    #[test]
+    pub fn print_compact() {
+        let instruction = instruction_list![pure::put(Value::Float(2.0)), pure::Debug, pure::Sleep];
+        assert_eq!(instruction.to_string(), "{ put 2.0; debug; sleep }");
+        assert_eq!(
+            format!("{instruction:#}"),
+            "{\n    put 2.0\n    debug\n    sleep\n}"
+        );
+    }
+}
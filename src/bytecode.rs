@@ -0,0 +1,631 @@
+//! Programs compiled to flat bytecode.
+//!
+//! [`Program::compile`] lowers the instruction tree of a program into a single list of [ops][Op].
+//! Lists are laid out in order and [`meta::Return`][crate::instruction::meta::Return] stops the
+//! program, functions are performed on the instruction stack described below. Branches jump
+//! over the ones not taken, loops jump back to their start and leave through the innermost loop
+//! entered while the program runs. Instructions performed from a constant or from a read-only
+//! variable are compiled once and called at a relative offset, so a loop that performs itself
+//! does not clone its body on every iteration, and a call in tail position jumps without keeping
+//! a frame. Errors are caught by the innermost try entered and not left yet. Values are kept in a
+//! constant pool and the remaining instructions in an instruction pool.
+//!
+//! Instructions that are only known once the program runs, like those cloned from read-write
+//! variables, are performed on an instruction [Stack] the way [Running] performs them. Results,
+//! errors and their locations are the same as when the program runs directly, but fuel is used
+//! for every op rather than for every instruction and observers are not supported.
+
+use crate::{
+    instruction::{meta, pure, traits::Loader, Context, Instruction, Meta, Stack},
+    limits::Limits,
+    location::{Location, Origin, Path, SourceMap},
+    program::{Program, Running},
+    value::Value,
+    variable, Error, Result,
+};
+use std::{mem, sync::Arc};
+
+mod compiler;
+
+/// Where the value the return value is set to after performing an instruction comes from, see
+/// [`Perform`][crate::instruction::meta::Perform],
+/// [`PerformClone`][crate::instruction::meta::PerformClone] and
+/// [`PerformTake`][crate::instruction::meta::PerformTake].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Argument {
+    /// A clone of the constant at the index.
+    Constant(usize),
+    Clone(variable::Id),
+    Take(variable::Id),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    /// Perform the instruction at the index of the instruction pool.
+    Perform(usize),
+    /// Set the return value to a clone of the constant at the index.
+    Put(usize),
+    /// Perform the compiled instruction starting the offset away from this op, then set the
+    /// return value to the argument.
+    Call { offset: isize, argument: Argument },
+    /// Continue at the compiled instruction starting the offset away from this op, returning to
+    /// the caller of the current one once it ends. The argument is still taken.
+    TailCall { offset: isize, argument: Argument },
+    /// Perform the instruction in the return value, then set the return value to the argument.
+    PerformValue(Argument),
+    /// Continue at the op the offset away from this op.
+    Jump(isize),
+    /// Continue at the op the offset away from this op if the return value is false, the
+    /// return value is set to none. Other values than booleans are wrong input for the instruction
+    /// at the index of the instruction pool.
+    JumpUnless { offset: isize, instruction: usize },
+    /// Continue at the op the offset away from this op if the return value equals the constant
+    /// at the index.
+    Case { value: usize, offset: isize },
+    /// Enter a loop, it is left at the op `exit` away from this op and continued at the op `next`
+    /// away from this op.
+    Enter { exit: isize, next: isize },
+    /// Leave the innermost loop.
+    Exit,
+    /// Leave the innermost loop, like [`meta::Break`][crate::instruction::meta::Break].
+    Break,
+    /// Continue the innermost loop, like [`meta::Continue`][crate::instruction::meta::Continue].
+    Continue,
+    /// Enter a try, errors are caught by continuing at the op `handler` away from this op with
+    /// the error as return value.
+    Try { handler: isize },
+    /// Leave the innermost try.
+    EndTry,
+    /// End of a compiled instruction, return to the caller or finish the program.
+    End,
+    /// Finish the program with the return value.
+    Halt,
+}
+
+/// A [Program] compiled to ops.
+#[derive(Debug, Clone, Default)]
+pub struct Bytecode {
+    code: Vec<Op>,
+    /// Path of the instruction every op was compiled from, relative to the instruction that was
+    /// compiled.
+    paths: Vec<Vec<usize>>,
+    /// Number of the instruction written as a value every op was compiled from in the source map,
+    /// if it was compiled from one.
+    values: Vec<Option<usize>>,
+    constants: Vec<Value>,
+    instructions: Vec<Instruction>,
+    variables: variable::Map,
+    is_fallible: bool,
+    source_map: Option<Arc<SourceMap>>,
+}
+
+impl Program {
+    /// Compile the program to bytecode, see the [module documentation][crate::bytecode].
+    #[must_use]
+    pub fn compile(&self) -> Bytecode {
+        compiler::compile(self)
+    }
+}
+
+impl Bytecode {
+    /// Ops of the program, the program starts at the first one.
+    #[must_use]
+    pub fn code(&self) -> &[Op] {
+        &self.code
+    }
+
+    #[must_use]
+    pub fn constants(&self) -> &[Value] {
+        &self.constants
+    }
+
+    #[must_use]
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    pub fn run_to_completion(&self, input: Value, loader: &dyn Loader) -> Result<Value> {
+        self.run_to_completion_with_limits(input, loader, &mut Limits::new())
+    }
+
+    /// Run the program to completion within `limits` like
+    /// [`Program::run_to_completion_with_limits`]. Reaching a limit is an error even if the
+    /// program is fallible, the program cannot be resumed afterwards.
+    pub fn run_to_completion_with_limits(
+        &self,
+        input: Value,
+        loader: &dyn Loader,
+        limits: &mut Limits,
+    ) -> Result<Value> {
+        let mut stack = Stack::new();
+        stack.set_source_map(self.source_map.clone());
+        let mut machine = Machine {
+            bytecode: self,
+            variables: self.variables.clone(),
+            stack,
+            frames: Vec::new(),
+            loops: Vec::new(),
+            handlers: Vec::new(),
+            base: Path::root(),
+            return_value: input,
+            next: 0,
+        };
+        match machine.run(loader, limits) {
+            Err(err) if err.is_limit() => Err(err.unlocated().clone()),
+            Err(_) if self.is_fallible => Ok(Value::None),
+            result => result,
+        }
+    }
+}
+
+/// Where to continue once a called instruction ends.
+#[derive(Debug)]
+struct Frame {
+    next: usize,
+    base: Path,
+    argument: Value,
+}
+
+/// A loop that was entered and not left yet.
+#[derive(Debug)]
+struct Loop {
+    exit: usize,
+    next: usize,
+    /// Number of frames when the loop was entered, leaving or continuing the loop returns from
+    /// the calls made since.
+    frames: usize,
+    /// Number of tries when the loop was entered, the tries entered since are left with it.
+    handlers: usize,
+    base: Path,
+}
+
+/// A try that was entered and not left yet.
+#[derive(Debug)]
+struct Handler {
+    start: usize,
+    frames: usize,
+    loops: usize,
+    base: Path,
+}
+
+/// State of running bytecode.
+struct Machine<'a> {
+    bytecode: &'a Bytecode,
+    variables: variable::Map,
+    /// Instructions performed from values, they are performed before the next op.
+    stack: Stack,
+    frames: Vec<Frame>,
+    loops: Vec<Loop>,
+    handlers: Vec<Handler>,
+    /// Path of the instruction that is being performed by the current call.
+    base: Path,
+    return_value: Value,
+    /// Index of the next op.
+    next: usize,
+}
+
+impl Machine<'_> {
+    fn run(&mut self, loader: &dyn Loader, limits: &mut Limits) -> Result<Value> {
+        loop {
+            limits.step()?;
+            let finished = if self.stack.is_empty() {
+                self.perform_op(loader, limits)
+            } else {
+                self.perform_instruction(loader, limits)
+            }
+            .or_else(|err| self.catch(err))?;
+            if finished {
+                break Ok(mem::take(&mut self.return_value));
+            }
+            if limits.memory.is_some() {
+                limits.check_memory(self.heap_size())?;
+            }
+        }
+    }
+
+    /// Perform the next op, returns whether the program finished.
+    fn perform_op(&mut self, loader: &dyn Loader, limits: &mut Limits) -> Result<bool> {
+        let at = self.next;
+        self.next += 1;
+        let op = self.bytecode.code[at];
+        match op {
+            Op::Perform(index) => {
+                let instr = self.bytecode.instructions[index].clone();
+                let value = mem::take(&mut self.return_value);
+                let mut context = Context {
+                    variables: &mut self.variables,
+                    stack: &mut self.stack,
+                    loader,
+                    limits,
+                    observer: None,
+                };
+                self.return_value = Running::handle_instruction(instr, value, &mut context)
+                    .map_err(|err| err.located(self.location(at)))?;
+            }
+            Op::Put(index) => self.return_value = self.bytecode.constants[index].clone(),
+            Op::Call { offset, argument } => self.call(at, offset, argument, false)?,
+            Op::TailCall { offset, argument } => self.call(at, offset, argument, true)?,
+            Op::PerformValue(argument) => match mem::take(&mut self.return_value) {
+                Value::Instruction(instruction) => {
+                    let put = pure::Put(self.argument(argument, at)?);
+                    self.stack.set_path(self.path(at));
+                    self.stack.push(put);
+                    self.stack.push_value(*instruction);
+                }
+                value => {
+                    return Err(Error::PerformOnNonInstruction(value).located(self.location(at)))
+                }
+            },
+            Op::Jump(offset) => self.next = at.wrapping_add_signed(offset),
+            Op::JumpUnless {
+                offset,
+                instruction,
+            } => match mem::take(&mut self.return_value) {
+                Value::Bool(true) => (),
+                Value::Bool(false) => self.next = at.wrapping_add_signed(offset),
+                value => {
+                    let instruction = self.bytecode.instructions[instruction].clone();
+                    return Err(
+                        Error::WrongInstructionInput(value, instruction).located(self.location(at))
+                    );
+                }
+            },
+            Op::Case { value, offset } => {
+                if self.return_value == self.bytecode.constants[value] {
+                    self.next = at.wrapping_add_signed(offset);
+                }
+            }
+            Op::Enter { exit, next } => self.loops.push(Loop {
+                exit: at.wrapping_add_signed(exit),
+                next: at.wrapping_add_signed(next),
+                frames: self.frames.len(),
+                handlers: self.handlers.len(),
+                base: self.base.clone(),
+            }),
+            Op::Exit => {
+                self.loops.pop();
+            }
+            Op::Break | Op::Continue => {
+                let is_break = op == Op::Break;
+                if !self.leave_loop(is_break) {
+                    let instruction = if is_break {
+                        meta::Break.into()
+                    } else {
+                        meta::Continue.into()
+                    };
+                    return Err(Error::OutsideOfLoop(instruction).located(self.location(at)));
+                }
+            }
+            Op::Try { handler } => self.handlers.push(Handler {
+                start: at.wrapping_add_signed(handler),
+                frames: self.frames.len(),
+                loops: self.loops.len(),
+                base: self.base.clone(),
+            }),
+            Op::EndTry => {
+                self.handlers.pop();
+            }
+            Op::End => match self.frames.pop() {
+                Some(frame) => {
+                    self.next = frame.next;
+                    self.base = frame.base;
+                    self.return_value = frame.argument;
+                }
+                None => return Ok(true),
+            },
+            Op::Halt => return Ok(true),
+        }
+        Ok(false)
+    }
+
+    /// Continue at the compiled instruction starting the offset away from the op at `at`, keeping
+    /// a frame to return to unless `is_tail`.
+    fn call(&mut self, at: usize, offset: isize, argument: Argument, is_tail: bool) -> Result<()> {
+        let argument = self.argument(argument, at)?;
+        let base = self.path(at).child(1);
+        let base = mem::replace(&mut self.base, base);
+        if !is_tail {
+            self.frames.push(Frame {
+                next: self.next,
+                base,
+                argument,
+            });
+        }
+        self.return_value = Value::None;
+        self.next = at.wrapping_add_signed(offset);
+        Ok(())
+    }
+
+    /// Perform the next instruction of the stack, returns whether the program finished.
+    fn perform_instruction(&mut self, loader: &dyn Loader, limits: &mut Limits) -> Result<bool> {
+        let Some(instr) = self.stack.pop() else {
+            return Ok(false);
+        };
+        // the stack only holds the instructions performed from values, returning outside of its
+        // functions drops the ops after them as well
+        match instr {
+            Instruction::Meta(Meta::Return(_)) if !self.stack.in_frame() => return Ok(true),
+            // loops of the ops are left from instructions outside of the loops and functions on
+            // the stack
+            Instruction::Meta(Meta::Break(_) | Meta::Continue(_))
+                if !self.stack.in_loop() && !self.stack.in_frame() && !self.loops.is_empty() =>
+            {
+                self.stack.clear();
+                self.leave_loop(matches!(instr, Instruction::Meta(Meta::Break(_))));
+                return Ok(false);
+            }
+            _ => (),
+        }
+        let value = mem::take(&mut self.return_value);
+        let mut context = Context {
+            variables: &mut self.variables,
+            stack: &mut self.stack,
+            loader,
+            limits,
+            observer: None,
+        };
+        self.return_value = Running::handle_instruction(instr, value, &mut context)
+            .map_err(|err| err.located(self.stack.location()))?;
+        Ok(false)
+    }
+
+    /// Break out of or continue the innermost loop, returns whether there was a loop.
+    fn leave_loop(&mut self, is_break: bool) -> bool {
+        let Some(innermost) = self.loops.last() else {
+            return false;
+        };
+        self.next = if is_break {
+            innermost.exit
+        } else {
+            innermost.next
+        };
+        self.frames.truncate(innermost.frames);
+        self.handlers.truncate(innermost.handlers);
+        self.base = innermost.base.clone();
+        if is_break {
+            self.loops.pop();
+        }
+        true
+    }
+
+    /// Continue with the handler of the innermost try, on the stack or else of the ops, returns
+    /// the error if there is none or if it was caused by reaching a limit.
+    fn catch(&mut self, err: Error) -> Result<bool> {
+        if err.is_limit() {
+            return Err(err);
+        }
+        let saved = if let Some(saved) = self.stack.catch() {
+            saved
+        } else {
+            let Some(innermost) = self.handlers.pop() else {
+                return Err(err);
+            };
+            self.frames.truncate(innermost.frames);
+            self.loops.truncate(innermost.loops);
+            self.base = innermost.base;
+            self.next = innermost.start;
+            self.stack.unwind()
+        };
+        self.variables.restore(saved);
+        self.return_value = err.into();
+        Ok(false)
+    }
+
+    fn argument(&mut self, argument: Argument, at: usize) -> Result<Value> {
+        match argument {
+            Argument::Constant(index) => Ok(self.bytecode.constants[index].clone()),
+            Argument::Clone(id) => self.variables.read(id).cloned(),
+            Argument::Take(id) => self.variables.read_mut(id).map(mem::take),
+        }
+        .map_err(|err| err.located(self.location(at)))
+    }
+
+    /// Path of the instruction the op at the index was compiled from.
+    fn path(&self, at: usize) -> Path {
+        self.bytecode.paths[at]
+            .iter()
+            .fold(self.base.clone(), |path, &index| path.child(index))
+    }
+
+    fn location(&self, at: usize) -> Location {
+        let origin = self.bytecode.values[at].map(|value| Origin {
+            value,
+            depth: self.base.depth(),
+        });
+        Location::new(self.path(at), self.bytecode.source_map.as_deref(), origin)
+    }
+
+    fn heap_size(&mut self) -> usize {
+        self.variables.update_heap_size()
+            + self.stack.heap_size()
+            + self.return_value.heap_size()
+            + self.frames.capacity() * mem::size_of::<Frame>()
+            + self
+                .frames
+                .iter()
+                .map(|frame| frame.argument.heap_size())
+                .sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::DefaultLoader;
+
+    fn run_both(program: &Program) -> (Result<Value>, Result<Value>) {
+        let bytecode = program.compile();
+        (
+            program
+                .clone()
+                .run_to_completion(Value::None, &DefaultLoader),
+            bytecode.run_to_completion(Value::None, &DefaultLoader),
+        )
+    }
+
+    #[test]
+    pub fn same_as_tree() {
+        let counting = crate::bml! {
+            rw n = 0;
+            ro l = instr {
+                take n;
+                op add 1;
+                assign n;
+                clone n;
+                op lt 1000;
+                cond instr { clone l; perform none } instr { clone n; return };
+                perform none;
+            };
+            clone l;
+            perform none;
+        };
+        assert_eq!(
+            run_both(&counting),
+            (Ok(Value::Int(1000)), Ok(Value::Int(1000)))
+        );
+
+        let looping = crate::bml! {
+            rw n = 0;
+            rw sum = 0;
+            ro stop = instr { clone n; op eq 7; cond instr break instr {}; perform none };
+            while { clone n; op lt 10 } {
+                take n;
+                op add 1;
+                assign n;
+                clone stop;
+                perform none;
+                clone n;
+                op eq 3;
+                cond instr continue instr {};
+                perform none;
+                loop { take sum; op_clone add n; assign sum; break };
+            };
+            take sum;
+        };
+        assert_eq!(run_both(&looping), (Ok(Value::Int(18)), Ok(Value::Int(18))));
+
+        let branching = crate::bml! {
+            rw n = 0;
+            ro l = instr {
+                take n;
+                op add 1;
+                assign n;
+                clone n;
+                switch { 5: put 50; 56: { put 0; return } } else put 1;
+                op_take add n;
+                assign n;
+                clone n;
+                op lt 100;
+                if { clone l; perform none } else clone n;
+            };
+            clone l;
+            perform none;
+        };
+        assert_eq!(run_both(&branching), (Ok(Value::Int(0)), Ok(Value::Int(0))));
+
+        let recovering = crate::bml! {
+            rw n = 0;
+            rw caught = 0;
+            rw f = instr { clone n; op div 0 };
+            ro l = instr { clone n; op eq 2; if { try { put 3; op div 0 } catch continue } };
+            loop {
+                take n;
+                op add 1;
+                assign n;
+                clone n;
+                op gt 4;
+                if break;
+                try { clone l; perform none; clone f; perform none } catch {
+                    take caught;
+                    op add 1;
+                    assign caught;
+                    clone n;
+                    op eq 3;
+                    if { try { put "x"; parse int } catch break }
+                };
+            };
+            take caught;
+        };
+        assert_eq!(
+            run_both(&recovering),
+            (Ok(Value::Int(2)), Ok(Value::Int(2)))
+        );
+
+        let failing = crate::bml! {
+            rw f = instr { put 1; op div 0 };
+            ro l = instr { put 2; clone f; perform 3 };
+            put "a";
+            clone l;
+            perform_clone f;
+        };
+        let (tree, bytecode) = run_both(&failing);
+        assert_eq!(tree, bytecode);
+        let location = bytecode.err().and_then(|err| err.location().cloned());
+        assert_eq!(
+            location.map(|location| location.path.indices()),
+            Some(vec![2, 1, 2, 1, 1])
+        );
+    }
+
+    #[test]
+    pub fn calls() {
+        let calling = crate::bml! {
+            rw n = 0;
+            rw i;
+            ro count = instr function [n] [i] {
+                put 0;
+                assign i;
+                loop {
+                    take i;
+                    op add 1;
+                    assign i;
+                    clone i;
+                    op_clone eq n;
+                    if { clone i; return };
+                };
+            };
+            ro l = instr { put [3]; call count; op_take add n; assign n };
+            put 4;
+            assign i;
+            loop {
+                clone l;
+                perform none;
+                take i;
+                op sub 1;
+                assign i;
+                clone i;
+                op eq 0;
+                if break;
+            };
+            clone n;
+        };
+        assert_eq!(run_both(&calling), (Ok(Value::Int(12)), Ok(Value::Int(12))));
+    }
+
+    #[test]
+    pub fn tail_call() {
+        let program = crate::bml! {
+            rw n = 0;
+            ro l = instr { take n; op add 1; assign n; clone l; perform none };
+            clone l;
+            perform none;
+        };
+        let bytecode = program.compile();
+        assert!(matches!(bytecode.code()[0], Op::Call { offset: 2, .. }));
+        assert_eq!(
+            bytecode.code()[5],
+            Op::TailCall {
+                offset: -3,
+                argument: Argument::Constant(1),
+            }
+        );
+
+        let mut limits = Limits {
+            fuel: crate::limits::Fuel::new(10_000),
+            ..Limits::new()
+        };
+        let result =
+            bytecode.run_to_completion_with_limits(Value::None, &DefaultLoader, &mut limits);
+        assert_eq!(result, Err(Error::OutOfFuel));
+    }
+}
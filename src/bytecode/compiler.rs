@@ -0,0 +1,345 @@
+use super::{Argument, Bytecode, Op};
+use crate::{
+    instruction::{meta, pure, reading, Instruction, Meta, Pure, Reading},
+    program::Program,
+    value::Value,
+};
+use std::{collections::BTreeMap, mem, sync::Arc};
+
+struct Compiler {
+    bytecode: Bytecode,
+    /// Start of every compiled instruction, [None] until it is compiled.
+    starts: Vec<Option<usize>>,
+    /// Instructions waiting to be compiled, with the index of their start.
+    pending: Vec<(Instruction, usize)>,
+    /// Number of the instruction written as a value being compiled in the source map, if it is
+    /// one.
+    value: Option<usize>,
+    /// Index of the start of the instructions of read-only variables, by variable index.
+    variables: BTreeMap<usize, usize>,
+    /// Calls to point at the start with the index once everything is compiled.
+    calls: Vec<(usize, usize)>,
+    /// Last op jumped to, ops before it cannot be merged into a call.
+    first: usize,
+}
+
+pub(super) fn compile(program: &Program) -> Bytecode {
+    let mut compiler = Compiler {
+        bytecode: Bytecode {
+            variables: program.variables().clone(),
+            is_fallible: program.is_fallible(),
+            source_map: program.source_map().cloned().map(Arc::new),
+            ..Bytecode::default()
+        },
+        starts: Vec::new(),
+        pending: Vec::new(),
+        value: None,
+        variables: BTreeMap::new(),
+        calls: Vec::new(),
+        first: 0,
+    };
+
+    // at the top level nothing is left to return to, so calls there keep their frame
+    compiler.instruction(program.instruction().clone(), &mut Vec::new(), false);
+    compiler.emit(Op::End, &[]);
+    while let Some((instruction, start)) = compiler.pending.pop() {
+        compiler.first = compiler.bytecode.code.len();
+        compiler.starts[start] = Some(compiler.first);
+        compiler.value = compiler
+            .bytecode
+            .source_map
+            .as_ref()
+            .and_then(|map| map.value(&instruction));
+        compiler.instruction(instruction, &mut Vec::new(), true);
+        compiler.emit(Op::End, &[]);
+    }
+
+    for (at, start) in mem::take(&mut compiler.calls) {
+        let target = compiler.starts[start].unwrap_or_default();
+        if let Op::Call { offset, .. } | Op::TailCall { offset, .. } =
+            &mut compiler.bytecode.code[at]
+        {
+            *offset = offset_between(at, target);
+        }
+    }
+    compiler.bytecode
+}
+
+fn offset_between(from: usize, to: usize) -> isize {
+    to.wrapping_sub(from).cast_signed()
+}
+
+impl Compiler {
+    fn emit(&mut self, op: Op, path: &[usize]) {
+        self.bytecode.code.push(op);
+        self.bytecode.paths.push(path.to_vec());
+        self.bytecode.values.push(self.value);
+    }
+
+    fn constant(&mut self, value: Value) -> usize {
+        self.bytecode.constants.push(value);
+        self.bytecode.constants.len() - 1
+    }
+
+    /// Compile `instruction` found at `path`, `is_tail` if nothing is performed after it before
+    /// the compiled instruction ends.
+    fn instruction(&mut self, instruction: Instruction, path: &mut Vec<usize>, is_tail: bool) {
+        match instruction {
+            Instruction::Meta(Meta::List(meta::List(list))) => {
+                let len = list.len();
+                for (index, instruction) in list.into_iter().enumerate() {
+                    path.push(index);
+                    self.instruction(instruction, path, is_tail && index + 1 == len);
+                    path.pop();
+                }
+            }
+            Instruction::Meta(Meta::Return(_)) => self.emit(Op::Halt, path),
+            Instruction::Meta(Meta::Loop(meta::Loop(body))) => self.repeat(None, *body, path),
+            Instruction::Meta(Meta::While(instruction)) => {
+                self.bytecode.instructions.push(instruction.clone().into());
+                let checked = self.bytecode.instructions.len() - 1;
+                let meta::While { condition, body } = instruction;
+                self.repeat(Some((*condition, checked)), *body, path);
+            }
+            Instruction::Meta(Meta::Break(_)) => self.emit(Op::Break, path),
+            Instruction::Meta(Meta::Continue(_)) => self.emit(Op::Continue, path),
+            Instruction::Meta(Meta::Try(meta::Try { body, handler })) => {
+                self.attempt(*body, *handler, path, is_tail);
+            }
+            Instruction::Meta(Meta::If(instruction)) => self.branch(instruction, path, is_tail),
+            Instruction::Meta(Meta::Switch(meta::Switch { cases, default })) => {
+                self.switch(cases, *default, path, is_tail);
+            }
+            Instruction::Meta(Meta::Perform(meta::Perform(value))) => {
+                let argument = Argument::Constant(self.constant(value));
+                self.perform(argument, path, is_tail);
+            }
+            Instruction::Meta(Meta::PerformClone(meta::PerformClone(id))) => {
+                self.perform(Argument::Clone(id), path, is_tail);
+            }
+            Instruction::Meta(Meta::PerformTake(meta::PerformTake(id))) => {
+                self.perform(Argument::Take(id), path, is_tail);
+            }
+            Instruction::Pure(Pure::Put(pure::Put(value))) => {
+                let index = self.constant(value);
+                self.emit(Op::Put(index), path);
+            }
+            instruction => {
+                self.bytecode.instructions.push(instruction);
+                self.emit(Op::Perform(self.bytecode.instructions.len() - 1), path);
+            }
+        }
+    }
+
+    /// Compile an instruction pushed by the instruction at `path`, as its child with the index.
+    fn child(
+        &mut self,
+        instruction: Instruction,
+        path: &mut Vec<usize>,
+        index: usize,
+        is_tail: bool,
+    ) {
+        path.push(index);
+        self.instruction(instruction, path, is_tail);
+        path.pop();
+    }
+
+    /// Compile a loop, with a condition checked before every iteration by the instruction at the
+    /// index of the instruction pool.
+    fn repeat(
+        &mut self,
+        condition: Option<(Instruction, usize)>,
+        body: Instruction,
+        path: &mut Vec<usize>,
+    ) {
+        let enter = self.bytecode.code.len();
+        self.emit(Op::Enter { exit: 0, next: 0 }, path);
+        let start = self.target();
+        let check = condition.map(|(condition, instruction)| {
+            self.child(condition, path, 0, false);
+            self.emit(
+                Op::JumpUnless {
+                    offset: 0,
+                    instruction,
+                },
+                path,
+            );
+            (self.bytecode.code.len() - 1, instruction)
+        });
+
+        self.child(body, path, usize::from(check.is_some()), false);
+        let back = offset_between(self.bytecode.code.len(), start);
+        self.emit(Op::Jump(back), path);
+        if let Some((at, instruction)) = check {
+            let done = self.target();
+            self.emit(Op::Exit, path);
+            self.bytecode.code[at] = Op::JumpUnless {
+                offset: offset_between(at, done),
+                instruction,
+            };
+        }
+
+        let exit = self.target();
+        self.bytecode.code[enter] = Op::Enter {
+            exit: offset_between(enter, exit),
+            next: offset_between(enter, start),
+        };
+    }
+
+    /// Compile an if, jumping over the instruction that is not performed.
+    fn branch(&mut self, instruction: meta::If, path: &mut Vec<usize>, is_tail: bool) {
+        self.bytecode.instructions.push(instruction.clone().into());
+        let checked = self.bytecode.instructions.len() - 1;
+        let meta::If { if_true, if_false } = instruction;
+
+        let check = self.bytecode.code.len();
+        self.emit(
+            Op::JumpUnless {
+                offset: 0,
+                instruction: checked,
+            },
+            path,
+        );
+        self.child(*if_true, path, 0, is_tail);
+        let skip = self.bytecode.code.len();
+        self.emit(Op::Jump(0), path);
+
+        let otherwise = self.target();
+        self.child(*if_false, path, 1, is_tail);
+        let end = self.target();
+        self.bytecode.code[check] = Op::JumpUnless {
+            offset: offset_between(check, otherwise),
+            instruction: checked,
+        };
+        self.bytecode.code[skip] = Op::Jump(offset_between(skip, end));
+    }
+
+    /// Compile a switch, a case op for every case then the default, followed by the instructions
+    /// of the cases.
+    fn switch(
+        &mut self,
+        cases: Vec<(Value, Instruction)>,
+        default: Instruction,
+        path: &mut Vec<usize>,
+        is_tail: bool,
+    ) {
+        let first = self.bytecode.code.len();
+        let (values, instructions): (Vec<_>, Vec<_>) = cases.into_iter().unzip();
+        for value in values {
+            let value = self.constant(value);
+            self.emit(Op::Case { value, offset: 0 }, path);
+        }
+        self.child(default, path, instructions.len(), is_tail);
+
+        let mut skips = Vec::new();
+        for (index, instruction) in instructions.into_iter().enumerate() {
+            skips.push(self.bytecode.code.len());
+            self.emit(Op::Jump(0), path);
+            let start = self.target();
+            if let Op::Case { offset, .. } = &mut self.bytecode.code[first + index] {
+                *offset = offset_between(first + index, start);
+            }
+            self.child(instruction, path, index, is_tail);
+        }
+
+        let end = self.target();
+        for at in skips {
+            self.bytecode.code[at] = Op::Jump(offset_between(at, end));
+        }
+    }
+
+    /// Compile a try, the handler follows the body and is jumped over when the body succeeds.
+    fn attempt(
+        &mut self,
+        body: Instruction,
+        handler: Instruction,
+        path: &mut Vec<usize>,
+        is_tail: bool,
+    ) {
+        let enter = self.bytecode.code.len();
+        self.emit(Op::Try { handler: 0 }, path);
+        self.child(body, path, 0, false);
+        self.emit(Op::EndTry, path);
+        let skip = self.bytecode.code.len();
+        self.emit(Op::Jump(0), path);
+
+        let start = self.target();
+        self.child(handler, path, 1, is_tail);
+        let end = self.target();
+        self.bytecode.code[enter] = Op::Try {
+            handler: offset_between(enter, start),
+        };
+        self.bytecode.code[skip] = Op::Jump(offset_between(skip, end));
+    }
+
+    /// Index of the next op, which is jumped to. Ops before it cannot be merged into a call.
+    fn target(&mut self) -> usize {
+        self.first = self.bytecode.code.len();
+        self.first
+    }
+
+    /// Perform the return value, calling it directly if the op before put a known instruction.
+    fn perform(&mut self, argument: Argument, path: &[usize], is_tail: bool) {
+        let Some(start) = self.known_instruction() else {
+            self.emit(Op::PerformValue(argument), path);
+            return;
+        };
+        self.bytecode.code.pop();
+        self.bytecode.paths.pop();
+        self.bytecode.values.pop();
+
+        self.calls.push((self.bytecode.code.len(), start));
+        let offset = 0;
+        if is_tail {
+            self.emit(Op::TailCall { offset, argument }, path);
+        } else {
+            self.emit(Op::Call { offset, argument }, path);
+        }
+    }
+
+    /// Index of the start of the instruction the last op puts in the return value, if it can
+    /// only put that instruction.
+    fn known_instruction(&mut self) -> Option<usize> {
+        if self.bytecode.code.len() <= self.first {
+            return None;
+        }
+        match self.bytecode.code.last()? {
+            Op::Put(index) => {
+                let Value::Instruction(instruction) = &self.bytecode.constants[*index] else {
+                    return None;
+                };
+                let instruction = (**instruction).clone();
+                Some(self.start(instruction))
+            }
+            Op::Perform(index) => {
+                let Instruction::Reading(Reading::Clone(reading::Clone(id))) =
+                    self.bytecode.instructions[*index]
+                else {
+                    return None;
+                };
+                // read-only variables keep their instruction while the program runs
+                if !id.is_read_only() {
+                    return None;
+                }
+                if let Some(start) = self.variables.get(&id.index()) {
+                    return Some(*start);
+                }
+                let Ok(Value::Instruction(instruction)) = self.bytecode.variables.read(id) else {
+                    return None;
+                };
+                let instruction = (**instruction).clone();
+                let start = self.start(instruction);
+                self.variables.insert(id.index(), start);
+                Some(start)
+            }
+            _ => None,
+        }
+    }
+
+    /// Queue `instruction` to be compiled, returns the index of its start.
+    fn start(&mut self, instruction: Instruction) -> usize {
+        self.starts.push(None);
+        self.pending.push((instruction, self.starts.len() - 1));
+        self.starts.len() - 1
+    }
+}
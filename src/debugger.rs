@@ -0,0 +1,222 @@
+//! Pausing running programs to look at them.
+//!
+//! A [Debugger] takes the steps of a [Running] program, pausing whenever the next instruction
+//! matches one of its [breakpoints][Breakpoint]. While paused the pending instructions, the
+//! return value and the variables can be inspected through [`Debugger::running`].
+//!
+//! Instructions that push other instructions, like [`meta::List`][crate::instruction::meta::List]
+//! and [`meta::Perform`][crate::instruction::meta::Perform], can be stepped into, pausing before
+//! the first pushed instruction, or stepped over, pausing once everything they pushed is done.
+//! Nested programs always run as a single step.
+
+use crate::{
+    instruction::{traits::Loader, Instruction, Stack},
+    limits::Limits,
+    location::Path,
+    program::Running,
+    value::Value,
+    variable, Result,
+};
+
+/// Condition on the next instruction to pause at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// The instruction at the path.
+    Path(Path),
+    /// Every instruction with the name, as spelled in the text format.
+    Kind(String),
+}
+
+impl Breakpoint {
+    #[must_use]
+    pub fn matches(&self, instruction: &Instruction, path: &Path) -> bool {
+        match self {
+            Self::Path(breakpoint) => breakpoint == path,
+            Self::Kind(name) => instruction.name() == name,
+        }
+    }
+}
+
+/// Why the debugger paused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pause {
+    /// The requested step is done.
+    Step,
+    /// The next instruction matches the breakpoint at the index.
+    Breakpoint(usize),
+    /// The program finished, its result is in [`Running::Finished`].
+    Finished,
+}
+
+/// Debugging session over a running program.
+#[derive(Debug, Clone, Default)]
+pub struct Debugger {
+    pub breakpoints: Vec<Breakpoint>,
+    running: Running,
+}
+
+impl Debugger {
+    #[must_use]
+    pub fn new(running: Running) -> Self {
+        Self {
+            breakpoints: Vec::new(),
+            running,
+        }
+    }
+
+    #[must_use]
+    pub fn running(&self) -> &Running {
+        &self.running
+    }
+
+    #[must_use]
+    pub fn into_running(self) -> Running {
+        self.running
+    }
+
+    /// Pending instructions, `None` once the program finished.
+    #[must_use]
+    pub fn stack(&self) -> Option<&Stack> {
+        match &self.running {
+            Running::Active { stack, .. } => Some(stack),
+            Running::Finished(_) => None,
+        }
+    }
+
+    /// Variables of the program, `None` once the program finished.
+    #[must_use]
+    pub fn variables(&self) -> Option<&variable::Map> {
+        match &self.running {
+            Running::Active { variables, .. } => Some(variables),
+            Running::Finished(_) => None,
+        }
+    }
+
+    /// Return value of the instruction performed last, `None` once the program finished.
+    #[must_use]
+    pub fn return_value(&self) -> Option<&Value> {
+        match &self.running {
+            Running::Active { return_value, .. } => Some(return_value),
+            Running::Finished(_) => None,
+        }
+    }
+
+    /// Perform the next instruction, pausing before the first instruction it pushed if any.
+    ///
+    /// # Errors
+    /// The error of a reached limit, see [`Running::progress_with_limits`].
+    pub fn step_into(&mut self, loader: &dyn Loader, limits: &mut Limits) -> Result<Pause> {
+        self.running.progress_with_limits(loader, limits)?;
+        Ok(self.pause().unwrap_or(Pause::Step))
+    }
+
+    /// Perform the next instruction together with every instruction it pushed, pausing early
+    /// at a breakpoint.
+    ///
+    /// # Errors
+    /// The error of a reached limit, see [`Running::progress_with_limits`].
+    pub fn step_over(&mut self, loader: &dyn Loader, limits: &mut Limits) -> Result<Pause> {
+        let Some(depth) = self.stack().map(Stack::len) else {
+            return Ok(Pause::Finished);
+        };
+        loop {
+            self.running.progress_with_limits(loader, limits)?;
+            if let Some(pause) = self.pause() {
+                break Ok(pause);
+            }
+            if self.stack().is_some_and(|stack| stack.len() < depth) {
+                break Ok(Pause::Step);
+            }
+        }
+    }
+
+    /// Run until the next instruction matches a breakpoint or the program finished. The next
+    /// instruction is performed even if it matches a breakpoint.
+    ///
+    /// # Errors
+    /// The error of a reached limit, see [`Running::progress_with_limits`].
+    pub fn resume(&mut self, loader: &dyn Loader, limits: &mut Limits) -> Result<Pause> {
+        loop {
+            self.running.progress_with_limits(loader, limits)?;
+            if let Some(pause) = self.pause() {
+                break Ok(pause);
+            }
+        }
+    }
+
+    fn pause(&self) -> Option<Pause> {
+        let Running::Active { stack, .. } = &self.running else {
+            return Some(Pause::Finished);
+        };
+        let (instruction, path) = stack.peek()?;
+        self.breakpoints
+            .iter()
+            .position(|breakpoint| breakpoint.matches(instruction, path))
+            .map(Pause::Breakpoint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::DefaultLoader;
+
+    #[test]
+    pub fn stepping() -> Result<()> {
+        let program = crate::bml! {
+            rw n = 0;
+            ro l = instr { take n; op add 1; assign n };
+            clone l;
+            perform none;
+            clone l;
+            perform none;
+            take n;
+        };
+        let next = |debugger: &Debugger| {
+            debugger
+                .stack()
+                .and_then(Stack::peek)
+                .map(|(instruction, path)| (instruction.name(), path.indices()))
+        };
+        let n = |debugger: &Debugger| {
+            debugger
+                .variables()
+                .and_then(|variables| variables.iter().next())
+                .map(|(_, value)| value.clone())
+        };
+        let (loader, limits) = (&DefaultLoader, &mut Limits::new());
+
+        let mut debugger = Debugger::new(program.run(Value::None));
+        debugger
+            .breakpoints
+            .push(Breakpoint::Kind("perform".into()));
+        assert_eq!(debugger.resume(loader, limits)?, Pause::Breakpoint(0));
+        assert_eq!(next(&debugger), Some(("perform", vec![1])));
+
+        assert_eq!(debugger.step_into(loader, limits)?, Pause::Step);
+        assert_eq!(next(&debugger), Some(("list", vec![1, 1])));
+        assert_eq!(debugger.step_into(loader, limits)?, Pause::Step);
+        assert_eq!(next(&debugger), Some(("take", vec![1, 1, 0])));
+        assert_eq!(debugger.step_into(loader, limits)?, Pause::Step);
+        assert_eq!(debugger.return_value(), Some(&Value::Int(0)));
+        assert_eq!(debugger.step_over(loader, limits)?, Pause::Step);
+        assert_eq!(debugger.return_value(), Some(&Value::Int(1)));
+
+        debugger
+            .breakpoints
+            .push(Breakpoint::Path(Path::from(&[4][..])));
+        assert_eq!(debugger.resume(loader, limits)?, Pause::Breakpoint(0));
+        assert_eq!(n(&debugger), Some(Value::Int(1)));
+        // the step ends in front of the breakpoint
+        assert_eq!(debugger.step_over(loader, limits)?, Pause::Breakpoint(1));
+        assert_eq!(next(&debugger), Some(("take", vec![4])));
+        assert_eq!(n(&debugger), Some(Value::Int(2)));
+
+        assert_eq!(debugger.resume(loader, limits)?, Pause::Finished);
+        assert_eq!(
+            debugger.into_running(),
+            Running::Finished(Ok(Value::Int(2)))
+        );
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 use std::{
     fmt::{self, Debug},
+    mem,
     sync::Arc,
 };
 
@@ -14,13 +15,13 @@ pub mod pure;
 pub mod reading;
 pub mod traits;
 
+mod context;
 mod set_macro;
 mod stack;
 
+pub use context::Context;
 pub use stack::Stack;
 
-use self::traits::Loader;
-
 #[derive(Debug, Deserialize, Serialize, Clone, Default, IsVariant, PartialEq)]
 pub enum Instruction {
     #[default]
@@ -46,13 +47,14 @@ Pure(rval: Value) -> Value: [
     ToFallible,
     ToInfallible,
     Not,
+    Throw,
 ],
 Reading(rval: Value, map: &variable::Map) -> Value: [
     Clone,
     GetClone,
     OpClone,
 ],
-Mutating(rval: Value, map: variable::Map) -> (Value, variable::Map): [
+Mutating(rval: Value, map: &mut variable::Map) -> Value: [
     Take,
     Assign,
     Swap,
@@ -60,20 +62,99 @@ Mutating(rval: Value, map: variable::Map) -> (Value, variable::Map): [
     MapAssign,
     OpTake,
 ],
-Meta(rval: Value, map: variable::Map, stack: Stack) -> (Value, variable::Map, Stack): [
+Meta(rval: Value, context: &mut Context<'_>) -> Value: [
     List,
     Return,
     Perform,
     PerformClone,
     PerformTake,
+    Loop,
+    While,
+    Break,
+    Continue,
+    If,
+    Switch,
+    Try,
+    Function,
+    Call,
 ],
-Loading(rval: Value, loader: &dyn Loader) -> Value: [
+Loading(rval: Value, context: &mut Context<'_>) -> Value: [
     Program,
     Load,
 ]
 }
 
 impl Instruction {
+    /// Name of the instruction, as spelled in the text format.
+    #[must_use]
+    pub fn name(&self) -> &'static str {
+        match self {
+            Instruction::Noop => "noop",
+            Instruction::Pure(instr) => instr.name(),
+            Instruction::Reading(instr) => instr.name(),
+            Instruction::Mutating(instr) => instr.name(),
+            Instruction::Meta(instr) => instr.name(),
+            Instruction::Loading(instr) => instr.name(),
+            Instruction::External(_) => "external",
+        }
+    }
+
+    /// Estimate of the number of bytes the instruction owns on the heap, see
+    /// [`Value::heap_size`].
+    #[must_use]
+    pub fn heap_size(&self) -> usize {
+        match self {
+            Instruction::Pure(Pure::Put(pure::Put(value)) | Pure::Op(pure::Op(_, value)))
+            | Instruction::Mutating(Mutating::MapAssign(mutating::MapAssign {
+                key: value, ..
+            }))
+            | Instruction::Meta(Meta::Perform(meta::Perform(value))) => value.heap_size(),
+            Instruction::Pure(Pure::Cond(pure::Cond { if_true, if_false })) => {
+                if_true.heap_size() + if_false.heap_size()
+            }
+            Instruction::Meta(Meta::List(meta::List(list))) => {
+                list.capacity() * mem::size_of::<Instruction>()
+                    + list.iter().map(Instruction::heap_size).sum::<usize>()
+            }
+            Instruction::Meta(Meta::Loop(meta::Loop(body))) => {
+                mem::size_of::<Instruction>() + body.heap_size()
+            }
+            Instruction::Meta(Meta::While(meta::While { condition, body })) => {
+                2 * mem::size_of::<Instruction>() + condition.heap_size() + body.heap_size()
+            }
+            Instruction::Meta(Meta::If(meta::If { if_true, if_false })) => {
+                2 * mem::size_of::<Instruction>() + if_true.heap_size() + if_false.heap_size()
+            }
+            Instruction::Meta(Meta::Function(meta::Function {
+                parameters,
+                locals,
+                body,
+            })) => {
+                (parameters.capacity() + locals.capacity()) * mem::size_of::<variable::Id>()
+                    + mem::size_of::<Instruction>()
+                    + body.heap_size()
+            }
+            Instruction::Meta(Meta::Try(meta::Try { body, handler })) => {
+                2 * mem::size_of::<Instruction>() + body.heap_size() + handler.heap_size()
+            }
+            Instruction::Meta(Meta::Switch(meta::Switch { cases, default })) => {
+                cases.capacity() * mem::size_of::<(Value, Instruction)>()
+                    + cases
+                        .iter()
+                        .map(|(value, instruction)| value.heap_size() + instruction.heap_size())
+                        .sum::<usize>()
+                    + mem::size_of::<Instruction>()
+                    + default.heap_size()
+            }
+            Instruction::Loading(Loading::Program(loading::Program(program))) => {
+                mem::size_of::<crate::program::Program>()
+                    + program.variables().heap_size()
+                    + program.instruction().heap_size()
+            }
+            _ => 0,
+        }
+    }
+
     #[must_use]
     pub fn flatten(self) -> Self {
         let Instruction::Meta(Meta::List(meta::List(instr_vec))) = self else {
@@ -91,19 +172,16 @@ impl Instruction {
             }
         }
 
-        match out_instrs.len() {
-            0 => Instruction::Noop,
-            1 => out_instrs
-                .into_iter()
-                .next()
-                .expect("should never fail since we know the size is 1"),
-            _ => meta::List(out_instrs).into(),
+        if out_instrs.len() > 1 {
+            meta::List(out_instrs).into()
+        } else {
+            out_instrs.pop().unwrap_or_default()
         }
     }
 }
 
 #[derive(Clone)]
-pub struct External(pub Arc<dyn traits::External>);
+pub struct External(pub Arc<dyn traits::SyncExternal>);
 
 impl Debug for External {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -133,6 +211,12 @@ impl traits::Loader for DefaultLoader {
     }
 }
 
+impl traits::AsyncLoader for DefaultLoader {
+    fn load(&self, value: Value) -> traits::BoxFuture<'_, Result<Value>> {
+        Box::pin(std::future::ready(Err(Error::UnloadableValue(value))))
+    }
+}
+
 // the name would make no sense otherwise and conflict with into
 #[allow(clippy::module_name_repetitions)]
 pub trait IntoInstruction {
@@ -176,6 +260,7 @@ macro_rules! instruction_list {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::value::Operation;
     use pure::put;
 
     #[test]
@@ -194,4 +279,29 @@ mod tests {
 
         assert_eq!(instruction_expect, instruction_input.flatten());
     }
+
+    #[test]
+    pub fn float_operations() {
+        use instr_traits::Pure as _;
+
+        let results = [Operation::Sub, Operation::Mul, Operation::Div]
+            .map(|operation| pure::Op(operation, Value::Float(2.5)).perform(Value::Float(7.5)));
+        assert_eq!(results, [5.0, 18.75, 3.0].map(|n| Ok(Value::Float(n))));
+    }
+
+    #[test]
+    pub fn list_input() {
+        let mut builder = crate::program::Builder::new();
+        builder
+            .push_instruction(pure::Op(Operation::Add, Value::Int(1)).into())
+            .push_instruction(instruction_list![
+                pure::Op(Operation::Mul, Value::Int(2)),
+                pure::Op(Operation::Sub, Value::Int(3)),
+            ]);
+        let program = builder.build(variable::Map::default());
+        assert_eq!(
+            program.run_to_completion(Value::Int(4), &DefaultLoader),
+            Ok(Value::Int(7))
+        );
+    }
 }
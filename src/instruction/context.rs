@@ -0,0 +1,12 @@
+use super::{traits::Loader, Stack};
+use crate::{limits::Limits, observer::Observer, variable};
+
+/// State of a running program, borrowed by an instruction while it is performed.
+pub struct Context<'a> {
+    pub variables: &'a mut variable::Map,
+    /// Instructions left to perform, the performed instruction is already popped.
+    pub stack: &'a mut Stack,
+    pub loader: &'a dyn Loader,
+    pub limits: &'a mut Limits,
+    pub observer: Option<&'a mut dyn Observer>,
+}
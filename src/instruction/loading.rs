@@ -1,6 +1,5 @@
-use super::instr_traits::Loading;
-use super::traits::Loader;
-use crate::{program, value::Value, Result};
+use super::{instr_traits::Loading, Context};
+use crate::{observer::Observer, program, value::Value, Result};
 use serde::{Deserialize, Serialize};
 use std::{mem, sync::Arc};
 use tap::Pipe;
@@ -8,20 +7,24 @@ use tap::Pipe;
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct Program(pub Arc<program::Program>);
 impl Loading for Program {
-    fn perform(self, return_value: Value, loader: &dyn Loader) -> Result<Value> {
+    fn perform(self, return_value: Value, context: &mut Context<'_>) -> Result<Value> {
         let Self(mut arc_prgr) = self;
+        let observer = context
+            .observer
+            .as_mut()
+            .map(|observer| &mut **observer as &mut dyn Observer);
 
         arc_prgr
             .pipe_ref_mut(Arc::make_mut)
             .pipe(mem::take)
-            .run_to_completion(return_value, loader)
+            .run_within(return_value, context.loader, context.limits, observer)
     }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
 pub struct Load;
 impl Loading for Load {
-    fn perform(self, return_value: Value, loader: &dyn Loader) -> Result<Value> {
-        loader.load(return_value)
+    fn perform(self, return_value: Value, context: &mut Context<'_>) -> Result<Value> {
+        context.loader.load(return_value)
     }
 }
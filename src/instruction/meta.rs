@@ -1,4 +1,4 @@
-use super::{instr_traits::Meta, pure, Instruction};
+use super::{instr_traits::Meta, pure, Context, Instruction};
 use crate::{value::Value, variable, Error, Result};
 use serde::{Deserialize, Serialize};
 use std::mem;
@@ -7,16 +7,12 @@ use tap::Pipe;
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct List(pub Vec<Instruction>);
 impl Meta for List {
-    fn perform(
-        self,
-        _return_value: Value,
-        variables: variable::Map,
-        mut instruction_stack: super::Stack,
-    ) -> Result<(Value, variable::Map, super::Stack)> {
+    fn perform(self, return_value: Value, context: &mut Context<'_>) -> Result<Value> {
         let Self(list) = self;
 
-        instruction_stack.extend(list.into_iter().rev());
-        Ok((Value::None, variables, instruction_stack))
+        // the first instruction gets the return value, as if the list was not there
+        context.stack.push_list(list);
+        Ok(return_value)
     }
 }
 
@@ -26,36 +22,30 @@ impl FromIterator<Instruction> for List {
     }
 }
 
+/// Return from the innermost [Function] with the return value, or finish the program if there
+/// is none.
 #[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
 pub struct Return;
 impl Meta for Return {
-    fn perform(
-        self,
-        return_value: Value,
-        variables: variable::Map,
-        mut instruction_stack: super::Stack,
-    ) -> Result<(Value, variable::Map, super::Stack)> {
-        instruction_stack.clear();
-        Ok((return_value, variables, instruction_stack))
+    fn perform(self, return_value: Value, context: &mut Context<'_>) -> Result<Value> {
+        if !context.stack.return_from_frame() {
+            context.stack.clear();
+        }
+        Ok(return_value)
     }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct Perform(pub Value);
 impl Meta for Perform {
-    fn perform(
-        self,
-        return_value: Value,
-        variables: variable::Map,
-        mut instruction_stack: super::Stack,
-    ) -> Result<(Value, variable::Map, super::Stack)> {
+    fn perform(self, return_value: Value, context: &mut Context<'_>) -> Result<Value> {
         let Self(value) = self;
 
         match return_value {
             Value::Instruction(instruction) => {
-                instruction_stack.push(pure::Put(value));
-                instruction_stack.push(*instruction);
-                Ok((Value::None, variables, instruction_stack))
+                context.stack.push(pure::Put(value));
+                context.stack.push_value(*instruction);
+                Ok(Value::None)
             }
             value => Err(Error::PerformOnNonInstruction(value)),
         }
@@ -65,19 +55,15 @@ impl Meta for Perform {
 #[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
 pub struct PerformClone(pub variable::Id);
 impl Meta for PerformClone {
-    fn perform(
-        self,
-        return_value: Value,
-        variables: variable::Map,
-        mut instruction_stack: super::Stack,
-    ) -> Result<(Value, variable::Map, super::Stack)> {
+    fn perform(self, return_value: Value, context: &mut Context<'_>) -> Result<Value> {
         let Self(value) = self;
 
         match return_value {
             Value::Instruction(instruction) => {
-                instruction_stack.push(variables.read(value)?.clone().pipe(pure::Put));
-                instruction_stack.push(*instruction);
-                Ok((Value::None, variables, instruction_stack))
+                let put = context.variables.read(value)?.clone().pipe(pure::Put);
+                context.stack.push(put);
+                context.stack.push_value(*instruction);
+                Ok(Value::None)
             }
             value => Err(Error::PerformOnNonInstruction(value)),
         }
@@ -87,21 +73,204 @@ impl Meta for PerformClone {
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
 pub struct PerformTake(pub variable::Id);
 impl Meta for PerformTake {
-    fn perform(
-        self,
-        return_value: Value,
-        mut variables: variable::Map,
-        mut instruction_stack: super::Stack,
-    ) -> Result<(Value, variable::Map, super::Stack)> {
+    fn perform(self, return_value: Value, context: &mut Context<'_>) -> Result<Value> {
         let Self(value) = self;
 
         match return_value {
             Value::Instruction(instruction) => {
-                instruction_stack.push(variables.read_mut(value)?.pipe(mem::take).pipe(pure::Put));
-                instruction_stack.push(*instruction);
-                Ok((Value::None, variables, instruction_stack))
+                let put = context
+                    .variables
+                    .read_mut(value)?
+                    .pipe(mem::take)
+                    .pipe(pure::Put);
+                context.stack.push(put);
+                context.stack.push_value(*instruction);
+                Ok(Value::None)
             }
             value => Err(Error::PerformOnNonInstruction(value)),
         }
     }
 }
+
+/// Perform the instruction over and over, until the loop is left with [Break]. Every iteration
+/// gets the return value of the previous one, the first one gets the return value of the loop.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Loop(pub Box<Instruction>);
+impl Meta for Loop {
+    fn perform(self, return_value: Value, context: &mut Context<'_>) -> Result<Value> {
+        let body = (*self.0).clone();
+        context.stack.push_loop(self, None).push(body);
+        Ok(return_value)
+    }
+}
+
+/// Perform the body as long as the condition returns true, the loop then returns none. The
+/// condition gets the return value of the previous iteration, the first time it gets the return
+/// value of the loop, and the body always gets none.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct While {
+    pub condition: Box<Instruction>,
+    pub body: Box<Instruction>,
+}
+impl Meta for While {
+    fn perform(self, return_value: Value, context: &mut Context<'_>) -> Result<Value> {
+        let condition = (*self.condition).clone();
+        if !context.stack.is_iteration() {
+            context.stack.push_loop(self, Some(condition));
+            return Ok(return_value);
+        }
+
+        match return_value {
+            Value::Bool(true) => {
+                let body = (*self.body).clone();
+                context.stack.push_loop(self, Some(condition)).push(body);
+                Ok(Value::None)
+            }
+            Value::Bool(false) => Ok(Value::None),
+            value => Err(Error::WrongInstructionInput(value, self.into())),
+        }
+    }
+}
+
+/// Leave the innermost loop, the return value is returned by the loop.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub struct Break;
+impl Meta for Break {
+    fn perform(self, return_value: Value, context: &mut Context<'_>) -> Result<Value> {
+        if context.stack.break_loop() {
+            Ok(return_value)
+        } else {
+            Err(Error::OutsideOfLoop(self.into()))
+        }
+    }
+}
+
+/// Skip the rest of the current iteration of the innermost loop, the return value is passed on
+/// to the next one.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub struct Continue;
+impl Meta for Continue {
+    fn perform(self, return_value: Value, context: &mut Context<'_>) -> Result<Value> {
+        if context.stack.continue_loop() {
+            Ok(return_value)
+        } else {
+            Err(Error::OutsideOfLoop(self.into()))
+        }
+    }
+}
+
+/// Perform one of two instructions, chosen by the boolean return value. The chosen instruction
+/// gets none.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct If {
+    pub if_true: Box<Instruction>,
+    pub if_false: Box<Instruction>,
+}
+impl Meta for If {
+    fn perform(self, return_value: Value, context: &mut Context<'_>) -> Result<Value> {
+        match return_value {
+            Value::Bool(true) => context.stack.push_child(0, *self.if_true),
+            Value::Bool(false) => context.stack.push_child(1, *self.if_false),
+            value => return Err(Error::WrongInstructionInput(value, self.into())),
+        };
+        Ok(Value::None)
+    }
+}
+
+/// Perform the instruction of the first case equal to the return value, or the default if none
+/// is. The chosen instruction gets the return value.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Switch {
+    pub cases: Vec<(Value, Instruction)>,
+    pub default: Box<Instruction>,
+}
+impl Meta for Switch {
+    fn perform(self, return_value: Value, context: &mut Context<'_>) -> Result<Value> {
+        let Self { cases, default } = self;
+
+        let index = cases.len();
+        let chosen = cases
+            .into_iter()
+            .enumerate()
+            .find(|(_, (value, _))| *value == return_value);
+        match chosen {
+            Some((index, (_, instruction))) => context.stack.push_child(index, instruction),
+            None => context.stack.push_child(index, *default),
+        };
+        Ok(return_value)
+    }
+}
+
+/// Perform the body, if it or an instruction it pushes fails the instructions left of it are
+/// dropped and the handler is performed with the error converted to a [value][Value]. The body
+/// gets the return value of the try. Errors caused by reaching a [limit][crate::limits::Limits]
+/// are not caught.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Try {
+    pub body: Box<Instruction>,
+    pub handler: Box<Instruction>,
+}
+impl Meta for Try {
+    fn perform(self, return_value: Value, context: &mut Context<'_>) -> Result<Value> {
+        context.stack.push_try(*self.body, *self.handler);
+        Ok(return_value)
+    }
+}
+
+/// Perform the body with its own values of the parameters and the locals, the return value has
+/// to be a list with an argument for every parameter. The values the variables had are restored
+/// once the body is done or [returns][Return], or when an error is caught outside of the body.
+/// The body gets none and the function returns what the body returns.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Function {
+    pub parameters: Vec<variable::Id>,
+    pub locals: Vec<variable::Id>,
+    pub body: Box<Instruction>,
+}
+impl Meta for Function {
+    fn perform(self, return_value: Value, context: &mut Context<'_>) -> Result<Value> {
+        if let Some(saved) = context.stack.ended_frame() {
+            context.variables.restore(saved);
+            return Ok(return_value);
+        }
+
+        let arguments = match return_value {
+            Value::List(arguments) if arguments.len() == self.parameters.len() => arguments,
+            value => return Err(Error::WrongInstructionInput(value, self.into())),
+        };
+        // nothing is changed unless every variable can be written
+        for &id in self.parameters.iter().chain(&self.locals) {
+            context.variables.read_mut(id)?;
+        }
+        let mut saved = Vec::with_capacity(self.parameters.len() + self.locals.len());
+        for (&id, argument) in self.parameters.iter().zip(arguments) {
+            saved.push((id, mem::replace(context.variables.read_mut(id)?, argument)));
+        }
+        for &id in &self.locals {
+            saved.push((id, mem::take(context.variables.read_mut(id)?)));
+        }
+
+        let body = (*self.body).clone();
+        context.stack.push_frame(self, saved).push(body);
+        Ok(Value::None)
+    }
+}
+
+/// Call the function in the variable with the return value as its arguments, see [Function].
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub struct Call(pub variable::Id);
+impl Meta for Call {
+    fn perform(self, return_value: Value, context: &mut Context<'_>) -> Result<Value> {
+        let Self(id) = self;
+
+        match context.variables.read(id)? {
+            Value::Instruction(instruction)
+                if matches!(**instruction, Instruction::Meta(super::Meta::Function(_))) =>
+            {
+                context.stack.push_value((**instruction).clone());
+                Ok(return_value)
+            }
+            value => Err(Error::WrongInstructionInput(value.clone(), self.into())),
+        }
+    }
+}
@@ -9,88 +9,58 @@ use std::mem;
 #[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
 pub struct Take(pub variable::Id);
 impl Mutating for Take {
-    fn perform(
-        self,
-        _return_value: Value,
-        mut variables: variable::Map,
-    ) -> Result<(Value, variable::Map)> {
-        Ok((mem::take(variables.read_mut(self.0)?), variables))
+    fn perform(self, _return_value: Value, variables: &mut variable::Map) -> Result<Value> {
+        Ok(mem::take(variables.read_mut(self.0)?))
     }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
 pub struct Assign(pub variable::Id);
 impl Mutating for Assign {
-    fn perform(
-        self,
-        return_value: Value,
-        mut variables: variable::Map,
-    ) -> Result<(Value, variable::Map)> {
+    fn perform(self, return_value: Value, variables: &mut variable::Map) -> Result<Value> {
         *variables.read_mut(self.0)? = return_value;
-        Ok((Value::None, variables))
+        Ok(Value::None)
     }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
 pub struct Swap(pub variable::Id);
 impl Mutating for Swap {
-    fn perform(
-        self,
-        return_value: Value,
-        mut variables: variable::Map,
-    ) -> Result<(Value, variable::Map)> {
-        Ok((
-            mem::replace(variables.read_mut(self.0)?, return_value),
-            variables,
-        ))
+    fn perform(self, return_value: Value, variables: &mut variable::Map) -> Result<Value> {
+        Ok(mem::replace(variables.read_mut(self.0)?, return_value))
     }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
 pub struct GetTake(pub variable::Id);
 impl Mutating for GetTake {
-    fn perform(
-        self,
-        return_value: Value,
-        mut variables: variable::Map,
-    ) -> Result<(Value, variable::Map)> {
+    fn perform(self, return_value: Value, variables: &mut variable::Map) -> Result<Value> {
         let key = variables.maybe_read(return_value)?;
-        let value = variables.read_mut(self.0)?.get_take(key)?;
-        Ok((value, variables))
+        variables.read_mut(self.0)?.get_take(key)
     }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct MapAssign {
-    map: variable::Id,
-    key: Value,
+    pub map: variable::Id,
+    pub key: Value,
 }
 impl Mutating for MapAssign {
-    fn perform(
-        self,
-        return_value: Value,
-        mut variables: variable::Map,
-    ) -> Result<(Value, variable::Map)> {
+    fn perform(self, return_value: Value, variables: &mut variable::Map) -> Result<Value> {
         let Self { map, key } = self;
         let key = variables.maybe_read(key)?;
         *variables.read_mut(map)?.get_mut(key)? = return_value;
-        Ok((Value::None, variables))
+        Ok(Value::None)
     }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
 pub struct OpTake(pub value::Operation, pub variable::Id);
 impl Mutating for OpTake {
-    fn perform(
-        self,
-        return_value: Value,
-        mut variables: variable::Map,
-    ) -> Result<(Value, variable::Map)> {
+    fn perform(self, return_value: Value, variables: &mut variable::Map) -> Result<Value> {
         let Self(operation, id) = self;
 
-        operation
-            .apply(return_value, mem::take(variables.read_mut(id)?))
-            .map(|value| (value, variables))
+        operation.apply(return_value, mem::take(variables.read_mut(id)?))
     }
 }
 
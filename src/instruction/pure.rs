@@ -1,5 +1,6 @@
-use super::{instr_traits::Pure, loading::Program, IntoInstruction};
+use super::{instr_traits::Pure, loading::Program, traits::Timer, IntoInstruction};
 use crate::{
+    limits::Limits,
     value::{self, def_op_fn, Value},
     Error, Result,
 };
@@ -19,30 +20,50 @@ impl Pure for Debug {
 pub struct Sleep;
 impl Pure for Sleep {
     fn perform(self, return_value: Value) -> Result<Value> {
-        let Value::Float(duration) = return_value else {
-            return Err(Error::WrongInstructionInput(
-                return_value,
-                self.into(),
-            ));
+        thread::sleep(self.duration(return_value)?);
+        Ok(Value::None)
+    }
+}
+
+impl Sleep {
+    fn duration(self, return_value: Value) -> Result<Duration> {
+        let Value::Float(seconds) = return_value else {
+            return Err(Error::WrongInstructionInput(return_value, self.into()));
         };
+        Duration::try_from_secs_f64(seconds)
+            .map_err(|_| Error::WrongInstructionInput(Value::Float(seconds), self.into()))
+    }
 
-        thread::sleep(Duration::from_secs_f64(duration));
+    /// Sleep like [perform][Pure::perform], but wake up early when one of the `limits` is reached.
+    pub(crate) fn perform_within(self, return_value: Value, limits: &Limits) -> Result<Value> {
+        limits.sleep(self.duration(return_value)?)?;
+        Ok(Value::None)
+    }
+
+    /// Wait using `timer` instead of blocking, waking up early when one of the `limits` is
+    /// reached.
+    pub(crate) async fn perform_async(
+        self,
+        return_value: Value,
+        timer: &dyn Timer,
+        limits: &Limits,
+    ) -> Result<Value> {
+        limits
+            .sleep_async(self.duration(return_value)?, timer)
+            .await?;
         Ok(Value::None)
     }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct Cond {
-    if_true: Value,
-    if_false: Value,
+    pub if_true: Value,
+    pub if_false: Value,
 }
 impl Pure for Cond {
     fn perform(self, return_value: Value) -> Result<Value> {
         let Value::Bool(value) = return_value else {
-            return Err(Error::WrongInstructionInput(
-                return_value,
-                self.into(),
-            ))
+            return Err(Error::WrongInstructionInput(return_value, self.into()));
         };
 
         let Self { if_true, if_false } = self;
@@ -80,7 +101,7 @@ impl Pure for Parse {
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
-pub struct Op(value::Operation, Value);
+pub struct Op(pub value::Operation, pub Value);
 impl Pure for Op {
     fn perform(self, lhs: Value) -> Result<Value> {
         let Self(operation, rhs) = self;
@@ -95,11 +116,16 @@ pub struct ToFallible;
 impl Pure for ToFallible {
     fn perform(self, return_value: Value) -> Result<Value> {
         let Value::Instruction(boxed_instr) = return_value else {
-             return Err(Error::WrongInstructionInput(return_value, self.into()))
+            return Err(Error::WrongInstructionInput(return_value, self.into()));
         };
 
-        let super::Instruction::Loading(super::Loading::Program(Program(mut arc_prgr))) = *boxed_instr else {
-             return Err(Error::WrongInstructionInput(boxed_instr.into(), self.into()))
+        let super::Instruction::Loading(super::Loading::Program(Program(mut arc_prgr))) =
+            *boxed_instr
+        else {
+            return Err(Error::WrongInstructionInput(
+                boxed_instr.into(),
+                self.into(),
+            ));
         };
 
         if !arc_prgr.is_fallible() {
@@ -116,11 +142,16 @@ pub struct ToInfallible;
 impl Pure for ToInfallible {
     fn perform(self, return_value: Value) -> Result<Value> {
         let Value::Instruction(boxed_instr) = return_value else {
-             return Err(Error::WrongInstructionInput(return_value, self.into()))
+            return Err(Error::WrongInstructionInput(return_value, self.into()));
         };
 
-        let super::Instruction::Loading(super::Loading::Program(Program(mut arc_prgr))) = *boxed_instr else {
-             return Err(Error::WrongInstructionInput(boxed_instr.into(), self.into()))
+        let super::Instruction::Loading(super::Loading::Program(Program(mut arc_prgr))) =
+            *boxed_instr
+        else {
+            return Err(Error::WrongInstructionInput(
+                boxed_instr.into(),
+                self.into(),
+            ));
         };
 
         if arc_prgr.is_fallible() {
@@ -143,3 +174,31 @@ impl Pure for Not {
         }
     }
 }
+
+/// Fail with the return value as the payload of [`Error::Thrown`].
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub struct Throw;
+impl Pure for Throw {
+    fn perform(self, return_value: Value) -> Result<Value> {
+        Err(Error::Thrown(return_value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn invalid_sleep() {
+        for seconds in [-1.0, f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                Sleep.perform(Value::Float(seconds)),
+                Err(Error::WrongInstructionInput(..))
+            ));
+            assert!(matches!(
+                Sleep.perform_within(Value::Float(seconds), &Limits::new()),
+                Err(Error::WrongInstructionInput(..))
+            ));
+        }
+    }
+}
@@ -46,6 +46,21 @@ macro_rules! instr {
         );
 
         impl $sect {
+            paste::paste! {
+                /// Names of the instructions in this set, as spelled in the text format.
+                pub const NAMES: &'static [&'static str] = &[$(stringify!([< $name:snake >])),*];
+
+                /// Name of the instruction, as spelled in the text format.
+                #[must_use]
+                pub fn name(&self) -> &'static str {
+                    match self {
+                        $(
+                        Self::$name(_) => stringify!([< $name:snake >]),
+                        )*
+                    }
+                }
+            }
+
             #[doc = "# Errors\nIf the performed instruction errors,"]
             pub fn perform(self, $($in_n: $in_ty),*) -> Result<$out_ty> {
                  use instr_traits::$sect as _;
@@ -59,6 +74,16 @@ macro_rules! instr {
             }
         }
         )*
+        impl Instruction {
+            paste::paste! {
+                /// Names of all instructions, as spelled in the text format.
+                pub const NAMES: &'static [&'static str] = &[
+                    "noop",
+                    $($(stringify!([< $name:snake >]),)*)*
+                ];
+            }
+        }
+
         pub mod instr_traits {
             use super::*;
 
@@ -1,7 +1,82 @@
 use super::Instruction;
+use crate::{
+    location::{self, Location, Origin, Path, PathTable, SourceMap},
+    value::Value,
+    variable,
+};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{borrow::Cow, mem, sync::Arc};
 
-#[derive(Debug, Default, Clone, PartialEq)]
-pub struct Stack(Vec<Instruction>);
+/// Instructions waiting to be performed, together with their [paths][Path].
+///
+/// Instructions pushed while an instruction is performed are considered children of the
+/// instruction that was popped last. Like for a [Program][crate::program::Program] the source
+/// map is not serialized, and paths are serialized once for all instructions they are an
+/// ancestor of.
+///
+/// A loop pushes itself with [`push_loop`][Self::push_loop] before the instructions of an
+/// iteration, and is performed again once they are done unless the loop is left with
+/// [`break_loop`][Self::break_loop]. A try records its handler with
+/// [`push_try`][Self::push_try], which is pushed by [`catch`][Self::catch] in place of the
+/// instructions left of the try when one of them fails. A function pushes itself with
+/// [`push_frame`][Self::push_frame] before its body together with the variables it saved, and is
+/// performed again to restore them once the body is done or
+/// [returns][Self::return_from_frame]. Loops outside of the innermost frame cannot be left from
+/// within it. Instructions taken from values are pushed with [`push_value`][Self::push_value], so
+/// that the instructions written as values in the source text can be located.
+#[derive(Debug, Default, Clone)]
+pub struct Stack {
+    entries: Vec<(Instruction, Path)>,
+    current: Path,
+    pushed: usize,
+    loops: Vec<ActiveLoop>,
+    /// Whether the instruction popped last was pushed by its loop.
+    is_iteration: bool,
+    handlers: Vec<Handler>,
+    frames: Vec<Frame>,
+    /// Variables to restore if the instruction popped last was pushed by its frame.
+    ended: Option<Vec<(variable::Id, Value)>>,
+    performed: Vec<Performed>,
+    /// Heap size of the instructions and values on the stack, kept up to date as they are pushed
+    /// and dropped so that it does not have to be measured after every instruction.
+    size: usize,
+    source_map: Option<Arc<SourceMap>>,
+}
+
+/// A loop on the stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct ActiveLoop {
+    /// Index of the entry of the loop instruction.
+    index: usize,
+    /// Number of entries left when continuing with the next iteration.
+    next: usize,
+}
+
+/// A try on the stack.
+#[derive(Debug, Clone, PartialEq)]
+struct Handler {
+    /// Number of entries when the try was performed, it is left once fewer are left.
+    depth: usize,
+    path: Path,
+    instr: Instruction,
+}
+
+/// A function called and not returned from yet.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Frame {
+    /// Index of the entry of the function.
+    index: usize,
+    /// Values of the variables of the caller.
+    saved: Vec<(variable::Id, Value)>,
+}
+
+/// An instruction written as a value that was pushed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Performed {
+    /// Index of the entry of the instruction, it is done once fewer entries are left.
+    index: usize,
+    origin: Origin,
+}
 
 impl Stack {
     #[must_use]
@@ -10,27 +85,588 @@ impl Stack {
     }
 
     pub fn clear(&mut self) -> &mut Self {
-        self.0.clear();
+        self.entries.clear();
+        self.loops.clear();
+        self.handlers.clear();
+        self.frames.clear();
+        self.performed.clear();
+        self.size = self.ended.as_deref().map_or(0, saved_size);
         self
     }
 
     pub fn push(&mut self, instr: impl Into<Instruction>) -> &mut Self {
-        self.0.push(instr.into());
+        let path = self.current.child(self.pushed);
+        self.pushed += 1;
+        self.push_entry(instr.into(), path);
+        self
+    }
+
+    fn push_entry(&mut self, instr: Instruction, path: Path) {
+        self.size += instr.heap_size();
+        self.entries.push((instr, path));
+    }
+
+    /// Push an instruction taken from a value. The instructions it pushes are located in the
+    /// source map by the instruction written as a value it equals, if there is one.
+    pub fn push_value(&mut self, instr: Instruction) -> &mut Self {
+        if let Some(value) = self.source_map.as_ref().and_then(|map| map.value(&instr)) {
+            self.performed.push(Performed {
+                index: self.entries.len(),
+                origin: Origin {
+                    value,
+                    depth: self.current.depth() + 1,
+                },
+            });
+        }
+        self.push(instr)
+    }
+
+    /// Drop the entries from `len` on.
+    fn truncate(&mut self, len: usize) {
+        if let Some(dropped) = self.entries.get(len..) {
+            self.size -= dropped
+                .iter()
+                .map(|(instr, _)| instr.heap_size())
+                .sum::<usize>();
+            self.entries.truncate(len);
+        }
+    }
+
+    /// Drop the handlers of the innermost tries that were left once `len` entries are left.
+    fn leave_tries(&mut self, len: usize) {
+        let left = self
+            .handlers
+            .iter()
+            .rposition(|handler| handler.depth <= len)
+            .map_or(0, |innermost| innermost + 1);
+        self.size -= self.handlers[left..]
+            .iter()
+            .map(|handler| handler.instr.heap_size())
+            .sum::<usize>();
+        self.handlers.truncate(left);
+    }
+
+    /// Push an instruction as the child with the index, whatever was pushed before it. Used for
+    /// instructions choosing one of their children, so each child keeps its own path.
+    pub fn push_child(&mut self, index: usize, instr: impl Into<Instruction>) -> &mut Self {
+        let path = self.current.child(index);
+        self.pushed = index + 1;
+        self.push_entry(instr.into(), path);
         self
     }
 
     pub fn extend(&mut self, instrs: impl IntoIterator<Item = Instruction>) -> &mut Self {
-        self.0.extend(instrs.into_iter());
+        for instr in instrs {
+            self.push(instr);
+        }
+        self
+    }
+
+    /// Push instructions such that they are performed in order, the first instruction is the
+    /// first child.
+    pub fn push_list(&mut self, instrs: Vec<Instruction>) -> &mut Self {
+        let first = self.pushed;
+        self.pushed += instrs.len();
+        for (index, instr) in (first..self.pushed).zip(instrs).rev() {
+            let path = self.current.child(index);
+            self.push_entry(instr, path);
+        }
         self
     }
 
     pub fn pop(&mut self) -> Option<Instruction> {
-        self.0.pop()
+        let (instr, path) = self.entries.pop()?;
+        self.size -= instr.heap_size();
+        self.current = path;
+        self.pushed = 0;
+        self.is_iteration = self
+            .loops
+            .last()
+            .is_some_and(|innermost| innermost.index == self.entries.len());
+        if self.is_iteration {
+            self.loops.pop();
+        }
+        self.size -= self.ended.take().as_deref().map_or(0, saved_size);
+        self.ended = self
+            .frames
+            .pop_if(|innermost| innermost.index == self.entries.len())
+            .map(|frame| frame.saved);
+        self.leave_tries(self.entries.len());
+        let done = self
+            .performed
+            .partition_point(|performed| performed.index <= self.entries.len());
+        self.performed.truncate(done);
+        Some(instr)
+    }
+
+    /// Push the instruction of a loop, it is performed with the same path once the instructions
+    /// pushed after it are done. `next` is pushed right after it and is performed before the next
+    /// iteration even when the current one is [continued][Self::continue_loop].
+    pub fn push_loop(
+        &mut self,
+        instr: impl Into<Instruction>,
+        next: Option<Instruction>,
+    ) -> &mut Self {
+        let index = self.entries.len();
+        self.push_entry(instr.into(), self.current.clone());
+        if let Some(next) = next {
+            self.push(next);
+        }
+        self.loops.push(ActiveLoop {
+            index,
+            next: self.entries.len(),
+        });
+        self
+    }
+
+    /// Whether the instruction popped last was pushed by [`push_loop`][Self::push_loop], it is
+    /// then performed for the next iteration of its loop.
+    #[must_use]
+    pub fn is_iteration(&self) -> bool {
+        self.is_iteration
+    }
+
+    /// Whether a loop is waiting for instructions on the stack within the innermost frame.
+    #[must_use]
+    pub fn in_loop(&self) -> bool {
+        self.innermost_loop().is_some()
+    }
+
+    /// Drop the instructions of the innermost loop together with the loop, returns whether there
+    /// was a loop within the innermost frame.
+    pub fn break_loop(&mut self) -> bool {
+        let Some(innermost) = self.innermost_loop() else {
+            return false;
+        };
+        self.truncate(innermost.index);
+        self.loops.pop();
+        true
+    }
+
+    /// Drop the instructions left of the current iteration of the innermost loop, returns whether
+    /// there was a loop within the innermost frame.
+    pub fn continue_loop(&mut self) -> bool {
+        let Some(innermost) = self.innermost_loop() else {
+            return false;
+        };
+        self.truncate(innermost.next);
+        true
+    }
+
+    fn innermost_loop(&self) -> Option<ActiveLoop> {
+        let innermost = *self.loops.last()?;
+        let frame = self.frames.last().map_or(0, |frame| frame.index + 1);
+        (innermost.index >= frame).then_some(innermost)
+    }
+
+    /// Push the instruction of a function, it is performed with the same path once the
+    /// instructions pushed after it are done and gets the variables it saved back from
+    /// [`ended_frame`][Self::ended_frame].
+    pub fn push_frame(
+        &mut self,
+        instr: impl Into<Instruction>,
+        saved: Vec<(variable::Id, Value)>,
+    ) -> &mut Self {
+        self.size += saved_size(&saved);
+        self.frames.push(Frame {
+            index: self.entries.len(),
+            saved,
+        });
+        self.push_entry(instr.into(), self.current.clone());
+        self
+    }
+
+    /// Variables saved by [`push_frame`][Self::push_frame] if the instruction popped last was
+    /// pushed by it, the function returns and restores them.
+    pub fn ended_frame(&mut self) -> Option<Vec<(variable::Id, Value)>> {
+        let saved = self.ended.take()?;
+        self.size -= saved_size(&saved);
+        Some(saved)
+    }
+
+    /// Whether a function is waiting for instructions on the stack.
+    #[must_use]
+    pub fn in_frame(&self) -> bool {
+        !self.frames.is_empty()
+    }
+
+    /// Drop the instructions left of the innermost frame, so that its function is performed
+    /// next. Returns whether there was a frame.
+    pub fn return_from_frame(&mut self) -> bool {
+        let Some(innermost) = self.frames.last() else {
+            return false;
+        };
+        let index = innermost.index;
+        self.truncate(index + 1);
+        self.loops.retain(|loop_| loop_.index < index);
+        self.leave_tries(index);
+        true
+    }
+
+    /// Drop the frames of the instructions from `index` on, returns the variables they saved in
+    /// the order they are to be [restored][variable::Map::restore].
+    fn leave_frames(&mut self, index: usize) -> Vec<(variable::Id, Value)> {
+        let first = self.frames.partition_point(|frame| frame.index < index);
+        let saved: Vec<_> = self
+            .frames
+            .drain(first..)
+            .flat_map(|frame| frame.saved)
+            .collect();
+        self.size -= saved_size(&saved);
+        saved
+    }
+
+    /// Push the body of a try as its first child, `handler` is pushed as its second child by
+    /// [`catch`][Self::catch] if the body or an instruction it pushes fails.
+    pub fn push_try(&mut self, body: impl Into<Instruction>, handler: Instruction) -> &mut Self {
+        self.size += handler.heap_size();
+        self.handlers.push(Handler {
+            depth: self.entries.len(),
+            path: self.current.clone(),
+            instr: handler,
+        });
+        self.push_child(0, body)
+    }
+
+    /// Drop the instructions left of the innermost try, together with the loops entered and the
+    /// functions called since, and push its handler. Returns the variables to restore for the
+    /// functions that were left if there was a try.
+    pub fn catch(&mut self) -> Option<Vec<(variable::Id, Value)>> {
+        // tries left by breaking out of a loop are only dropped by the next pop
+        self.leave_tries(self.entries.len());
+        let innermost = self.handlers.pop()?;
+        self.size -= innermost.instr.heap_size();
+        self.truncate(innermost.depth);
+        while self
+            .loops
+            .last()
+            .is_some_and(|loop_| loop_.index >= innermost.depth)
+        {
+            self.loops.pop();
+        }
+        let saved = self.leave_frames(innermost.depth);
+        self.set_path(innermost.path).push_child(1, innermost.instr);
+        Some(saved)
+    }
+
+    /// Drop all instructions, returns the variables to restore for the functions that were left.
+    pub fn unwind(&mut self) -> Vec<(variable::Id, Value)> {
+        let saved = self.leave_frames(0);
+        self.clear();
+        saved
+    }
+
+    /// Make the instructions pushed next children of `path`, as if the instruction at `path` was
+    /// popped last.
+    pub(crate) fn set_path(&mut self, path: Path) -> &mut Self {
+        self.current = path;
+        self.pushed = 0;
+        self
+    }
+
+    /// Path of the instruction popped last.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.current
+    }
+
+    #[must_use]
+    pub fn source_map(&self) -> Option<&Arc<SourceMap>> {
+        self.source_map.as_ref()
+    }
+
+    pub fn set_source_map(&mut self, source_map: Option<Arc<SourceMap>>) -> &mut Self {
+        self.source_map = source_map;
+        self
+    }
+
+    /// The instruction written as a value that the instruction popped last was performed from, if
+    /// it was.
+    #[must_use]
+    pub fn origin(&self) -> Option<Origin> {
+        self.performed.last().map(|performed| performed.origin)
+    }
+
+    /// Location of the instruction popped last.
+    #[must_use]
+    pub fn location(&self) -> Location {
+        Location::new(
+            self.current.clone(),
+            self.source_map.as_deref(),
+            self.origin(),
+        )
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Estimate of the number of bytes the pending instructions own on the heap, see
+    /// [`Value::heap_size`][crate::value::Value::heap_size]. The paths of the instructions are
+    /// children of ancestors of the path popped last, so they are counted as a node each.
+    #[must_use]
+    pub fn heap_size(&self) -> usize {
+        self.entries.capacity() * mem::size_of::<(Instruction, Path)>()
+            + self.loops.capacity() * mem::size_of::<ActiveLoop>()
+            + self.handlers.capacity() * mem::size_of::<Handler>()
+            + self.frames.capacity() * mem::size_of::<Frame>()
+            + self.performed.capacity() * mem::size_of::<Performed>()
+            + self.current.heap_size()
+            + self.entries.len() * location::NODE_SIZE
+            + self.size
+    }
+
+    /// Heap size of the instructions and values on the stack, measured instead of kept up to
+    /// date.
+    fn measure(&self) -> usize {
+        self.iter().map(Instruction::heap_size).sum::<usize>()
+            + self
+                .handlers
+                .iter()
+                .map(|handler| handler.instr.heap_size())
+                .sum::<usize>()
+            + self
+                .frames
+                .iter()
+                .map(|frame| saved_size(&frame.saved))
+                .sum::<usize>()
+            + self.ended.as_deref().map_or(0, saved_size)
+    }
+
+    /// Iterate over the pending instructions, starting with the one that will be performed next.
+    pub fn iter(&self) -> impl Iterator<Item = &Instruction> {
+        self.entries.iter().rev().map(|(instr, _)| instr)
+    }
+
+    /// Iterate over the pending instructions together with their paths, starting with the one
+    /// that will be performed next.
+    pub fn entries(&self) -> impl Iterator<Item = (&Instruction, &Path)> {
+        self.entries.iter().rev().map(|(instr, path)| (instr, path))
+    }
+
+    /// The instruction that will be performed next and its path.
+    #[must_use]
+    pub fn peek(&self) -> Option<(&Instruction, &Path)> {
+        self.entries().next()
+    }
+}
+
+/// Heap size of the variables saved by a frame.
+fn saved_size(saved: &[(variable::Id, Value)]) -> usize {
+    saved
+        .iter()
+        .map(|(_, value)| mem::size_of::<(variable::Id, Value)>() + value.heap_size())
+        .sum()
+}
+
+/// A [Stack] as it is serialized, paths are given by their number in a [`PathTable`].
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "Stack")]
+struct Serialized<'a> {
+    paths: Vec<(usize, usize)>,
+    entries: Vec<(Cow<'a, Instruction>, usize)>,
+    current: usize,
+    pushed: usize,
+    loops: Cow<'a, [ActiveLoop]>,
+    is_iteration: bool,
+    handlers: Vec<SerializedHandler<'a>>,
+    frames: Cow<'a, [Frame]>,
+    ended: Cow<'a, Option<Vec<(variable::Id, Value)>>>,
+    #[serde(default)]
+    performed: Cow<'a, [Performed]>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "Handler")]
+struct SerializedHandler<'a> {
+    depth: usize,
+    path: usize,
+    instr: Cow<'a, Instruction>,
+}
+
+impl Serialize for Stack {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut paths = PathTable::default();
+        let entries = self
+            .entries
+            .iter()
+            .map(|(instr, path)| (Cow::Borrowed(instr), paths.insert(path)))
+            .collect();
+        let handlers = self
+            .handlers
+            .iter()
+            .map(|handler| SerializedHandler {
+                depth: handler.depth,
+                path: paths.insert(&handler.path),
+                instr: Cow::Borrowed(&handler.instr),
+            })
+            .collect();
+        let current = paths.insert(&self.current);
+        Serialized {
+            paths: paths.into_nodes(),
+            entries,
+            current,
+            pushed: self.pushed,
+            loops: Cow::Borrowed(&self.loops),
+            is_iteration: self.is_iteration,
+            handlers,
+            frames: Cow::Borrowed(&self.frames),
+            ended: Cow::Borrowed(&self.ended),
+            performed: Cow::Borrowed(&self.performed),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Stack {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let serialized = Serialized::deserialize(deserializer)?;
+        let paths = PathTable::paths(&serialized.paths)
+            .ok_or_else(|| de::Error::custom("path node before its parent"))?;
+        let path = |number: usize| {
+            paths
+                .get(number)
+                .cloned()
+                .ok_or_else(|| de::Error::custom(format!("unknown path {number}")))
+        };
+        let mut stack = Self {
+            entries: serialized
+                .entries
+                .into_iter()
+                .map(|(instr, number)| Ok((instr.into_owned(), path(number)?)))
+                .collect::<Result<_, D::Error>>()?,
+            current: path(serialized.current)?,
+            pushed: serialized.pushed,
+            loops: serialized.loops.into_owned(),
+            is_iteration: serialized.is_iteration,
+            handlers: serialized
+                .handlers
+                .into_iter()
+                .map(|handler| {
+                    Ok(Handler {
+                        depth: handler.depth,
+                        path: path(handler.path)?,
+                        instr: handler.instr.into_owned(),
+                    })
+                })
+                .collect::<Result<_, D::Error>>()?,
+            frames: serialized.frames.into_owned(),
+            ended: serialized.ended.into_owned(),
+            performed: serialized.performed.into_owned(),
+            size: 0,
+            source_map: None,
+        };
+        stack.size = stack.measure();
+        Ok(stack)
+    }
+}
+
+// the source map only describes where instructions came from
+impl PartialEq for Stack {
+    fn eq(&self, other: &Self) -> bool {
+        self.entries == other.entries
+            && self.current == other.current
+            && self.pushed == other.pushed
+            && self.loops == other.loops
+            && self.handlers == other.handlers
+            && self.frames == other.frames
+    }
+}
+
+/// The instruction is given the root path.
+impl From<Instruction> for Stack {
+    fn from(value: Instruction) -> Self {
+        let mut stack = Self::new();
+        stack.push_entry(value, Path::root());
+        stack
     }
 }
 
 impl From<Vec<Instruction>> for Stack {
     fn from(value: Vec<Instruction>) -> Self {
-        Self(value)
+        let mut stack = Self::new();
+        stack.extend(value);
+        stack
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{instruction::DefaultLoader, program::Running};
+
+    #[test]
+    pub fn tracked_size() {
+        let program = crate::bml! {
+            rw n = 0;
+            rw i;
+            rw caught = 0;
+            ro count = instr function [n] [i] {
+                put 0;
+                assign i;
+                loop {
+                    take i;
+                    op add 1;
+                    assign i;
+                    clone i;
+                    op_clone eq n;
+                    if { clone i; return };
+                };
+            };
+            ro fail = instr function [n] [] { clone n; throw };
+            ro f = instr {
+                try { put [2]; call count; put [3]; call fail } catch {
+                    take caught;
+                    op add 1;
+                    assign caught;
+                    put [1];
+                    call count;
+                };
+            };
+            put 3;
+            assign i;
+            while { clone i; op gt 0 } {
+                take i;
+                op sub 1;
+                assign i;
+                clone f;
+                perform none;
+                clone i;
+                op eq 1;
+                if break;
+            };
+            put [4];
+            call fail;
+        };
+
+        let mut running = program.run(Value::None);
+        while let Running::Active { stack, .. } = &running {
+            assert_eq!(stack.size, stack.measure());
+            running.progress_in_place(&DefaultLoader);
+        }
+        let Running::Finished(Err(err)) = running else {
+            panic!("program did not fail");
+        };
+        assert_eq!(err.unlocated(), &crate::Error::Thrown(Value::Int(4)));
+    }
+
+    #[test]
+    pub fn path_size() {
+        // every instruction is pushed by the one popped before it, its path is one node longer
+        let mut stack = Stack::from(Instruction::Noop);
+        let size = stack.heap_size();
+        for _ in 0..1000 {
+            stack.pop();
+            stack.push(Instruction::Noop);
+        }
+        assert_eq!(stack.peek().map(|(_, path)| path.depth()), Some(1000));
+        // counted as the path popped last and one node for the instruction
+        assert_eq!(stack.heap_size(), size + 999 * location::NODE_SIZE);
     }
 }
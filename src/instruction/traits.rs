@@ -1,20 +1,12 @@
-use crate::{value::Value, variable, Result};
-use std::fmt;
+use crate::{value::Value, Result};
+use std::{fmt, future::Future, pin::Pin, time::Duration};
 
 type ExtraDebugFn = Box<dyn Fn(&mut fmt::Formatter<'_>) -> fmt::Result>;
 pub trait External {
-    fn perform(
-        &self,
-        return_value: Value,
-        variables: variable::Map,
-        instruction_stack: super::Stack,
-    ) -> Result<(Value, variable::Map, super::Stack)>;
-
-    fn perform_tup(
-        &self,
-        tup: (Value, variable::Map, super::Stack),
-    ) -> Result<(Value, variable::Map, super::Stack)> {
-        self.perform(tup.0, tup.1, tup.2)
+    fn perform(&self, return_value: Value, context: &mut super::Context<'_>) -> Result<Value>;
+
+    fn perform_tup(&self, tup: (Value, &mut super::Context<'_>)) -> Result<Value> {
+        self.perform(tup.0, tup.1)
     }
 
     fn extra_debug(&self) -> Option<ExtraDebugFn> {
@@ -22,6 +14,25 @@ pub trait External {
     }
 }
 
+/// [External] instruction that can be shared between threads. Instructions are stored in values,
+/// which programs running on several threads share, so only these can be put in an
+/// [External][super::External].
+pub trait SyncExternal: External + Send + Sync {}
+
+impl<T: External + Send + Sync + ?Sized> SyncExternal for T {}
+
 pub trait Loader {
     fn load(&self, value: Value) -> Result<Value>;
 }
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
+
+/// [Loader] for programs run asynchronously, loading is awaited without blocking the thread.
+pub trait AsyncLoader {
+    fn load(&self, value: Value) -> BoxFuture<'_, Result<Value>>;
+}
+
+/// Lets programs run asynchronously wait, provided by the async runtime of the host.
+pub trait Timer {
+    fn sleep(&self, duration: Duration) -> BoxFuture<'_, ()>;
+}
@@ -9,45 +9,65 @@
 )]
 #![allow(clippy::missing_errors_doc)]
 
+// lets macros refer to the crate by name in the crate itself
+extern crate self as bookmark_language;
+
 use derive_more::IsVariant;
 use instruction::Instruction;
-use std::result;
+use location::Location;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, result};
 use thiserror::Error;
 use value::{Operation, Value};
 
+pub use bookmark_language_macros::bml;
+
+pub mod asm;
+pub mod bytecode;
+pub mod debugger;
 pub mod instruction;
+pub mod limits;
+pub mod location;
+pub mod observer;
+pub mod profiler;
 pub mod program;
+pub mod script;
 pub mod variable;
 
 /// Result alias used in library.
 pub type Result<T> = result::Result<T, Error>;
 
 /// Error type in use by library.
-#[derive(Debug, Error, IsVariant, PartialEq, Clone)]
+#[derive(Debug, Error, IsVariant, PartialEq, Clone, Serialize, Deserialize)]
 pub enum Error {
     /// Used when an attempt is made to get access to a variable using an invalid id.
-    #[error("{0:?} is not the id of a variable in use")]
+    #[error("{0} is not the id of a variable in use")]
     UnknownVariable(variable::Id),
 
     /// Used when an an attempt is made to get read-write access to a read-only variable.
-    #[error("attempt to get a mutable reference to read only variable {0:?}")]
+    #[error("attempt to get a mutable reference to read only variable {0}")]
     WriteToReadOnly(variable::Id),
 
     /// Used when the [Perform][instruction::meta::Perform] instruction was used with an input that
     /// is not performable.
-    #[error("the Perform instruction was used when last return value was not an instruction")]
+    #[error("the perform instruction was used when last return value was {0}, not an instruction")]
     PerformOnNonInstruction(Value),
 
+    /// Used when [Break][instruction::meta::Break] or [Continue][instruction::meta::Continue] is
+    /// performed outside of a loop.
+    #[error("{0} was performed outside of a loop")]
+    OutsideOfLoop(Instruction),
+
     /// Used when an [operation][value::Operation] is applied to two incompatible values.
-    #[error("operation {0:?} is not supported for {1:?} and {2:?}")]
+    #[error("operation {0} is not supported for {1} and {2}")]
     UnsuppurtedOperation(Operation, Value, Value),
 
     /// Used when zero division is tried.
-    #[error("tried to divide {0:?} by {1:?} (Zero)")]
+    #[error("tried to divide {0} by {1} (zero)")]
     ZeroDiv(Value, Value),
 
     /// Used when an arithmetic operation under or overflow.
-    #[error("over/underflow occured on integer operation {op:?}, lhs = {lhs:?}, rhs = {rhs:?}")]
+    #[error("over/underflow occured on integer operation {op}, lhs = {lhs}, rhs = {rhs}")]
     IntegerOverOrUnderFlow {
         /// The operation that was tried.
         op: Operation,
@@ -58,39 +78,180 @@ pub enum Error {
     },
 
     /// Used when the [cast][instruction::pure::cast] instruction fails.
-    #[error("failed to cast {0:?} to {1:?}")]
+    #[error("failed to cast {0} to {1}")]
     FailedCast(Value, value::Type),
 
     /// Used when the [parse][instruction::pure::Parse] instruction fails.
-    #[error("failed to parse {1:?} into {0:?}")]
+    #[error("failed to parse {1} into {0}")]
     FailedParse(value::Type, Value),
 
     /// Used when the [cast][instruction::pure::cast] intructon is used to perform a cast that is
     /// not allowed.
-    #[error("cast {0:?} to {0:?} is invalid, value tried {2:?}")]
+    #[error("cast {0} to {1} is invalid, value tried {2}")]
     InvalidCast(value::Type, value::Type, Value),
 
     /// Used when the [parse][instruction::pure::Parse] instrution is used to try an parse to an
     /// uparseable type.
-    #[error("parse to {0:?} is invalid")]
+    #[error("parse to {0} is invalid")]
     InvalidParse(value::Type),
 
     /// Used when the [parse][instruction::pure::Parse] instruction is given an input that is not a
     /// string.
-    #[error("can only parse strings, value tried")]
+    #[error("can only parse strings, value tried {0}")]
     NonStringParse(Value),
 
-    #[error("{key:?} is not a key of {map:?}")]
+    #[error("{key} is not a key of {map}")]
     InvalidAcces { key: Value, map: Value },
 
-    #[error("{0:?} is the wrong type of key used for access to type {1:?}")]
+    #[error("{0} is the wrong type of key used for access to type {1}")]
     WrongKeyType(Value, value::Type),
 
-    #[error("{0:?} cannot be used for {1:?}")]
+    #[error("{0} cannot be used for {1}")]
     WrongInstructionInput(Value, Instruction),
 
-    #[error("{0:?} cannot be loaded using current loader")]
+    #[error("{0} cannot be loaded using current loader")]
     UnloadableValue(Value),
+
+    /// Used when the [throw][instruction::pure::Throw] instruction is performed, carries the
+    /// value it was performed with.
+    #[error("{0} was thrown")]
+    Thrown(Value),
+
+    /// Used when a program runs out of [fuel][limits::Fuel].
+    #[error("ran out of fuel")]
+    OutOfFuel,
+
+    /// Used when a program is still running at its [deadline][limits::Limits::deadline].
+    #[error("deadline exceeded")]
+    DeadlineExceeded,
+
+    /// Used when a program is stopped by a [cancellation][limits::Cancellation].
+    #[error("cancelled")]
+    Cancelled,
+
+    /// Used when the values of a program take up more than its [memory
+    /// limit][limits::Limits::memory].
+    #[error("memory limit exceeded")]
+    MemoryLimitExceeded,
+
+    /// Wraps errors with the location of the instruction that caused them.
+    #[error("{location}: {error}")]
+    Located {
+        location: Location,
+        error: Box<Error>,
+    },
+}
+
+impl Error {
+    #[must_use]
+    pub fn located(self, location: Location) -> Self {
+        Self::Located {
+            location,
+            error: Box::new(self),
+        }
+    }
+
+    /// Location of the instruction that caused the error, errors from nested programs are
+    /// located in the outermost program.
+    #[must_use]
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            Self::Located { location, .. } => Some(location),
+            _ => None,
+        }
+    }
+
+    /// Whether the error was caused by reaching one of the [limits][limits::Limits] rather than
+    /// by the program itself.
+    #[must_use]
+    pub fn is_limit(&self) -> bool {
+        matches!(
+            self.unlocated(),
+            Self::OutOfFuel | Self::DeadlineExceeded | Self::Cancelled | Self::MemoryLimitExceeded
+        )
+    }
+
+    /// The error with all locations removed.
+    #[must_use]
+    pub fn unlocated(&self) -> &Self {
+        let mut err = self;
+        while let Self::Located { error, .. } = err {
+            err = error;
+        }
+        err
+    }
+
+    /// Name of the variant of the error, in snake case. Located errors have the kind of the
+    /// error they wrap.
+    #[must_use]
+    pub fn kind(&self) -> &'static str {
+        match self.unlocated() {
+            Self::UnknownVariable(_) => "unknown_variable",
+            Self::WriteToReadOnly(_) => "write_to_read_only",
+            Self::PerformOnNonInstruction(_) => "perform_on_non_instruction",
+            Self::OutsideOfLoop(_) => "outside_of_loop",
+            Self::UnsuppurtedOperation(..) => "unsupported_operation",
+            Self::ZeroDiv(..) => "zero_div",
+            Self::IntegerOverOrUnderFlow { .. } => "integer_over_or_under_flow",
+            Self::FailedCast(..) => "failed_cast",
+            Self::FailedParse(..) => "failed_parse",
+            Self::InvalidCast(..) => "invalid_cast",
+            Self::InvalidParse(_) => "invalid_parse",
+            Self::NonStringParse(_) => "non_string_parse",
+            Self::InvalidAcces { .. } => "invalid_access",
+            Self::WrongKeyType(..) => "wrong_key_type",
+            Self::WrongInstructionInput(..) => "wrong_instruction_input",
+            Self::UnloadableValue(_) => "unloadable_value",
+            Self::Thrown(_) => "thrown",
+            Self::OutOfFuel => "out_of_fuel",
+            Self::DeadlineExceeded => "deadline_exceeded",
+            Self::Cancelled => "cancelled",
+            Self::MemoryLimitExceeded => "memory_limit_exceeded",
+            Self::Located { .. } => unreachable!("unlocated errors are not located"),
+        }
+    }
+
+    /// The values the error is about, types and instructions included.
+    fn into_values(self) -> Vec<Value> {
+        match self {
+            Self::UnknownVariable(id) | Self::WriteToReadOnly(id) => vec![Value::Id(id)],
+            Self::PerformOnNonInstruction(value)
+            | Self::NonStringParse(value)
+            | Self::UnloadableValue(value)
+            | Self::Thrown(value) => vec![value],
+            Self::OutsideOfLoop(instr) => vec![instr.into()],
+            Self::UnsuppurtedOperation(_, lhs, rhs)
+            | Self::ZeroDiv(lhs, rhs)
+            | Self::IntegerOverOrUnderFlow { lhs, rhs, .. } => vec![lhs, rhs],
+            Self::FailedCast(value, ty) | Self::WrongKeyType(value, ty) => {
+                vec![value, Value::Type(ty)]
+            }
+            Self::FailedParse(ty, value) => vec![Value::Type(ty), value],
+            Self::InvalidCast(from, to, value) => vec![Value::Type(from), Value::Type(to), value],
+            Self::InvalidParse(ty) => vec![Value::Type(ty)],
+            Self::InvalidAcces { key, map } => vec![key, map],
+            Self::WrongInstructionInput(value, instr) => vec![value, instr.into()],
+            Self::OutOfFuel
+            | Self::DeadlineExceeded
+            | Self::Cancelled
+            | Self::MemoryLimitExceeded => Vec::new(),
+            Self::Located { error, .. } => error.into_values(),
+        }
+    }
+}
+
+/// A map of the `kind` of the error, its `message` and the `values` it is about, as given to the
+/// handler of a [Try][instruction::meta::Try]. The location is left out of the message.
+impl From<Error> for Value {
+    fn from(value: Error) -> Self {
+        let kind = value.kind();
+        let message = value.unlocated().to_string();
+        Value::Map(BTreeMap::from([
+            ("kind".into(), kind.into()),
+            ("message".into(), message.into()),
+            ("values".into(), value.into_values().into()),
+        ]))
+    }
 }
 
 pub mod value;
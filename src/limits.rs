@@ -0,0 +1,417 @@
+//! Limits on how long a program may run.
+//!
+//! [Limits] are checked between instructions by
+//! [`Running::progress_with_limits`][crate::program::Running::progress_with_limits] and shared with
+//! the programs run by [`loading::Program`][crate::instruction::loading::Program] instructions.
+//! When a limit is reached the running program is left as it was before the step, so the host
+//! can decide to raise the limit and resume it or to drop it.
+
+use crate::{
+    instruction::traits::{BoxFuture, Timer},
+    Error, Result,
+};
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex, PoisonError},
+    task::{Context, Poll, Waker},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Everything that can stop a running program from the outside, by default nothing does.
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    pub fuel: Fuel,
+    /// Point in time after which no more instructions are performed.
+    pub deadline: Option<Instant>,
+    pub cancellation: Option<Cancellation>,
+    /// Number of bytes the variables, the instruction stack and the return value of a program
+    /// may take up on the heap, as estimated by their `heap_size` methods. The values of programs
+    /// waiting for a nested program to finish count towards the memory of the nested program.
+    pub memory: Option<usize>,
+}
+
+impl Limits {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn is_unlimited(&self) -> bool {
+        self.fuel.remaining().is_none()
+            && self.deadline.is_none()
+            && self.cancellation.is_none()
+            && self.memory.is_none()
+    }
+
+    /// Check the limits and use one unit of fuel.
+    pub(crate) fn step(&mut self) -> Result<()> {
+        if self
+            .cancellation
+            .as_ref()
+            .is_some_and(Cancellation::is_cancelled)
+        {
+            return Err(Error::Cancelled);
+        }
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Err(Error::DeadlineExceeded);
+        }
+        self.fuel.burn()
+    }
+
+    /// Check that values of the given heap size fit in the memory limit.
+    pub(crate) fn check_memory(&self, heap_size: usize) -> Result<()> {
+        match self.memory {
+            Some(memory) if heap_size > memory => Err(Error::MemoryLimitExceeded),
+            _ => Ok(()),
+        }
+    }
+
+    /// Block for `duration`, waking early if the program is cancelled or the deadline is reached.
+    pub(crate) fn sleep(&self, duration: Duration) -> Result<()> {
+        let (duration, result) = self.until_deadline(duration);
+        match &self.cancellation {
+            Some(cancellation) if cancellation.sleep(duration) => Err(Error::Cancelled),
+            Some(_) => result,
+            None => {
+                thread::sleep(duration);
+                result
+            }
+        }
+    }
+
+    /// Wait for `duration` using `timer`, waking early if the program is cancelled or the
+    /// deadline is reached.
+    pub(crate) async fn sleep_async(&self, duration: Duration, timer: &dyn Timer) -> Result<()> {
+        let (duration, result) = self.until_deadline(duration);
+        let sleep = timer.sleep(duration);
+        if let Some(cancellation) = &self.cancellation {
+            let is_cancelled = UntilCancelled {
+                sleep,
+                cancellation,
+            }
+            .await;
+            if is_cancelled {
+                return Err(Error::Cancelled);
+            }
+        } else {
+            sleep.await;
+        }
+        result
+    }
+
+    /// How long to sleep for `duration`, and the result of the sleep.
+    fn until_deadline(&self, duration: Duration) -> (Duration, Result<()>) {
+        let now = Instant::now();
+        let wake = now.checked_add(duration);
+        match self.deadline {
+            Some(deadline) if wake.is_none_or(|wake| wake > deadline) => (
+                deadline.saturating_duration_since(now),
+                Err(Error::DeadlineExceeded),
+            ),
+            _ => (duration, Ok(())),
+        }
+    }
+}
+
+/// Number of steps a running program may still take.
+///
+/// Every call to [`Running::progress_with_limits`][crate::program::Running::progress_with_limits] while the program is active is one step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Fuel(Option<u64>);
+
+impl Fuel {
+    #[must_use]
+    pub fn new(steps: u64) -> Self {
+        Self(Some(steps))
+    }
+
+    /// Fuel that never runs out, this is the default.
+    #[must_use]
+    pub fn unlimited() -> Self {
+        Self(None)
+    }
+
+    /// Steps left, `None` if the fuel is unlimited.
+    #[must_use]
+    pub fn remaining(&self) -> Option<u64> {
+        self.0
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0 == Some(0)
+    }
+
+    /// Add steps, unlimited fuel stays unlimited.
+    pub fn top_up(&mut self, steps: u64) -> &mut Self {
+        if let Some(remaining) = &mut self.0 {
+            *remaining = remaining.saturating_add(steps);
+        }
+        self
+    }
+
+    fn burn(&mut self) -> Result<()> {
+        match &mut self.0 {
+            Some(0) => Err(Error::OutOfFuel),
+            Some(remaining) => {
+                *remaining -= 1;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// Lets another thread stop running programs, clones share the same state.
+///
+/// Once cancelled, every program run with the cancellation stops before its next instruction and
+/// a [`Sleep`][crate::instruction::pure::Sleep] in progress wakes up, also when it is awaited.
+#[derive(Debug, Clone, Default)]
+pub struct Cancellation(Arc<(Mutex<CancellationState>, Condvar)>);
+
+#[derive(Debug, Default)]
+struct CancellationState {
+    is_cancelled: bool,
+    /// Tasks waiting in an awaited sleep.
+    wakers: Vec<Waker>,
+}
+
+impl Cancellation {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        let (state, condvar) = &*self.0;
+        let wakers = {
+            let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+            state.is_cancelled = true;
+            std::mem::take(&mut state.wakers)
+        };
+        condvar.notify_all();
+        wakers.into_iter().for_each(Waker::wake);
+    }
+
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        let (state, _) = &*self.0;
+        state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_cancelled
+    }
+
+    /// Block for `duration` or until cancelled, returns whether it was cancelled.
+    fn sleep(&self, duration: Duration) -> bool {
+        let (state, condvar) = &*self.0;
+        let guard = state.lock().unwrap_or_else(PoisonError::into_inner);
+        let (guard, _) = condvar
+            .wait_timeout_while(guard, duration, |state| !state.is_cancelled)
+            .unwrap_or_else(PoisonError::into_inner);
+        guard.is_cancelled
+    }
+
+    /// Ready once cancelled, the task is woken when [cancel][Self::cancel] is called.
+    fn poll_cancelled(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let (state, _) = &*self.0;
+        let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+        if state.is_cancelled {
+            return Poll::Ready(());
+        }
+        if !state.wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+            state.wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+/// Awaits a sleep until it is done or the cancellation is cancelled, whether it was cancelled is
+/// the output.
+struct UntilCancelled<'a> {
+    sleep: BoxFuture<'a, ()>,
+    cancellation: &'a Cancellation,
+}
+
+impl Future for UntilCancelled<'_> {
+    type Output = bool;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<bool> {
+        if self.cancellation.poll_cancelled(cx).is_ready() {
+            return Poll::Ready(true);
+        }
+        self.sleep.as_mut().poll(cx).map(|()| false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        instruction::{DefaultLoader, Instruction},
+        program::{Program, Running},
+        value::Value,
+        variable,
+    };
+
+    fn with_fuel(steps: u64) -> Limits {
+        Limits {
+            fuel: Fuel::new(steps),
+            ..Limits::new()
+        }
+    }
+
+    #[test]
+    pub fn fuel() {
+        let run = |program: Program, steps| {
+            let mut limits = with_fuel(steps);
+            program
+                .run_to_completion_with_limits(Value::None, &DefaultLoader, &mut limits)
+                .map(|value| (value, limits.fuel.remaining()))
+        };
+
+        // every instruction and the final check of the empty stack is a step
+        let program = crate::bml! { put 1; op add 1 };
+        assert_eq!(run(program.clone(), 3), Err(Error::OutOfFuel));
+        assert_eq!(run(program, 5), Ok((Value::Int(2), Some(1))));
+
+        let nested = crate::bml! { fallible; program { fallible; put 1; op add 1 } };
+        assert_eq!(run(nested.clone(), 5), Err(Error::OutOfFuel));
+        assert_eq!(run(nested, 6), Ok((Value::Int(2), Some(0))));
+    }
+
+    #[test]
+    pub fn resume() {
+        let program = crate::bml! {
+            rw n = 0;
+            ro l = instr { take n; op add 1; assign n; clone l; perform none };
+            clone l;
+            perform none;
+        };
+        let mut running = program.run(Value::None);
+        let mut limits = with_fuel(100);
+        assert_eq!(
+            running.resume(&DefaultLoader, &mut limits),
+            Err(Error::OutOfFuel)
+        );
+        assert!(matches!(running, Running::Active { .. }));
+
+        limits.fuel.top_up(100);
+        assert_eq!(
+            running.resume(&DefaultLoader, &mut limits),
+            Err(Error::OutOfFuel)
+        );
+        let Running::Active { variables, .. } = &running else {
+            panic!("program finished");
+        };
+        assert_eq!(
+            variables.iter().next(),
+            Some((variable::Id::rw(0), &Value::Int(33)))
+        );
+
+        // a nested program is retried until it gets enough fuel to finish
+        let mut running = crate::bml! { program { put 1; op add 1 } }.run(Value::None);
+        let mut limits = with_fuel(0);
+        let result = loop {
+            limits.fuel.top_up(1);
+            match running.resume(&DefaultLoader, &mut limits) {
+                Err(Error::OutOfFuel) => (),
+                result => break result,
+            }
+        };
+        assert_eq!(result, Ok(Value::Int(2)));
+    }
+
+    #[test]
+    pub fn memory() {
+        let limits = |memory| Limits {
+            memory: Some(memory),
+            ..Limits::new()
+        };
+        let doubling = crate::bml! {
+            rw s = "x";
+            ro l = instr { clone s; op_clone add s; assign s; clone l; perform none };
+            clone l;
+            perform none;
+        };
+        assert_eq!(
+            doubling.run_to_completion_with_limits(
+                Value::None,
+                &DefaultLoader,
+                &mut limits(10_000)
+            ),
+            Err(Error::MemoryLimitExceeded)
+        );
+
+        // the values of the outer program count towards the limit of the nested one
+        let nested = |outer: &str| {
+            let outer = Value::string(outer);
+            crate::bml! { rw s = (outer); program { put "nested" } }
+        };
+        let size = Value::string("nested").heap_size();
+        assert_eq!(
+            nested("").run_to_completion_with_limits(
+                Value::None,
+                &DefaultLoader,
+                &mut limits(1_000)
+            ),
+            Ok(Value::string("nested"))
+        );
+        assert_eq!(
+            nested(&"x".repeat(1_000 - size)).run_to_completion_with_limits(
+                Value::None,
+                &DefaultLoader,
+                &mut limits(1_000)
+            ),
+            Err(Error::MemoryLimitExceeded)
+        );
+    }
+
+    #[test]
+    pub fn deadline_and_cancellation() {
+        let forever = crate::bml! {
+            ro l = instr { clone l; perform none };
+            clone l;
+            perform none;
+        };
+        let mut limits = Limits {
+            deadline: Some(Instant::now() + Duration::from_millis(20)),
+            ..Limits::new()
+        };
+        assert_eq!(
+            forever.run_to_completion_with_limits(Value::None, &DefaultLoader, &mut limits),
+            Err(Error::DeadlineExceeded)
+        );
+
+        let cancellation = Cancellation::new();
+        let mut limits = Limits {
+            cancellation: Some(cancellation.clone()),
+            ..Limits::new()
+        };
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            cancellation.cancel();
+        });
+        let mut running = crate::bml! { fallible; put 60.0; sleep }.run(Value::None);
+        let result = running.resume(&DefaultLoader, &mut limits);
+        canceller.join().expect("cancelling thread");
+
+        assert_eq!(result, Err(Error::Cancelled));
+        let Running::Active {
+            stack,
+            return_value,
+            ..
+        } = running
+        else {
+            panic!("program finished");
+        };
+        assert_eq!(return_value, Value::Float(60.0));
+        assert_eq!(stack.iter().next().map(Instruction::name), Some("sleep"));
+    }
+}
@@ -0,0 +1,415 @@
+//! Locations of instructions, used to point out where a program failed.
+//!
+//! Every instruction on the [instruction stack][crate::instruction::Stack] has a [Path] describing
+//! where in the instruction tree it came from. The instruction of a program has the root path,
+//! the n:th instruction of a [list][crate::instruction::meta::List] is the n:th child of the list
+//! and instructions pushed by any other instruction are children of it in the order they were
+//! pushed. Programs parsed from text additionally carry a [`SourceMap`] from paths to the text
+//! the instructions were parsed from.
+
+use crate::{asm::Span, instruction::Instruction};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, mem,
+    sync::Arc,
+};
+
+/// Path of an instruction in the instruction tree.
+#[derive(Clone, Default)]
+pub struct Path(Option<Arc<Node>>);
+
+struct Node {
+    parent: Path,
+    index: usize,
+    depth: usize,
+}
+
+/// Number of bytes a node of a path takes up on the heap, together with its reference counts.
+pub(crate) const NODE_SIZE: usize = mem::size_of::<Node>() + 2 * mem::size_of::<usize>();
+
+impl Path {
+    #[must_use]
+    pub fn root() -> Self {
+        Self(None)
+    }
+
+    #[must_use]
+    pub fn child(&self, index: usize) -> Self {
+        Self(Some(Arc::new(Node {
+            parent: self.clone(),
+            index,
+            depth: self.depth() + 1,
+        })))
+    }
+
+    #[must_use]
+    pub fn is_root(&self) -> bool {
+        self.0.is_none()
+    }
+
+    #[must_use]
+    pub fn parent(&self) -> Option<&Path> {
+        self.0.as_ref().map(|node| &node.parent)
+    }
+
+    /// Index of the path among the children of its parent.
+    #[must_use]
+    pub fn index(&self) -> Option<usize> {
+        self.0.as_ref().map(|node| node.index)
+    }
+
+    /// Number of child indices from the root to this path.
+    #[must_use]
+    pub fn depth(&self) -> usize {
+        self.0.as_ref().map_or(0, |node| node.depth)
+    }
+
+    /// Estimate of the number of bytes the nodes of the path take up on the heap, most of them
+    /// are usually shared with other paths.
+    #[must_use]
+    pub fn heap_size(&self) -> usize {
+        self.depth() * NODE_SIZE
+    }
+
+    /// Child indices from the root to this path.
+    #[must_use]
+    pub fn indices(&self) -> Vec<usize> {
+        let mut indices = Vec::with_capacity(self.depth());
+        let mut path = self;
+        while let Some(node) = &path.0 {
+            indices.push(node.index);
+            path = &node.parent;
+        }
+        indices.reverse();
+        indices
+    }
+
+    /// Child indices from `ancestor` to this path, or [None] if `ancestor` is neither this path
+    /// nor one of its ancestors.
+    #[must_use]
+    pub fn indices_below(&self, ancestor: &Path) -> Option<Vec<usize>> {
+        let mut indices = Vec::new();
+        let mut path = self;
+        while path.depth() > ancestor.depth() {
+            let node = path.0.as_ref()?;
+            indices.push(node.index);
+            path = &node.parent;
+        }
+        if path != ancestor {
+            return None;
+        }
+        indices.reverse();
+        Some(indices)
+    }
+}
+
+impl From<&[usize]> for Path {
+    fn from(value: &[usize]) -> Self {
+        value
+            .iter()
+            .fold(Path::root(), |path, &index| path.child(index))
+    }
+}
+
+/// Serialized as its [indices][Path::indices].
+impl Serialize for Path {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.indices().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Path {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::<usize>::deserialize(deserializer).map(|indices| Path::from(indices.as_slice()))
+    }
+}
+
+/// Paths sharing their ancestors, like the paths of the instructions on a
+/// [stack][crate::instruction::Stack], numbered such that every node is serialized once as the
+/// number of its parent and its index. The root path is number 0.
+#[derive(Default)]
+pub(crate) struct PathTable {
+    nodes: Vec<(usize, usize)>,
+    numbers: HashMap<*const Node, usize>,
+}
+
+impl PathTable {
+    /// Number of `path`, its nodes that are not in the table yet are added.
+    pub(crate) fn insert(&mut self, path: &Path) -> usize {
+        let mut added = Vec::new();
+        let mut path = path;
+        let mut number = loop {
+            let Some(node) = &path.0 else {
+                break 0;
+            };
+            if let Some(&number) = self.numbers.get(&Arc::as_ptr(node)) {
+                break number;
+            }
+            added.push(node);
+            path = &node.parent;
+        };
+        for node in added.into_iter().rev() {
+            self.nodes.push((number, node.index));
+            number = self.nodes.len();
+            self.numbers.insert(Arc::as_ptr(node), number);
+        }
+        number
+    }
+
+    /// Parents and indices of the nodes, the node with number n is at n - 1.
+    pub(crate) fn into_nodes(self) -> Vec<(usize, usize)> {
+        self.nodes
+    }
+
+    /// Paths of the `nodes` of a table by their number, `None` if a node comes before its parent.
+    pub(crate) fn paths(nodes: &[(usize, usize)]) -> Option<Vec<Path>> {
+        let mut paths = vec![Path::root()];
+        for &(parent, index) in nodes {
+            let path = paths.get(parent)?.child(index);
+            paths.push(path);
+        }
+        Some(paths)
+    }
+}
+
+// Paths of instructions performed by loops grow with every iteration, comparing and dropping them
+// is done without recursion.
+impl PartialEq for Path {
+    fn eq(&self, other: &Self) -> bool {
+        let (mut lhs, mut rhs) = (self, other);
+        loop {
+            match (&lhs.0, &rhs.0) {
+                (None, None) => break true,
+                (Some(l), Some(r)) if Arc::ptr_eq(l, r) => break true,
+                (Some(l), Some(r)) if l.index == r.index => (lhs, rhs) = (&l.parent, &r.parent),
+                _ => break false,
+            }
+        }
+    }
+}
+
+impl Eq for Path {}
+
+impl Drop for Path {
+    fn drop(&mut self) {
+        let mut next = self.0.take();
+        while let Some(node) = next {
+            next = Arc::try_unwrap(node)
+                .ok()
+                .and_then(|mut node| node.parent.0.take());
+        }
+    }
+}
+
+impl fmt::Debug for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Path").field(&self.indices()).finish()
+    }
+}
+
+/// Indices separated by `.`, long paths have their middle left out.
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const SHOWN: usize = 4;
+
+        let indices = self.indices();
+        if indices.is_empty() {
+            return f.write_str("root");
+        }
+
+        let join = |indices: &[usize]| {
+            indices
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(".")
+        };
+        if indices.len() > SHOWN * 2 {
+            write!(
+                f,
+                "{}.(…).{}",
+                join(&indices[..SHOWN]),
+                join(&indices[indices.len() - SHOWN..])
+            )
+        } else {
+            f.write_str(&join(&indices))
+        }
+    }
+}
+
+/// Where in source text the instructions of a program were parsed from.
+///
+/// Instructions written as values are not part of the instruction tree, their spans are kept
+/// relative to the instruction they are part of. Instructions performed from a value equal to one
+/// of them are looked up there, given the [Origin] of the value.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMap {
+    file: Option<Arc<str>>,
+    spans: BTreeMap<Vec<usize>, Span>,
+    values: Vec<(Instruction, BTreeMap<Vec<usize>, Span>)>,
+}
+
+/// An instruction written as a value that instructions were performed from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Origin {
+    /// Number of the value in the [`SourceMap`].
+    pub value: usize,
+    /// Depth of the path the value was performed with, the paths of its instructions are relative
+    /// to it from there on.
+    pub depth: usize,
+}
+
+impl SourceMap {
+    #[must_use]
+    pub fn new(file: Option<Arc<str>>) -> Self {
+        Self {
+            file,
+            spans: BTreeMap::new(),
+            values: Vec::new(),
+        }
+    }
+
+    pub fn insert(&mut self, path: Vec<usize>, span: Span) {
+        self.spans.insert(path, span);
+    }
+
+    /// Add an instruction written as a value together with the spans of its instructions, by
+    /// their paths relative to it. Returns the number of the value.
+    pub fn insert_value(
+        &mut self,
+        instruction: Instruction,
+        spans: impl IntoIterator<Item = (Vec<usize>, Span)>,
+    ) -> usize {
+        self.values.push((instruction, spans.into_iter().collect()));
+        self.values.len() - 1
+    }
+
+    /// Number of the instruction written as a value that equals `instruction`, if there is one.
+    #[must_use]
+    pub fn value(&self, instruction: &Instruction) -> Option<usize> {
+        self.values
+            .iter()
+            .position(|(value, _)| value == instruction)
+    }
+
+    #[must_use]
+    pub fn file(&self) -> Option<&Arc<str>> {
+        self.file.as_ref()
+    }
+
+    /// Span of the instruction at `path`, or of the closest ancestor that has one. Instructions
+    /// performed from a value are looked up in the value of `origin` if there is one, and
+    /// otherwise get the span of the instruction that performed them.
+    #[must_use]
+    pub fn span(&self, path: &Path, origin: Option<Origin>) -> Option<Span> {
+        let indices = path.indices();
+        let closest = |spans: &BTreeMap<Vec<usize>, Span>, indices: &[usize]| {
+            (0..=indices.len())
+                .rev()
+                .find_map(|len| spans.get(&indices[..len]).copied())
+        };
+        origin
+            .and_then(|origin| {
+                let (_, spans) = self.values.get(origin.value)?;
+                closest(spans, indices.get(origin.depth..)?)
+            })
+            .or_else(|| closest(&self.spans, &indices))
+    }
+}
+
+/// Location of an instruction.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Location {
+    pub path: Path,
+    pub file: Option<Arc<str>>,
+    pub span: Option<Span>,
+}
+
+impl Location {
+    /// Location of the instruction at `path`, looked up in `source_map` if there is one, see
+    /// [`SourceMap::span`].
+    #[must_use]
+    pub fn new(path: Path, source_map: Option<&SourceMap>, origin: Option<Origin>) -> Self {
+        Self {
+            file: source_map.and_then(|map| map.file.clone()),
+            span: source_map.and_then(|map| map.span(&path, origin)),
+            path,
+        }
+    }
+}
+
+impl From<Path> for Location {
+    fn from(path: Path) -> Self {
+        Self {
+            path,
+            file: None,
+            span: None,
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.file, &self.span) {
+            (Some(file), Some(span)) => write!(f, "{file}:{} ({})", span.start, self.path),
+            (None, Some(span)) => write!(f, "{} ({})", span.start, self.path),
+            (_, None) => write!(f, "instruction {}", self.path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm, instruction::DefaultLoader, value::Value};
+
+    #[test]
+    pub fn located_errors() -> Result<(), asm::ParseError> {
+        let src = "rw a = 0\n\nput 1\n{\n    debug\n    put 1\n    op_clone div a\n}\n";
+        let err = asm::parse_named(src, "div.bml")?
+            .run_to_completion(Value::None, &DefaultLoader)
+            .map_err(|err| err.to_string());
+
+        assert_eq!(
+            err,
+            Err("div.bml:7:5 (1.2): tried to divide 1 by 0 (zero)".to_owned())
+        );
+        Ok(())
+    }
+
+    #[test]
+    pub fn located_values() -> Result<(), asm::ParseError> {
+        let src = "ro l = instr {\n    put 4\n    op div 0\n}\n\nclone l\nperform none\n";
+        let program = asm::parse_named(src, "div.bml")?;
+        let expected = Err("div.bml:3:5 (1.1.1): tried to divide 4 by 0 (zero)".to_owned());
+
+        let err = program
+            .compile()
+            .run_to_completion(Value::None, &DefaultLoader)
+            .map_err(|err| err.to_string());
+        assert_eq!(err, expected);
+        let err = program
+            .run_to_completion(Value::None, &DefaultLoader)
+            .map_err(|err| err.to_string());
+        assert_eq!(err, expected);
+        Ok(())
+    }
+
+    #[test]
+    pub fn paths() {
+        let path = Path::from(&[0, 1, 2, 1, 2, 1, 2, 1, 2, 3][..]);
+        assert_eq!(
+            path.parent(),
+            Some(&Path::from(&[0, 1, 2, 1, 2, 1, 2, 1, 2][..]))
+        );
+        assert_eq!(path.depth(), 10);
+        assert_eq!(
+            path.indices_below(&Path::from(&[0, 1, 2, 1, 2, 1, 2][..])),
+            Some(vec![1, 2, 3])
+        );
+        assert_eq!(path.indices_below(&Path::from(&[0, 1, 3][..])), None);
+        assert_eq!(path.to_string(), "0.1.2.1.(…).2.1.2.3");
+        assert_eq!(Path::root().to_string(), "root");
+    }
+}
@@ -0,0 +1,207 @@
+//! Watching programs while they run.
+//!
+//! An [Observer] is called before and after every instruction performed by
+//! [`Running::progress_observed`][crate::program::Running::progress_observed], including the
+//! instructions of the programs run by [`loading::Program`][crate::instruction::loading::Program]
+//! instructions. [`JsonTrace`] writes every step as a line of JSON.
+
+use crate::{instruction::Instruction, location::Location, value::Value, variable, Result};
+use serde::Serialize;
+use std::io::{self, Write};
+
+/// An instruction about to be performed.
+#[derive(Debug, Clone, Copy)]
+pub struct Step<'a> {
+    pub instruction: &'a Instruction,
+    pub location: &'a Location,
+    /// Return value of the previous instruction.
+    pub input: &'a Value,
+}
+
+/// Called around every instruction, by default nothing is done.
+pub trait Observer {
+    fn before(&mut self, _step: &Step<'_>, _variables: &variable::Map) {}
+
+    /// Called with the return value of the instruction or the error it failed with.
+    fn after(&mut self, _step: &Step<'_>, _output: &Result<Value>, _variables: &variable::Map) {}
+}
+
+/// Observes with both observers, the calls to the second one are nested in the calls to the first
+/// one. Before an instruction the first one is called first, after it the second one is.
+impl<A: Observer, B: Observer> Observer for (A, B) {
+    fn before(&mut self, step: &Step<'_>, variables: &variable::Map) {
+        self.0.before(step, variables);
+        self.1.before(step, variables);
+    }
+
+    fn after(&mut self, step: &Step<'_>, output: &Result<Value>, variables: &variable::Map) {
+        self.1.after(step, output, variables);
+        self.0.after(step, output, variables);
+    }
+}
+
+/// Observes with the observer if there is one.
+impl<O: Observer> Observer for Option<O> {
+    fn before(&mut self, step: &Step<'_>, variables: &variable::Map) {
+        if let Some(observer) = self {
+            observer.before(step, variables);
+        }
+    }
+
+    fn after(&mut self, step: &Step<'_>, output: &Result<Value>, variables: &variable::Map) {
+        if let Some(observer) = self {
+            observer.after(step, output, variables);
+        }
+    }
+}
+
+/// Writes a line of JSON for every performed instruction.
+///
+/// Each line is an object with the `depth` of nested programs, the `location` and the name of
+/// the `instruction`, its `input`, its `output` or `error` and the read-write `variables` after
+/// it was performed. Values that cannot be serialized, like external instructions, are written
+/// as text.
+#[derive(Debug)]
+pub struct JsonTrace<W: Write> {
+    writer: W,
+    depth: usize,
+    error: Option<io::Error>,
+}
+
+#[derive(Serialize)]
+struct Entry {
+    depth: usize,
+    location: String,
+    instruction: &'static str,
+    input: serde_json::Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    variables: Vec<serde_json::Value>,
+}
+
+impl<W: Write> JsonTrace<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            depth: 0,
+            error: None,
+        }
+    }
+
+    /// Flush the trace and give back the writer.
+    ///
+    /// # Errors
+    /// The first error that occurred while writing, nothing is written after it.
+    pub fn finish(mut self) -> io::Result<W> {
+        match self.error {
+            Some(err) => Err(err),
+            None => self.writer.flush().map(|()| self.writer),
+        }
+    }
+
+    fn write(&mut self, entry: &Entry) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, entry)?;
+        self.writer.write_all(b"\n")
+    }
+}
+
+fn json(value: &Value) -> serde_json::Value {
+    serde_json::to_value(value).unwrap_or_else(|_| value.to_string().into())
+}
+
+impl<W: Write> Observer for JsonTrace<W> {
+    fn before(&mut self, _step: &Step<'_>, _variables: &variable::Map) {
+        self.depth += 1;
+    }
+
+    fn after(&mut self, step: &Step<'_>, output: &Result<Value>, variables: &variable::Map) {
+        self.depth = self.depth.saturating_sub(1);
+        if self.error.is_some() {
+            return;
+        }
+
+        let entry = Entry {
+            depth: self.depth,
+            location: step.location.to_string(),
+            instruction: step.instruction.name(),
+            input: json(step.input),
+            output: output.as_ref().ok().map(json),
+            error: output.as_ref().err().map(ToString::to_string),
+            variables: variables
+                .iter()
+                .filter(|(id, _)| !id.is_read_only())
+                .map(|(_, value)| json(value))
+                .collect(),
+        };
+        if let Err(err) = self.write(&entry) {
+            self.error = Some(err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{instruction::DefaultLoader, limits::Limits, Error};
+
+    #[test]
+    pub fn json_trace() -> io::Result<()> {
+        let program = crate::bml! {
+            rw n = 1;
+            take n;
+            op add 1;
+            assign n;
+            program { put "nested" };
+            clone n;
+            op div 0;
+        };
+        let mut trace = JsonTrace::new(Vec::new());
+        let result = program.run_to_completion_observed(
+            Value::None,
+            &DefaultLoader,
+            &mut Limits::new(),
+            &mut trace,
+        );
+        assert_eq!(
+            result.map_err(|err| err.unlocated().clone()),
+            Err(Error::ZeroDiv(Value::Int(2), Value::Int(0)))
+        );
+
+        let trace = trace.finish()?;
+        let lines = serde_json::Deserializer::from_slice(&trace)
+            .into_iter::<serde_json::Value>()
+            .collect::<serde_json::Result<Vec<_>>>()?;
+        let steps: Vec<_> = lines
+            .iter()
+            .map(|line| (line["depth"].as_u64(), line["instruction"].as_str()))
+            .collect();
+        assert_eq!(
+            steps,
+            [
+                (Some(0), Some("list")),
+                (Some(0), Some("take")),
+                (Some(0), Some("op")),
+                (Some(0), Some("assign")),
+                (Some(1), Some("put")),
+                (Some(0), Some("program")),
+                (Some(0), Some("clone")),
+                (Some(0), Some("op")),
+            ]
+        );
+        assert_eq!(
+            lines[2],
+            serde_json::json!({
+                "depth": 0,
+                "location": "instruction 1",
+                "instruction": "op",
+                "input": { "Int": 1 },
+                "output": { "Int": 2 },
+                "variables": ["None"],
+            })
+        );
+        assert_eq!(lines[7]["error"], "tried to divide 2 by 0 (zero)");
+        Ok(())
+    }
+}
@@ -0,0 +1,378 @@
+//! Measuring where running programs spend their time.
+//!
+//! A [Profiler] is an [Observer] that counts and times every performed instruction, both by
+//! [name][crate::instruction::Instruction::name] and by [Site]. The time of an instruction includes the nested
+//! program of a [`loading::Program`][crate::instruction::loading::Program] and the call to
+//! [`Loader::load`][crate::instruction::traits::Loader::load] of a
+//! [`loading::Load`][crate::instruction::loading::Load]. The resulting [Profile] is displayed as
+//! a summary table, or written as folded stacks for flamegraph tools with
+//! [`Profile::write_folded`].
+
+use crate::{
+    instruction::{Instruction, Meta},
+    location::Path,
+    observer::{Observer, Step},
+    value::Value,
+    variable, Result,
+};
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    fmt::{self, Write as _},
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+/// Number of the slowest sites shown in the summary table.
+const SUMMARY_SITES: usize = 20;
+
+/// Where an instruction was performed: the [path][Path] indices of the instruction in the program
+/// it ran in, preceded by the path of every instruction running the nested programs it is in.
+///
+/// A value performed again from the same path within the value that performed it, like a value
+/// performing itself, has its instructions at the paths they had the first time. Sites then do not
+/// grow with every iteration of a loop written that way.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Site(pub Vec<Vec<usize>>);
+
+/// Paths separated by `/`, the instructions of a program have the path `root`.
+impl fmt::Display for Site {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, path) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str("/")?;
+            }
+            write_path(f, path)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_path(f: &mut impl fmt::Write, path: &[usize]) -> fmt::Result {
+    if path.is_empty() {
+        return f.write_str("root");
+    }
+    for (i, index) in path.iter().enumerate() {
+        if i > 0 {
+            f.write_char('.')?;
+        }
+        write!(f, "{index}")?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Stats {
+    /// Number of times the instructions were performed.
+    pub count: u64,
+    /// Time spent performing the instructions.
+    pub time: Duration,
+}
+
+impl Stats {
+    fn add(&mut self, time: Duration) {
+        self.count += 1;
+        self.time += time;
+    }
+}
+
+/// Instruction performed at a site.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SiteStats {
+    pub name: &'static str,
+    pub stats: Stats,
+}
+
+/// Measurements of a [Profiler].
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Profile {
+    pub kinds: BTreeMap<&'static str, Stats>,
+    pub sites: BTreeMap<Site, SiteStats>,
+}
+
+impl Profile {
+    /// Write one line per site in the folded stack format, the frames of a site are the
+    /// instructions that pushed it, and the instructions running the nested programs it is in.
+    /// The count of a line is the time in nanoseconds spent in the site itself, without the time
+    /// spent in the nested program it ran.
+    pub fn write_folded(&self, mut writer: impl Write) -> io::Result<()> {
+        let mut nested = BTreeMap::<&[Vec<usize>], Duration>::new();
+        for (Site(paths), site) in &self.sites {
+            if let Some((_, outer)) = paths.split_last() {
+                if !outer.is_empty() {
+                    *nested.entry(outer).or_default() += site.stats.time;
+                }
+            }
+        }
+
+        for (Site(paths), site) in &self.sites {
+            let time = site
+                .stats
+                .time
+                .saturating_sub(nested.get(paths.as_slice()).copied().unwrap_or_default());
+            let mut frames = String::new();
+            for level in 0..paths.len() {
+                for len in 0..=paths[level].len() {
+                    let mut frame = paths[..level].to_vec();
+                    frame.push(paths[level][..len].to_vec());
+                    let Some(ancestor) = self.sites.get(&Site(frame)) else {
+                        continue;
+                    };
+                    if !frames.is_empty() {
+                        frames.push(';');
+                    }
+                    // writing to a string cannot fail
+                    let _ = write!(frames, "{}@", ancestor.name)
+                        .and_then(|()| write_path(&mut frames, &paths[level][..len]));
+                }
+            }
+            writeln!(writer, "{frames} {}", time.as_nanos())?;
+        }
+        Ok(())
+    }
+}
+
+/// Instruction kinds and the slowest sites, ordered by the time spent in them.
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut kinds: Vec<_> = self.kinds.iter().collect();
+        kinds.sort_by_key(|(_, stats)| Reverse(stats.time));
+        writeln!(
+            f,
+            "{:<16} {:>10} {:>14} {:>12}",
+            "instruction", "count", "total", "mean"
+        )?;
+        for (name, stats) in kinds {
+            write_stats(f, name, stats)?;
+            writeln!(f)?;
+        }
+
+        let mut sites: Vec<_> = self.sites.iter().collect();
+        sites.sort_by_key(|(_, site)| Reverse(site.stats.time));
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:<16} {:>10} {:>14} {:>12}  site",
+            "instruction", "count", "total", "mean"
+        )?;
+        for (site, stats) in sites.into_iter().take(SUMMARY_SITES) {
+            write_stats(f, stats.name, &stats.stats)?;
+            writeln!(f, "  {site}")?;
+        }
+        Ok(())
+    }
+}
+
+fn write_stats(f: &mut fmt::Formatter<'_>, name: &str, stats: &Stats) -> fmt::Result {
+    let mean = u32::try_from(stats.count)
+        .ok()
+        .filter(|count| *count > 0)
+        .map_or(Duration::ZERO, |count| stats.time / count);
+    write!(
+        f,
+        "{name:<16} {:>10} {:>14} {:>12}",
+        stats.count,
+        format!("{:?}", stats.time),
+        format!("{mean:?}")
+    )
+}
+
+/// Records a [Profile] of the programs it observes.
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    profile: Profile,
+    pending: Vec<(Site, Instant)>,
+    /// Values performed and not done yet, in the outermost program and in every nested program
+    /// that is running.
+    performed: Vec<Vec<Performed>>,
+}
+
+/// An instruction performed from a value.
+#[derive(Debug, Clone)]
+struct Performed {
+    /// Path of the instruction, the instructions it pushes are below it.
+    root: Path,
+    /// Path of the instruction in its [Site].
+    site: Vec<usize>,
+    /// Path of the instruction that performed it, relative to the value that one was performed
+    /// from.
+    performer: Vec<usize>,
+}
+
+/// Index of the child an instruction that performs a value pushes the value as.
+fn value_child(instruction: &Instruction) -> Option<usize> {
+    match instruction {
+        Instruction::Meta(Meta::Perform(_) | Meta::PerformClone(_) | Meta::PerformTake(_)) => {
+            Some(1)
+        }
+        Instruction::Meta(Meta::Call(_)) => Some(0),
+        _ => None,
+    }
+}
+
+impl Profiler {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    #[must_use]
+    pub fn into_profile(self) -> Profile {
+        self.profile
+    }
+}
+
+impl Observer for Profiler {
+    fn before(&mut self, step: &Step<'_>, _variables: &variable::Map) {
+        // only an instruction running a nested program is still pending
+        let mut site = self
+            .pending
+            .last()
+            .map(|(site, _)| site.clone())
+            .unwrap_or_default();
+        self.performed.resize_with(self.pending.len() + 1, Vec::new);
+        let performed = &mut self.performed[self.pending.len()];
+
+        // a value is done once an instruction that it did not push is performed
+        let path = &step.location.path;
+        let (relative, mut position) = loop {
+            let Some(innermost) = performed.last() else {
+                break (path.indices(), Vec::new());
+            };
+            if let Some(relative) = path.indices_below(&innermost.root) {
+                break (relative, innermost.site.clone());
+            }
+            performed.pop();
+        };
+        position.extend_from_slice(&relative);
+
+        if let Some(index) = value_child(step.instruction) {
+            let root = path.child(index);
+            if let Some(first) = performed
+                .iter()
+                .position(|value| value.performer == relative)
+            {
+                performed.truncate(first + 1);
+                performed[first].root = root;
+            } else {
+                let mut site = position.clone();
+                site.push(index);
+                performed.push(Performed {
+                    root,
+                    site,
+                    performer: relative,
+                });
+            }
+        }
+
+        site.0.push(position);
+        self.pending.push((site, Instant::now()));
+    }
+
+    fn after(&mut self, step: &Step<'_>, _output: &Result<Value>, _variables: &variable::Map) {
+        let Some((site, start)) = self.pending.pop() else {
+            return;
+        };
+        let time = start.elapsed();
+        let name = step.instruction.name();
+        self.profile.kinds.entry(name).or_default().add(time);
+        self.profile
+            .sites
+            .entry(site)
+            .or_insert(SiteStats {
+                name,
+                stats: Stats::default(),
+            })
+            .stats
+            .add(time);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        instruction::DefaultLoader,
+        limits::{Fuel, Limits},
+    };
+
+    #[test]
+    pub fn profile() -> io::Result<()> {
+        let program = crate::bml! { put 1; op add 1; program { put 2; op add 1 } };
+        let mut profiler = Profiler::new();
+        let result = program.run_to_completion_observed(
+            Value::None,
+            &DefaultLoader,
+            &mut Limits::new(),
+            &mut profiler,
+        );
+        assert_eq!(result, Ok(Value::Int(3)));
+
+        let profile = profiler.into_profile();
+        let counts: Vec<_> = profile
+            .kinds
+            .iter()
+            .map(|(name, stats)| (*name, stats.count))
+            .collect();
+        assert_eq!(counts, [("list", 2), ("op", 2), ("program", 1), ("put", 2)]);
+        let program = &profile.sites[&Site(vec![vec![2]])];
+        assert_eq!(program.name, "program");
+        assert!(program.stats.time >= profile.sites[&Site(vec![vec![2], vec![1]])].stats.time);
+
+        let mut folded = Vec::new();
+        profile.write_folded(&mut folded)?;
+        let stacks: Vec<_> = String::from_utf8_lossy(&folded)
+            .lines()
+            .filter_map(|line| line.rsplit_once(' '))
+            .map(|(stack, _)| stack.to_owned())
+            .collect();
+        assert_eq!(
+            stacks,
+            [
+                "list@root",
+                "list@root;put@0",
+                "list@root;op@1",
+                "list@root;program@2",
+                "list@root;program@2;list@root",
+                "list@root;program@2;list@root;put@0",
+                "list@root;program@2;list@root;op@1",
+            ]
+        );
+        assert!(profile.to_string().starts_with("instruction"));
+        Ok(())
+    }
+
+    #[test]
+    pub fn recursion() -> io::Result<()> {
+        let program = crate::bml! {
+            ro l = instr { put 1; clone l; perform none };
+            clone l;
+            perform none;
+        };
+        let mut limits = Limits {
+            fuel: Fuel::new(3000),
+            ..Limits::new()
+        };
+        let mut profiler = Profiler::new();
+        let result = program.run_to_completion_observed(
+            Value::None,
+            &DefaultLoader,
+            &mut limits,
+            &mut profiler,
+        );
+        assert_eq!(result, Err(crate::Error::OutOfFuel));
+
+        let profile = profiler.into_profile();
+        assert!(profile.kinds["perform"].count > 500);
+        assert!(profile.sites.len() < 20);
+        let mut folded = Vec::new();
+        profile.write_folded(&mut folded)?;
+        assert!(folded.len() < 1000);
+        Ok(())
+    }
+}
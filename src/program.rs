@@ -1,21 +1,47 @@
 use crate::{
-    instruction::{self, traits::Loader, External, Instruction, IntoInstruction},
+    instruction::{self, traits::Loader, Context, External, Instruction, IntoInstruction, Pure},
+    limits::{Fuel, Limits},
+    location::{Location, SourceMap},
+    observer::{Observer, Step},
     value::Value,
     variable, Result,
 };
 use serde::{Deserialize, Serialize};
-use std::mem;
+use std::{mem, sync::Arc};
 
-#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+mod asynchronous;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct Program {
     variables: variable::Map,
     instruction: Instruction,
     is_fallible: bool,
+    #[serde(skip)]
+    source_map: Option<Arc<SourceMap>>,
+}
+
+// the source map only describes where instructions came from
+impl PartialEq for Program {
+    fn eq(&self, other: &Self) -> bool {
+        self.variables == other.variables
+            && self.instruction == other.instruction
+            && self.is_fallible == other.is_fallible
+    }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// State of a running program.
+///
+/// The state can be serialized to continue running the program later, unless an
+/// [External][crate::instruction::External] instruction is pending or stored in a value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Running {
-    Active(variable::Map, instruction::Stack, Value),
+    Active {
+        variables: variable::Map,
+        /// Instructions left to perform.
+        stack: instruction::Stack,
+        /// Return value of the instruction performed last.
+        return_value: Value,
+    },
     Finished(Result<Value>),
 }
 
@@ -26,78 +52,281 @@ impl Default for Running {
 }
 
 impl Running {
-    fn handle_instruction(
+    pub(crate) fn handle_instruction(
         instr: Instruction,
         value: Value,
-        variables: &mut variable::Map,
-        stack: &mut instruction::Stack,
-        loader: &dyn Loader,
+        context: &mut Context<'_>,
     ) -> Result<Value> {
-        let mut return_value = Value::None;
         match instr {
-            Instruction::Noop => (),
-            Instruction::Pure(instr) => return_value = instr.perform(value)?,
-            Instruction::Reading(instr) => return_value = instr.perform(value, variables)?,
-            Instruction::Mutating(instr) => {
-                (return_value, *variables) = instr.perform(value, mem::take(variables))?;
-            }
-            Instruction::Meta(instr) => {
-                (return_value, *variables, *stack) =
-                    instr.perform(value, mem::take(variables), mem::take(stack))?;
-            }
-            Instruction::Loading(instr) => return_value = instr.perform(value, loader)?,
-            Instruction::External(External(instr)) => {
-                (return_value, *variables, *stack) =
-                    instr.perform(value, mem::take(variables), mem::take(stack))?;
+            Instruction::Noop => Ok(Value::None),
+            // the only instruction that blocks, it has to wake up when a limit is reached
+            Instruction::Pure(Pure::Sleep(instr)) => instr.perform_within(value, context.limits),
+            Instruction::Pure(instr) => instr.perform(value),
+            Instruction::Reading(instr) => instr.perform(value, context.variables),
+            Instruction::Mutating(instr) => instr.perform(value, context.variables),
+            Instruction::Meta(instr) => instr.perform(value, context),
+            Instruction::Loading(instr) => {
+                let memory = context.limits.memory;
+                if let Some(memory) = &mut context.limits.memory {
+                    *memory = memory
+                        .saturating_sub(context.variables.heap_size() + context.stack.heap_size());
+                }
+                let result = instr.perform(value, context);
+                context.limits.memory = memory;
+                result
             }
+            Instruction::External(External(instr)) => instr.perform(value, context),
         }
-        Ok(return_value)
     }
 
     #[must_use]
-    pub fn progress(self, loader: &dyn Loader) -> Self {
-        if let Self::Active(mut variables, mut stack, value) = self {
-            let Some(instr) = stack.pop() else {
-                return Self::Finished(Ok(value));
+    pub fn progress(mut self, loader: &dyn Loader) -> Self {
+        self.perform_next(loader, &mut Limits::new(), None);
+        self
+    }
+
+    /// Perform the next instruction in place, finishing the program if there is none or if it
+    /// fails outside of a [try][crate::instruction::meta::Try].
+    fn perform_next(
+        &mut self,
+        loader: &dyn Loader,
+        limits: &mut Limits,
+        observer: Option<&mut dyn Observer>,
+    ) {
+        let Self::Active {
+            variables,
+            stack,
+            return_value,
+        } = self
+        else {
+            return;
+        };
+        let Some(instr) = stack.pop() else {
+            *self = Self::Finished(Ok(mem::take(return_value)));
+            return;
+        };
+        let value = mem::take(return_value);
+        let path = stack.path().clone();
+        let origin = stack.origin();
+
+        // the instruction and its input are consumed, they are only kept for an observer
+        let mut watched = observer.map(|observer| {
+            let location =
+                Location::new(path.clone(), stack.source_map().map(AsRef::as_ref), origin);
+            let step = Step {
+                instruction: &instr,
+                location: &location,
+                input: &value,
+            };
+            observer.before(&step, variables);
+            (observer, instr.clone(), location, value.clone())
+        });
+
+        let mut context = Context {
+            variables,
+            stack,
+            loader,
+            limits,
+            observer: watched
+                .as_mut()
+                .map(|(observer, ..)| &mut **observer as &mut dyn Observer),
+        };
+        let result = Self::handle_instruction(instr, value, &mut context);
+        if let Some((observer, instruction, location, input)) = watched {
+            let step = Step {
+                instruction: &instruction,
+                location: &location,
+                input: &input,
             };
+            observer.after(&step, &result, variables);
+        }
 
-            match Self::handle_instruction(instr, value, &mut variables, &mut stack, loader) {
-                Ok(value) => Self::Active(variables, stack, value),
-                Err(err) => Self::Finished(Err(err)),
+        match result {
+            Ok(value) => *return_value = value,
+            Err(err) => {
+                // limits stop the program whatever it is doing, they cannot be caught
+                let caught = if err.is_limit() { None } else { stack.catch() };
+                if let Some(saved) = caught {
+                    variables.restore(saved);
+                    *return_value = err.into();
+                } else {
+                    let location =
+                        Location::new(path, stack.source_map().map(AsRef::as_ref), origin);
+                    *self = Self::Finished(Err(err.located(location)));
+                }
             }
-        } else {
-            self
         }
     }
 
     pub fn progress_in_place(&mut self, loader: &dyn Loader) {
-        *self = mem::take(self).progress(loader);
+        self.perform_next(loader, &mut Limits::new(), None);
     }
-}
 
-impl Program {
-    fn try_run_to_completion(self, input: Value, loader: &dyn Loader) -> Result<Value> {
-        let mut running = self.run(input);
+    /// Take one step within `limits`, see [`progress`][Self::progress].
+    ///
+    /// # Errors
+    /// An error for which [`Error::is_limit`][crate::Error::is_limit] is true if a limit was
+    /// reached. The program and the fuel are then left as they were before the step, except when
+    /// the step itself exceeded the memory limit. A nested program or a sleep that is stopped by a
+    /// limit is started again when the step is retried.
+    pub fn progress_with_limits(&mut self, loader: &dyn Loader, limits: &mut Limits) -> Result<()> {
+        self.step(loader, limits, None)
+    }
+
+    /// Take one step within `limits` like [`progress_with_limits`][Self::progress_with_limits],
+    /// calling `observer` around every instruction that is performed.
+    pub fn progress_observed(
+        &mut self,
+        loader: &dyn Loader,
+        limits: &mut Limits,
+        observer: &mut dyn Observer,
+    ) -> Result<()> {
+        self.step(loader, limits, Some(observer))
+    }
+
+    fn step(
+        &mut self,
+        loader: &dyn Loader,
+        limits: &mut Limits,
+        observer: Option<&mut dyn Observer>,
+    ) -> Result<()> {
+        if let Self::Finished(_) = self {
+            return Ok(());
+        }
+        let before =
+            (self.is_suspending() && !limits.is_unlimited()).then(|| (self.clone(), limits.fuel));
+        limits.step()?;
+
+        self.perform_next(loader, limits, observer);
+        self.settle(before, limits)
+    }
+
+    /// Whether the next instruction waits for something outside of the program, these are the
+    /// instructions that are started again when stopped by a limit.
+    fn is_suspending(&self) -> bool {
+        let Self::Active { stack, .. } = self else {
+            return false;
+        };
+        matches!(
+            stack.iter().next(),
+            Some(Instruction::Loading(_) | Instruction::Pure(Pure::Sleep(_)))
+        )
+    }
 
+    /// Check the limits after a step, rolling back to the state `before` the step if it was
+    /// stopped by a limit.
+    fn settle(&mut self, before: Option<(Self, Fuel)>, limits: &mut Limits) -> Result<()> {
+        match (before, &mut *self) {
+            (Some((before, fuel)), Self::Finished(Err(err))) if err.is_limit() => {
+                let err = err.unlocated().clone();
+                (*self, limits.fuel) = (before, fuel);
+                Err(err)
+            }
+            (
+                _,
+                Self::Active {
+                    variables,
+                    stack,
+                    return_value,
+                },
+            ) if limits.memory.is_some() => limits.check_memory(
+                variables.update_heap_size() + stack.heap_size() + return_value.heap_size(),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Progress until the program is finished and take its result, checking `limits` before
+    /// every step.
+    ///
+    /// # Errors
+    /// The error the program failed with, or the error of the limit that was reached first. In
+    /// that case the program is still active and continues where it stopped when resumed with
+    /// raised limits.
+    pub fn resume(&mut self, loader: &dyn Loader, limits: &mut Limits) -> Result<Value> {
+        self.resume_within(loader, limits, None)
+    }
+
+    /// Progress until the program is finished like [`resume`][Self::resume], calling `observer`
+    /// around every instruction that is performed.
+    pub fn resume_observed(
+        &mut self,
+        loader: &dyn Loader,
+        limits: &mut Limits,
+        observer: &mut dyn Observer,
+    ) -> Result<Value> {
+        self.resume_within(loader, limits, Some(observer))
+    }
+
+    fn resume_within(
+        &mut self,
+        loader: &dyn Loader,
+        limits: &mut Limits,
+        mut observer: Option<&mut dyn Observer>,
+    ) -> Result<Value> {
         loop {
-            match running.progress(loader) {
-                Running::Finished(value) => break value,
-                active @ Running::Active(..) => running = active,
+            let observer = observer
+                .as_mut()
+                .map(|observer| &mut **observer as &mut dyn Observer);
+            self.step(loader, limits, observer)?;
+            if let Self::Finished(result) = self {
+                break mem::replace(result, Ok(Value::None));
             }
         }
     }
+}
 
+impl Program {
     #[must_use]
     pub fn run(self, input: Value) -> Running {
-        Running::Active(self.variables, vec![self.instruction].into(), input)
+        let mut stack = instruction::Stack::from(self.instruction);
+        stack.set_source_map(self.source_map);
+        Running::Active {
+            variables: self.variables,
+            stack,
+            return_value: input,
+        }
     }
 
     pub fn run_to_completion(self, input: Value, loader: &dyn Loader) -> Result<Value> {
-        if self.is_fallible {
-            self.try_run_to_completion(input, loader)
-                .or(Ok(Value::None))
-        } else {
-            self.try_run_to_completion(input, loader)
+        self.run_to_completion_with_limits(input, loader, &mut Limits::new())
+    }
+
+    /// Run the program to completion within `limits`, reaching a limit is an error even if the
+    /// program is fallible.
+    pub fn run_to_completion_with_limits(
+        self,
+        input: Value,
+        loader: &dyn Loader,
+        limits: &mut Limits,
+    ) -> Result<Value> {
+        self.run_within(input, loader, limits, None)
+    }
+
+    /// Run the program to completion within `limits` like
+    /// [`run_to_completion_with_limits`][Self::run_to_completion_with_limits], calling `observer`
+    /// around every instruction that is performed.
+    pub fn run_to_completion_observed(
+        self,
+        input: Value,
+        loader: &dyn Loader,
+        limits: &mut Limits,
+        observer: &mut dyn Observer,
+    ) -> Result<Value> {
+        self.run_within(input, loader, limits, Some(observer))
+    }
+
+    pub(crate) fn run_within(
+        self,
+        input: Value,
+        loader: &dyn Loader,
+        limits: &mut Limits,
+        observer: Option<&mut dyn Observer>,
+    ) -> Result<Value> {
+        let is_fallible = self.is_fallible;
+        match self.run(input).resume_within(loader, limits, observer) {
+            Err(err) if is_fallible && !err.is_limit() => Ok(Value::None),
+            result => result,
         }
     }
 
@@ -121,12 +350,29 @@ impl Program {
     pub fn is_fallible(&self) -> bool {
         self.is_fallible
     }
+
+    #[must_use]
+    pub fn variables(&self) -> &variable::Map {
+        &self.variables
+    }
+
+    #[must_use]
+    pub fn instruction(&self) -> &Instruction {
+        &self.instruction
+    }
+
+    /// Where the instructions of the program were parsed from, if they were parsed from text.
+    #[must_use]
+    pub fn source_map(&self) -> Option<&SourceMap> {
+        self.source_map.as_deref()
+    }
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct Builder {
     instruction_vec: Vec<Instruction>,
     is_fallible: bool,
+    source_map: Option<SourceMap>,
 }
 
 impl Builder {
@@ -145,23 +391,387 @@ impl Builder {
         self
     }
 
+    pub fn source_map(&mut self, source_map: SourceMap) -> &mut Self {
+        self.source_map = Some(source_map);
+        self
+    }
+
     #[must_use]
     pub fn build(self, variable_map: variable::Map) -> Program {
         let Builder {
             mut instruction_vec,
             is_fallible,
+            source_map,
         } = self;
 
         Program {
             is_fallible,
+            source_map: source_map.map(Arc::new),
             variables: variable_map,
-            instruction: match instruction_vec.len() {
-                0 => Instruction::Noop,
-                1 => instruction_vec
-                    .pop()
-                    .expect("since we now the length is 1 pop should always succeed"),
-                _ => instruction::meta::List(instruction_vec).into_instruction(),
+            instruction: if instruction_vec.len() > 1 {
+                instruction::meta::List(instruction_vec).into_instruction()
+            } else {
+                instruction_vec.pop().unwrap_or_default()
             },
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::DefaultLoader;
+    use std::{cell::Cell, rc::Rc, thread};
+
+    #[test]
+    pub fn threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Program>();
+        assert_send_sync::<Running>();
+        assert_send_sync::<Value>();
+        assert_send_sync::<Limits>();
+
+        let program =
+            Arc::new(crate::bml! { rw n = 0; assign n; clone n; op mul 2; op_clone add n });
+        let workers: Vec<_> = (0..4)
+            .map(|i| {
+                let program = Arc::clone(&program);
+                thread::spawn(move || {
+                    Program::clone(&program).run_to_completion(Value::Int(i), &DefaultLoader)
+                })
+            })
+            .collect();
+        let results: Vec<_> = workers
+            .into_iter()
+            .map(|worker| worker.join().expect("worker thread"))
+            .collect();
+        assert_eq!(results, [0, 3, 6, 9].map(|n| Ok(Value::Int(n))));
+
+        // a program started on one thread is finished on another
+        let mut running = Program::clone(&program).run(Value::Int(5));
+        running.progress_in_place(&DefaultLoader);
+        running.progress_in_place(&DefaultLoader);
+        let result = thread::spawn(move || running.resume(&DefaultLoader, &mut Limits::new()))
+            .join()
+            .expect("worker thread");
+        assert_eq!(result, Ok(Value::Int(15)));
+    }
+
+    #[test]
+    pub fn local_loader() {
+        /// Counts its loads without being shareable between threads.
+        struct Counting(Rc<Cell<i64>>);
+        impl Loader for Counting {
+            fn load(&self, _: Value) -> Result<Value> {
+                self.0.set(self.0.get() + 1);
+                Ok(Value::Int(self.0.get()))
+            }
+        }
+
+        let loader = Counting(Rc::default());
+        let program = crate::bml! { load; load; op add 1 };
+        assert_eq!(
+            program.run_to_completion(Value::None, &loader),
+            Ok(Value::Int(3))
+        );
+    }
+
+    #[test]
+    pub fn checkpoint() -> serde_json::Result<()> {
+        let program = crate::bml! {
+            rw n = 0;
+            ro l = instr { take n; op add 1; assign n };
+            clone l;
+            perform none;
+            clone l;
+            perform none;
+            take n;
+            op div 0;
+        };
+        let mut running = program.run(Value::None);
+        let mut limits = Limits {
+            fuel: Fuel::new(12),
+            ..Limits::new()
+        };
+        assert_eq!(
+            running.resume(&DefaultLoader, &mut limits),
+            Err(crate::Error::OutOfFuel)
+        );
+
+        // paused inside of the second performed instruction
+        let saved = serde_json::to_string(&running)?;
+        let mut restored: Running = serde_json::from_str(&saved)?;
+        assert_eq!(restored, running);
+        let Running::Active { stack, .. } = &restored else {
+            panic!("program finished");
+        };
+        assert_eq!(stack.path().indices(), [3, 1, 0]);
+
+        let result = restored.resume(&DefaultLoader, &mut Limits::new());
+        assert_eq!(
+            result.as_ref().map_err(ToString::to_string),
+            Err("instruction 5: tried to divide 2 by 0 (zero)".to_owned())
+        );
+        let finished = Running::Finished(result);
+        assert_eq!(
+            serde_json::from_str::<Running>(&serde_json::to_string(&finished)?)?,
+            finished
+        );
+        Ok(())
+    }
+
+    #[test]
+    pub fn checkpoint_size() -> serde_json::Result<()> {
+        // the instruction performs itself from within itself, so every iteration is pushed as a
+        // child of the last one
+        let checkpoint = |steps| {
+            let program = crate::bml! {
+                ro l = instr { clone l; perform none };
+                clone l;
+                perform none;
+            };
+            let mut running = program.run(Value::None);
+            let mut limits = Limits {
+                fuel: Fuel::new(steps),
+                ..Limits::new()
+            };
+            assert_eq!(
+                running.resume(&DefaultLoader, &mut limits),
+                Err(crate::Error::OutOfFuel)
+            );
+            serde_json::to_string(&running).map(|saved| (running, saved))
+        };
+
+        let (_, small) = checkpoint(1_000)?;
+        let (running, large) = checkpoint(10_000)?;
+        assert!(
+            large.len() < small.len() * 20,
+            "{} bytes after 1000 steps and {} after 10000",
+            small.len(),
+            large.len()
+        );
+        assert_eq!(serde_json::from_str::<Running>(&large)?, running);
+        Ok(())
+    }
+
+    #[test]
+    pub fn loops() {
+        let sum = crate::bml! {
+            rw n = 0;
+            rw sum = 0;
+            ro stop = instr { clone n; op eq 7; cond instr break instr {}; perform none };
+            while { clone n; op lt 10 } {
+                take n;
+                op add 1;
+                assign n;
+                clone stop;
+                perform none;
+                clone n;
+                op eq 3;
+                cond instr continue instr {};
+                perform none;
+                take sum;
+                op_clone add n;
+                assign sum;
+            };
+            take sum;
+        };
+        assert_eq!(
+            sum.run_to_completion(Value::None, &DefaultLoader),
+            Ok(Value::Int(1 + 2 + 4 + 5 + 6))
+        );
+
+        let doubling = crate::bml! {
+            rw x = 0;
+            loop {
+                op mul 2;
+                assign x;
+                clone x;
+                op gt 100;
+                cond instr { clone x; break } instr {};
+                perform none;
+                clone x;
+            };
+        };
+        assert_eq!(
+            doubling.run_to_completion(Value::Int(1), &DefaultLoader),
+            Ok(Value::Int(128))
+        );
+
+        let outside = crate::bml! { put 1; break };
+        let result = outside.run_to_completion(Value::None, &DefaultLoader);
+        assert_eq!(
+            result.as_ref().map_err(ToString::to_string),
+            Err("instruction 1: break was performed outside of a loop".to_owned())
+        );
+    }
+
+    #[test]
+    pub fn branches() {
+        let sum = crate::bml! {
+            rw n = 0;
+            rw sum = 0;
+            while { clone n; op lt 5 } {
+                take n;
+                op add 1;
+                assign n;
+                clone n;
+                switch {
+                    2: put 20;
+                    4: put 40
+                } else { op lt 3; if put 1 else put 100 };
+                op_take add sum;
+                assign sum;
+            };
+            take sum;
+        };
+        assert_eq!(
+            sum.run_to_completion(Value::None, &DefaultLoader),
+            Ok(Value::Int(1 + 20 + 100 + 40 + 100))
+        );
+
+        let switched = crate::bml! { put 3; switch { 3: op add 1; 4: op add 2 } };
+        assert_eq!(
+            switched.run_to_completion(Value::None, &DefaultLoader),
+            Ok(Value::Int(4))
+        );
+
+        let failing = crate::bml! { put false; if put 1 else { put 1; op div 0 } };
+        let location = failing
+            .run_to_completion(Value::None, &DefaultLoader)
+            .err()
+            .and_then(|err| err.location().cloned());
+        assert_eq!(
+            location.map(|location| location.path.indices()),
+            Some(vec![1, 1, 1])
+        );
+
+        let wrong = crate::bml! { put 1; if {} };
+        let result = wrong.run_to_completion(Value::None, &DefaultLoader);
+        assert!(matches!(
+            result.map_err(|err| err.unlocated().clone()),
+            Err(crate::Error::WrongInstructionInput(Value::Int(1), _))
+        ));
+    }
+
+    #[test]
+    pub fn errors() {
+        let parsed = crate::bml! { put "12a"; try { parse int; op add 1 } catch {} };
+        let error = [
+            ("kind".into(), Value::string("failed_parse")),
+            (
+                "message".into(),
+                Value::string("failed to parse \"12a\" into int"),
+            ),
+            (
+                "values".into(),
+                Value::List(vec![
+                    Value::Type(crate::value::Type::Int),
+                    Value::string("12a"),
+                ]),
+            ),
+        ];
+        assert_eq!(
+            parsed.run_to_completion(Value::None, &DefaultLoader),
+            Ok(Value::Map(error.into()))
+        );
+
+        let caught = crate::bml! {
+            rw n = 0;
+            loop {
+                take n;
+                op add 1;
+                assign n;
+                try { loop { put "x"; parse int } } catch {};
+                clone n;
+                op eq 3;
+                if break;
+                clone n;
+            };
+        };
+        assert_eq!(
+            caught.run_to_completion(Value::None, &DefaultLoader),
+            Ok(Value::None)
+        );
+
+        let failing = crate::bml! { put "12a"; try parse int catch { put 1; op div 0 } };
+        let location = failing
+            .run_to_completion(Value::None, &DefaultLoader)
+            .err()
+            .and_then(|err| err.location().cloned());
+        assert_eq!(
+            location.map(|location| location.path.indices()),
+            Some(vec![1, 1, 1])
+        );
+
+        let thrown = crate::bml! { put { "duplicate": "https://example.com" }; throw };
+        let result = thrown.run_to_completion(Value::None, &DefaultLoader);
+        let Err(err) = result else {
+            panic!("nothing was thrown");
+        };
+        assert_eq!(err.kind(), "thrown");
+        assert!(matches!(
+            err.unlocated(),
+            crate::Error::Thrown(Value::Map(payload)) if payload.contains_key("duplicate")
+        ));
+
+        let endless = crate::bml! { try loop noop catch noop };
+        let mut limits = Limits {
+            fuel: Fuel::new(100),
+            ..Limits::new()
+        };
+        assert_eq!(
+            endless.run_to_completion_with_limits(Value::None, &DefaultLoader, &mut limits),
+            Err(crate::Error::OutOfFuel)
+        );
+    }
+
+    #[test]
+    pub fn calls() {
+        let factorial = crate::bml! {
+            rw n = 7;
+            rw arguments;
+            ro fact = instr function [n] [arguments] {
+                clone n;
+                op lt 2;
+                if { put 1; return };
+                put [0];
+                assign arguments;
+                clone n;
+                op sub 1;
+                map_assign arguments 0;
+                take arguments;
+                call fact;
+                op_clone mul n;
+            };
+            put [10];
+            call fact;
+            op_clone add n;
+        };
+        assert_eq!(
+            factorial.run_to_completion(Value::None, &DefaultLoader),
+            Ok(Value::Int(3_628_807))
+        );
+
+        let restored = crate::bml! {
+            rw n = 1;
+            ro failing = instr function [n] [] { put 1; op div 0 };
+            try { put [2]; call failing } catch {};
+            clone n;
+        };
+        assert_eq!(
+            restored.run_to_completion(Value::None, &DefaultLoader),
+            Ok(Value::Int(1))
+        );
+
+        let escaping = crate::bml! {
+            ro f = instr function [] [] { break };
+            loop { put []; call f };
+        };
+        let result = escaping.run_to_completion(Value::None, &DefaultLoader);
+        assert_eq!(
+            result.as_ref().map_err(ToString::to_string),
+            Err("instruction 0.1.0.0.0: break was performed outside of a loop".to_owned())
+        );
+    }
+}
@@ -0,0 +1,258 @@
+//! Running programs as futures.
+//!
+//! Sleeping and loading are awaited using the [Timer] and the [`AsyncLoader`] of the host instead
+//! of blocking, and a program yields to the async runtime regularly in between, so many programs
+//! can run on the same thread.
+
+use super::{Program, Running};
+use crate::{
+    instruction::{
+        self, loading,
+        traits::{AsyncLoader, Timer},
+        DefaultLoader, Instruction, Loading, Pure,
+    },
+    limits::Limits,
+    location::Location,
+    value::Value,
+    Result,
+};
+use std::{
+    future::Future,
+    mem,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+/// Number of steps a program takes before it yields to the async runtime.
+const STEPS_PER_YIELD: u32 = 256;
+
+impl Running {
+    /// Progress until the program is finished and take its result like
+    /// [`resume`][Self::resume], awaiting sleeps and loads instead of blocking.
+    ///
+    /// # Errors
+    /// The error the program failed with, or the error of the limit that was reached first.
+    pub async fn resume_async(
+        &mut self,
+        loader: &dyn AsyncLoader,
+        timer: &dyn Timer,
+        limits: &mut Limits,
+    ) -> Result<Value> {
+        let mut steps = 0;
+        loop {
+            if self.is_suspending() {
+                self.step_async(loader, timer, limits).await?;
+            } else {
+                // nothing is loaded in a step that does not suspend
+                self.progress_with_limits(&DefaultLoader, limits)?;
+                steps += 1;
+                if steps == STEPS_PER_YIELD {
+                    steps = 0;
+                    YieldNow(false).await;
+                }
+            }
+
+            if let Self::Finished(result) = self {
+                break mem::replace(result, Ok(Value::None));
+            }
+        }
+    }
+
+    async fn step_async(
+        &mut self,
+        loader: &dyn AsyncLoader,
+        timer: &dyn Timer,
+        limits: &mut Limits,
+    ) -> Result<()> {
+        let before = (!limits.is_unlimited()).then(|| (self.clone(), limits.fuel));
+        limits.step()?;
+
+        let Self::Active {
+            variables,
+            stack,
+            return_value,
+        } = self
+        else {
+            return Ok(());
+        };
+        let Some(instr) = stack.pop() else {
+            return Ok(());
+        };
+        let path = stack.path().clone();
+        let origin = stack.origin();
+        let value = mem::take(return_value);
+
+        let result = match instr {
+            Instruction::Pure(Pure::Sleep(instr)) => {
+                instr.perform_async(value, timer, limits).await
+            }
+            Instruction::Loading(Loading::Load(_)) => loader.load(value).await,
+            Instruction::Loading(Loading::Program(loading::Program(program))) => {
+                let memory = limits.memory;
+                if let Some(memory) = &mut limits.memory {
+                    *memory = memory.saturating_sub(variables.heap_size() + stack.heap_size());
+                }
+                let program = Arc::unwrap_or_clone(program);
+                let result =
+                    Box::pin(program.run_to_completion_async(value, loader, timer, limits)).await;
+                limits.memory = memory;
+                result
+            }
+            instr => {
+                let mut context = instruction::Context {
+                    variables,
+                    stack,
+                    loader: &DefaultLoader,
+                    limits,
+                    observer: None,
+                };
+                Self::handle_instruction(instr, value, &mut context)
+            }
+        };
+
+        match result {
+            Ok(value) => *return_value = value,
+            Err(err) => {
+                // limits stop the program whatever it is doing, they cannot be caught
+                let caught = if err.is_limit() { None } else { stack.catch() };
+                if let Some(saved) = caught {
+                    variables.restore(saved);
+                    *return_value = err.into();
+                } else {
+                    let location =
+                        Location::new(path, stack.source_map().map(AsRef::as_ref), origin);
+                    *self = Self::Finished(Err(err.located(location)));
+                }
+            }
+        }
+        self.settle(before, limits)
+    }
+}
+
+impl Program {
+    /// Run the program to completion within `limits` like
+    /// [`run_to_completion_with_limits`][Self::run_to_completion_with_limits], awaiting sleeps
+    /// and loads instead of blocking.
+    pub async fn run_to_completion_async(
+        self,
+        input: Value,
+        loader: &dyn AsyncLoader,
+        timer: &dyn Timer,
+        limits: &mut Limits,
+    ) -> Result<Value> {
+        let is_fallible = self.is_fallible;
+        match self.run(input).resume_async(loader, timer, limits).await {
+            Err(err) if is_fallible && !err.is_limit() => Ok(Value::None),
+            result => result,
+        }
+    }
+}
+
+/// Pending once, letting other tasks run.
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{instruction::traits::BoxFuture, limits::Cancellation, Error};
+    use std::{
+        thread,
+        time::{Duration, Instant},
+    };
+
+    struct TokioTimer;
+    impl Timer for TokioTimer {
+        fn sleep(&self, duration: Duration) -> BoxFuture<'_, ()> {
+            Box::pin(tokio::time::sleep(duration))
+        }
+    }
+
+    /// Loads strings as their length, after a while.
+    struct SlowLoader;
+    impl AsyncLoader for SlowLoader {
+        fn load(&self, value: Value) -> BoxFuture<'_, Result<Value>> {
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                match value {
+                    Value::String(string) => Ok(Value::Int(string.len().try_into().unwrap_or(0))),
+                    value => Err(Error::UnloadableValue(value)),
+                }
+            })
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    pub async fn interleaved() {
+        let run = |program: Program| async {
+            program
+                .run_to_completion_async(Value::None, &SlowLoader, &TokioTimer, &mut Limits::new())
+                .await
+        };
+        let sleeping = crate::bml! { put 0.2; sleep; put "abc"; load; op add 1 };
+        let nested = crate::bml! { program { put 0.2; sleep; put 5 }; op mul 2 };
+
+        let start = Instant::now();
+        let results = tokio::join!(run(sleeping), run(nested));
+        assert_eq!(results, (Ok(Value::Int(4)), Ok(Value::Int(10))));
+        assert!(start.elapsed() < Duration::from_millis(350));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    pub async fn cancelled_sleep() {
+        let cancellation = Cancellation::new();
+        let mut limits = Limits {
+            cancellation: Some(cancellation.clone()),
+            ..Limits::new()
+        };
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            cancellation.cancel();
+        });
+
+        let mut running = crate::bml! { put 60.0; sleep }.run(Value::None);
+        let result = running
+            .resume_async(&DefaultLoader, &TokioTimer, &mut limits)
+            .await;
+        canceller.join().expect("cancelling thread");
+        assert_eq!(result, Err(Error::Cancelled));
+        assert!(matches!(running, Running::Active { .. }));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    pub async fn caught() {
+        let program = crate::bml! {
+            rw n = 1;
+            try { put 2; assign n; put none; load } catch { clone n };
+        };
+        let result = program
+            .run_to_completion_async(Value::None, &SlowLoader, &TokioTimer, &mut Limits::new())
+            .await;
+        assert_eq!(result, Ok(Value::Int(2)));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    pub async fn invalid_sleep() {
+        let program = crate::bml! { put 0.0; op sub 1.0; sleep };
+        let result = program
+            .run_to_completion_async(Value::None, &DefaultLoader, &TokioTimer, &mut Limits::new())
+            .await;
+        assert!(matches!(
+            result.as_ref().map_err(Error::unlocated),
+            Err(Error::WrongInstructionInput(..))
+        ));
+    }
+}
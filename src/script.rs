@@ -0,0 +1,233 @@
+//! Script language compiled to instructions.
+//!
+//! Scripts are written with infix expressions and structured control flow instead of the
+//! accumulator style of the [text format][crate::asm], and compile to a regular
+//! [Program][crate::program::Program].
+//!
+//! ```text
+//! fn fib(n) {
+//!     let a = 0
+//!     let b = 1
+//!     while n > 0 {
+//!         let next = a + b
+//!         a = b
+//!         b = next
+//!         n = n - 1
+//!     }
+//!     a
+//! }
+//!
+//! let seen = {"fib": [], "input": input}
+//! seen["fib"] = [fib(10), fib(20)]
+//! if seen["fib"][1] > 1000 { "large" } else { seen }
+//! ```
+//!
+//! - Statements end at a line break or a `;`, the value of a program, function or block is the
+//!   value of its last statement. Statements that are not expressions have the value `none`.
+//! - `let` declares a variable in the enclosing block, a variable may be shadowed by declaring
+//!   it again. Variables and list or map elements are assigned with `=`.
+//! - `if` and `else` form an expression, the condition has to be a bool.
+//! - `while` repeats its block for as long as the condition is true.
+//! - `try { ... } catch err { ... }` is an expression, if the first block fails the second one
+//!   is evaluated with `err` set to a map of the `kind`, `message` and `values` of the error.
+//! - `throw` fails with a value, which is the only element of `values` when it is caught.
+//! - Functions are declared at the top level and may be called before their declaration. They
+//!   only see their parameters and their own variables, every call gets its own values of them
+//!   so functions may be recursive. `return` leaves a function with a value, or `none` without
+//!   one, and outside of a function it finishes the program.
+//! - `input` is the value the program was run with.
+//! - The operators are, from lowest precedence, `||`, `&&`, comparisons, `+ -`, `* /` and the
+//!   prefix operators `- !`. Both operands of `&&` and `||` are always evaluated.
+
+use crate::{asm::ParseError, program::Program};
+
+mod compiler;
+mod lexer;
+mod parser;
+
+/// Compile source text into a [Program].
+pub fn compile(src: &str) -> Result<Program, ParseError> {
+    compiler::compile(&parser::parse(src)?, None)
+}
+
+/// Compile source text read from `file` into a [Program], the name of the file is included in
+/// the locations of runtime errors.
+pub fn compile_named(src: &str, file: &str) -> Result<Program, ParseError> {
+    compiler::compile(&parser::parse(src)?, Some(file.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{instruction::DefaultLoader, value::Value};
+
+    fn run(src: &str, input: Value) -> Result<Value, String> {
+        compile(src)
+            .map_err(|err| err.to_string())?
+            .run_to_completion(input, &DefaultLoader)
+            .map_err(|err| err.to_string())
+    }
+
+    #[test]
+    pub fn arithmetic() {
+        assert_eq!(run("1 + 2 * 3 - 8 / 2", Value::None), Ok(Value::Int(3)));
+        assert_eq!(
+            run("let x = 2.5\n-x * 2.0 - 1.0", Value::None),
+            Ok(Value::Float(-6.0))
+        );
+        assert_eq!(
+            run(
+                "let x = input; (x - 1) * (x + 1) != x * x - 1",
+                Value::Int(7)
+            ),
+            Ok(Value::Bool(false))
+        );
+    }
+
+    #[test]
+    pub fn control_flow() {
+        let src = r#"
+            fn fib(n) {
+                let a = 0
+                let b = 1
+                while n > 0 {
+                    let next = a + b
+                    a = b
+                    b = next
+                    n = n - 1
+                }
+                a
+            }
+            fn describe(n) {
+                if n < 10 { "small" }
+                else if n < 1000 { "medium" }
+                else { "large" }
+            }
+
+            let seen = {"fib": [], "input": input}
+            seen["fib"] = [fib(input), describe(fib(input)), describe(fib(2))]
+            seen
+        "#;
+
+        assert_eq!(
+            run(src, Value::Int(20)),
+            Ok(Value::Map(
+                [
+                    (
+                        "fib".into(),
+                        Value::List(vec![
+                            Value::Int(6765),
+                            Value::string("large"),
+                            Value::string("small")
+                        ])
+                    ),
+                    ("input".into(), Value::Int(20)),
+                ]
+                .into()
+            ))
+        );
+    }
+
+    #[test]
+    pub fn errors() {
+        let src = r#"
+            let parsed = [none, none, none]
+            let i = 0
+            while i < 3 {
+                let n = try { [1, 0, 4][i] * 2 / [1, 0, 4][i] } catch err { err["kind"] }
+                parsed[i] = n
+                i = i + 1
+            }
+            parsed
+        "#;
+        assert_eq!(
+            run(src, Value::None),
+            Ok(Value::List(vec![
+                Value::Int(2),
+                Value::string("zero_div"),
+                Value::Int(2)
+            ]))
+        );
+
+        let src = r#"
+            fn check(url) {
+                if url == "" { throw {"invalid": url} }
+                url
+            }
+            let checked = try { check(input) } catch err { err["values"][0] }
+            check(checked["invalid"] + "https://example.com")
+            throw "done"
+        "#;
+        assert_eq!(
+            run(src, Value::string("")),
+            Err("8:13 (3.1): \"done\" was thrown".to_owned())
+        );
+    }
+
+    #[test]
+    pub fn evaluation_order() {
+        let src = r#"
+            fn f(n) { throw ["f", n] }
+            fn g(n) { throw ["g", n] }
+            let thrown = try { f(1) + g(2) } catch err { err["values"][0] }
+            [thrown, [10][0] - [3][0], [12][0] / (input - 1)]
+        "#;
+        assert_eq!(
+            run(src, Value::Int(4)),
+            Ok(Value::List(vec![
+                Value::List(vec![Value::string("f"), Value::Int(1)]),
+                Value::Int(7),
+                Value::Int(4)
+            ]))
+        );
+    }
+
+    #[test]
+    pub fn recursion() {
+        let src = r#"
+            fn fib(n) {
+                if n < 2 { return n }
+                let a = fib(n - 1)
+                a + fib(n - 2)
+            }
+            fn even(n) { if n == 0 { true } else { odd(n - 1) } }
+            fn odd(n) { if n == 0 { false } else { even(n - 1) } }
+            fn first(list, wanted) {
+                let i = 0
+                while i < 3 {
+                    if list[i] == wanted { return i }
+                    i = i + 1
+                }
+            }
+
+            let n = input
+            if n > 20 { return "too large" }
+            [fib(n), even(n), n, first([1, n, 3], n), first([4, 5, 6], 0)]
+        "#;
+        assert_eq!(
+            run(src, Value::Int(15)),
+            Ok(Value::List(vec![
+                Value::Int(610),
+                Value::Bool(false),
+                Value::Int(15),
+                Value::Int(1),
+                Value::None
+            ]))
+        );
+        assert_eq!(run(src, Value::Int(21)), Ok(Value::string("too large")));
+    }
+
+    #[test]
+    pub fn compile_errors() {
+        let message = |src| compile(src).map(|_| ()).map_err(|err| err.to_string());
+
+        assert_eq!(
+            message("let a = 1\nb = a"),
+            Err("2:1: unknown variable `b`".to_owned())
+        );
+        assert_eq!(
+            message("fn f(a, b) { a }\nf(1)"),
+            Err("2:1: `f` takes 2 arguments but 1 were given".to_owned())
+        );
+    }
+}
@@ -0,0 +1,474 @@
+use super::parser::{BinOp, Block, Expr, ExprKind, Function, Script, Stmt, StmtKind};
+use crate::{
+    asm::{ParseError, Span},
+    instruction::{meta, mutating, pure, reading, Instruction},
+    location::SourceMap,
+    program::{self, Program},
+    value::{Operation, Type, Value},
+    variable::{Id, MapBuilder},
+};
+use std::{collections::HashMap, sync::Arc};
+
+/// Variables used when a function is called.
+struct Signature {
+    /// Read-only variable holding the function.
+    body: Id,
+    params: Vec<Id>,
+}
+
+/// How an operand can be given to an instruction without computing it first.
+enum Operand {
+    Const(Value),
+    Var(Id),
+    Computed,
+}
+
+#[derive(Default)]
+struct Compiler {
+    variables: MapBuilder,
+    functions: HashMap<String, Signature>,
+    scopes: Vec<HashMap<String, Id>>,
+    /// Variables of the function being compiled, [None] at the top level.
+    locals: Option<Vec<Id>>,
+    input: Option<Id>,
+}
+
+pub(crate) fn compile(script: &Script, file: Option<Arc<str>>) -> Result<Program, ParseError> {
+    let mut compiler = Compiler::default();
+
+    compiler.declare_functions(&script.functions)?;
+    for function in &script.functions {
+        compiler.function(function)?;
+    }
+
+    compiler.scopes = vec![HashMap::new()];
+    compiler.locals = None;
+    let mut items = Vec::new();
+    for stmt in &script.statements {
+        let mut out = Vec::new();
+        compiler.statement(stmt, &mut out)?;
+        items.push((out, Some(stmt.span)));
+    }
+
+    // the input is only available as the return value before the first instruction
+    if let Some(input) = compiler.input {
+        items.insert(0, (vec![mutating::Assign(input).into()], None));
+    }
+
+    let is_single = items.len() == 1;
+    let mut source_map = SourceMap::new(file);
+    let mut builder = program::Builder::new();
+    for (index, (mut out, span)) in items.into_iter().enumerate() {
+        if let Some(span) = span {
+            source_map.insert(if is_single { vec![] } else { vec![index] }, span);
+        }
+        builder.push_instruction(if out.len() == 1 {
+            out.remove(0)
+        } else {
+            meta::List(out).into()
+        });
+    }
+    builder.source_map(source_map);
+
+    Ok(builder.build(compiler.variables.build()))
+}
+
+impl Compiler {
+    fn error(span: Span, message: impl Into<String>) -> ParseError {
+        ParseError::new(span, message)
+    }
+
+    fn declare_functions(&mut self, functions: &[Function]) -> Result<(), ParseError> {
+        for function in functions {
+            if self.functions.contains_key(&function.name) {
+                return Err(Self::error(
+                    function.span,
+                    format!("function `{}` is already defined", function.name),
+                ));
+            }
+            let signature = Signature {
+                body: self.variables.reserve_ro(),
+                params: function
+                    .params
+                    .iter()
+                    .map(|_| self.variables.reserve_rw())
+                    .collect(),
+            };
+            self.functions.insert(function.name.clone(), signature);
+        }
+        Ok(())
+    }
+
+    fn function(&mut self, function: &Function) -> Result<(), ParseError> {
+        let signature = &self.functions[&function.name];
+        let (body, parameters) = (signature.body, signature.params.clone());
+
+        let mut params = HashMap::new();
+        for (name, &id) in function.params.iter().zip(&signature.params) {
+            if params.insert(name.clone(), id).is_some() {
+                return Err(Self::error(
+                    function.span,
+                    format!("parameter `{name}` is declared more than once"),
+                ));
+            }
+        }
+        self.scopes = vec![params];
+        self.locals = Some(Vec::new());
+
+        let mut out = Vec::new();
+        self.block(&function.body, &mut out)?;
+        let function = meta::Function {
+            parameters,
+            locals: self.locals.take().unwrap_or_default(),
+            body: Box::new(meta::List(out).into()),
+        };
+        self.set(body, function);
+        Ok(())
+    }
+
+    fn set(&mut self, id: Id, instruction: impl Into<Instruction>) {
+        self.variables
+            .set(id, instruction.into().into())
+            .expect("id was reserved in the same builder");
+    }
+
+    /// Reserve a variable, it is local to the function being compiled.
+    fn temp(&mut self) -> Id {
+        let id = self.variables.reserve_rw();
+        if let Some(locals) = &mut self.locals {
+            locals.push(id);
+        }
+        id
+    }
+
+    fn lookup(&self, name: &str, span: Span) -> Result<Id, ParseError> {
+        if let Some(id) = self.scopes.iter().rev().find_map(|scope| scope.get(name)) {
+            Ok(*id)
+        } else if self.functions.contains_key(name) {
+            Err(Self::error(
+                span,
+                format!("`{name}` is a function and can only be called"),
+            ))
+        } else {
+            Err(Self::error(span, format!("unknown variable `{name}`")))
+        }
+    }
+
+    fn operand(&self, expr: &Expr) -> Result<Operand, ParseError> {
+        Ok(if let Some(value) = constant(expr) {
+            Operand::Const(value)
+        } else if let ExprKind::Var(name) = &expr.kind {
+            Operand::Var(self.lookup(name, expr.span)?)
+        } else {
+            Operand::Computed
+        })
+    }
+
+    /// Compile a block, its value is the value of the last statement.
+    fn block(&mut self, block: &Block, out: &mut Vec<Instruction>) -> Result<(), ParseError> {
+        self.scopes.push(HashMap::new());
+        let result = block
+            .0
+            .iter()
+            .try_for_each(|stmt| self.statement(stmt, out));
+        self.scopes.pop();
+        result
+    }
+
+    fn statement(&mut self, stmt: &Stmt, out: &mut Vec<Instruction>) -> Result<(), ParseError> {
+        match &stmt.kind {
+            StmtKind::Let(name, value) => {
+                // declared after the value so that it may refer to a shadowed variable
+                self.expr(value, out)?;
+                let id = self.temp();
+                self.scopes
+                    .last_mut()
+                    .expect("there is always a scope")
+                    .insert(name.clone(), id);
+                out.push(mutating::Assign(id).into());
+            }
+            StmtKind::Assign {
+                name,
+                index: None,
+                value,
+            } => {
+                let id = self.lookup(name, stmt.span)?;
+                self.expr(value, out)?;
+                out.push(mutating::Assign(id).into());
+            }
+            StmtKind::Assign {
+                name,
+                index: Some(index),
+                value,
+            } => {
+                let map = self.lookup(name, stmt.span)?;
+                let key = match self.operand(index)? {
+                    Operand::Const(key) => key,
+                    Operand::Var(id) => Value::Id(id),
+                    Operand::Computed => {
+                        let id = self.temp();
+                        self.expr(index, out)?;
+                        out.push(mutating::Assign(id).into());
+                        Value::Id(id)
+                    }
+                };
+                self.expr(value, out)?;
+                out.push(mutating::MapAssign { map, key }.into());
+            }
+            StmtKind::While(condition, body) => {
+                let mut check = Vec::new();
+                self.expr(condition, &mut check)?;
+                let mut repeat = Vec::new();
+                self.block(body, &mut repeat)?;
+                out.push(
+                    meta::While {
+                        condition: Box::new(meta::List(check).into()),
+                        body: Box::new(meta::List(repeat).into()),
+                    }
+                    .into(),
+                );
+            }
+            StmtKind::Return(value) => {
+                match value {
+                    Some(value) => self.expr(value, out)?,
+                    None => out.push(pure::Put(Value::None).into()),
+                }
+                out.push(meta::Return.into());
+            }
+            StmtKind::Throw(value) => {
+                self.expr(value, out)?;
+                out.push(pure::Throw.into());
+            }
+            StmtKind::Expr(expr) => self.expr(expr, out)?,
+        }
+        Ok(())
+    }
+
+    /// Compile an expression, its value is left as the return value.
+    fn expr(&mut self, expr: &Expr, out: &mut Vec<Instruction>) -> Result<(), ParseError> {
+        if let Some(value) = constant(expr) {
+            out.push(pure::Put(value).into());
+            return Ok(());
+        }
+
+        match &expr.kind {
+            ExprKind::Literal(value) => out.push(pure::Put(value.clone()).into()),
+            ExprKind::Var(name) => out.push(reading::Clone(self.lookup(name, expr.span)?).into()),
+            ExprKind::Input => {
+                let id = *self
+                    .input
+                    .get_or_insert_with(|| self.variables.reserve_rw());
+                out.push(reading::Clone(id).into());
+            }
+            ExprKind::List(items) => self.list(items, out)?,
+            ExprKind::Map(entries) => {
+                let initial = entries
+                    .iter()
+                    .map(|(key, value)| (key.clone(), constant(value).unwrap_or_default()))
+                    .collect();
+                let entries = entries
+                    .iter()
+                    .map(|(key, value)| (Value::String(key.clone()), value));
+                self.collection(Value::Map(initial), entries, out)?;
+            }
+            ExprKind::Index(base, index) => {
+                if let Operand::Var(id) = self.operand(base)? {
+                    self.expr(index, out)?;
+                    out.push(reading::GetClone(id).into());
+                } else {
+                    let id = self.temp();
+                    self.expr(base, out)?;
+                    out.push(mutating::Assign(id).into());
+                    self.expr(index, out)?;
+                    out.push(mutating::GetTake(id).into());
+                }
+            }
+            ExprKind::Call(name, args) => self.call(name, args, expr.span, out)?,
+            ExprKind::Neg(value) => {
+                // multiplied by -1 of the same numeric type
+                let id = self.temp();
+                self.expr(value, out)?;
+                out.extend([
+                    mutating::Assign(id).into(),
+                    reading::Clone(id).into(),
+                    pure::Coerce(Type::Type).into(),
+                    pure::Op(Operation::Eq, Value::Type(Type::Int)).into(),
+                    pure::Cond {
+                        if_true: Value::Int(-1),
+                        if_false: Value::Float(-1.0),
+                    }
+                    .into(),
+                    mutating::OpTake(Operation::Mul, id).into(),
+                ]);
+            }
+            ExprKind::Not(value) => {
+                self.expr(value, out)?;
+                out.push(pure::Not.into());
+            }
+            ExprKind::Binary(op, lhs, rhs) => self.binary(*op, lhs, rhs, out)?,
+            ExprKind::If(condition, if_true, if_false) => {
+                let branch = |this: &mut Self, block: &Block| {
+                    let mut out = Vec::new();
+                    this.block(block, &mut out)?;
+                    Ok::<_, ParseError>(Box::new(meta::List(out).into()))
+                };
+
+                self.expr(condition, out)?;
+                let if_true = branch(self, if_true)?;
+                let if_false = match if_false {
+                    Some(block) => branch(self, block)?,
+                    None => Box::new(Instruction::Noop),
+                };
+                out.push(meta::If { if_true, if_false }.into());
+            }
+            ExprKind::Try(body, name, handler) => {
+                let mut attempt = Vec::new();
+                self.block(body, &mut attempt)?;
+
+                let error = self.temp();
+                self.scopes.push(HashMap::from([(name.clone(), error)]));
+                let mut recover = vec![mutating::Assign(error).into()];
+                let result = self.block(handler, &mut recover);
+                self.scopes.pop();
+                result?;
+
+                out.push(
+                    meta::Try {
+                        body: Box::new(meta::List(attempt).into()),
+                        handler: Box::new(meta::List(recover).into()),
+                    }
+                    .into(),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn list(&mut self, items: &[Expr], out: &mut Vec<Instruction>) -> Result<(), ParseError> {
+        if let Some(list) = items.iter().map(constant).collect::<Option<_>>() {
+            out.push(pure::Put(Value::List(list)).into());
+            return Ok(());
+        }
+        let initial = items
+            .iter()
+            .map(|item| constant(item).unwrap_or_default())
+            .collect();
+        let items = (0..).map(Value::Int).zip(items);
+        self.collection(Value::List(initial), items, out)
+    }
+
+    /// Build a list or map, constant items are part of the initial value and the rest are
+    /// assigned one at a time.
+    fn collection<'e>(
+        &mut self,
+        initial: Value,
+        items: impl Iterator<Item = (Value, &'e Expr)>,
+        out: &mut Vec<Instruction>,
+    ) -> Result<(), ParseError> {
+        let id = self.temp();
+        out.extend([pure::Put(initial).into(), mutating::Assign(id).into()]);
+
+        for (key, item) in items {
+            if constant(item).is_none() {
+                self.expr(item, out)?;
+                out.push(mutating::MapAssign { map: id, key }.into());
+            }
+        }
+        out.push(mutating::Take(id).into());
+        Ok(())
+    }
+
+    fn binary(
+        &mut self,
+        op: BinOp,
+        lhs: &Expr,
+        rhs: &Expr,
+        out: &mut Vec<Instruction>,
+    ) -> Result<(), ParseError> {
+        let operation = match op {
+            BinOp::Add => Operation::Add,
+            BinOp::Sub => Operation::Sub,
+            BinOp::Mul => Operation::Mul,
+            BinOp::Div => Operation::Div,
+            BinOp::Eq | BinOp::Ne => Operation::Eq,
+            BinOp::Lt => Operation::Lt,
+            BinOp::Le => Operation::Le,
+            BinOp::Gt => Operation::Gt,
+            BinOp::Ge => Operation::Ge,
+            BinOp::And => Operation::And,
+            BinOp::Or => Operation::Or,
+        };
+
+        match self.operand(rhs)? {
+            Operand::Const(value) => {
+                self.expr(lhs, out)?;
+                out.push(pure::Op(operation, value).into());
+            }
+            Operand::Var(id) => {
+                self.expr(lhs, out)?;
+                out.push(reading::OpClone(operation, id).into());
+            }
+            Operand::Computed => {
+                // the left operand is computed first, the right one is swapped in for it
+                let id = self.temp();
+                self.expr(lhs, out)?;
+                out.push(mutating::Assign(id).into());
+                self.expr(rhs, out)?;
+                out.push(mutating::Swap(id).into());
+                out.push(mutating::OpTake(operation, id).into());
+            }
+        }
+
+        if op == BinOp::Ne {
+            out.push(pure::Not.into());
+        }
+        Ok(())
+    }
+
+    /// Arguments are computed before any parameter is assigned, since computing them may call the
+    /// same function.
+    fn call(
+        &mut self,
+        name: &str,
+        args: &[Expr],
+        span: Span,
+        out: &mut Vec<Instruction>,
+    ) -> Result<(), ParseError> {
+        let Some(signature) = self.functions.get(name) else {
+            return Err(Self::error(span, format!("unknown function `{name}`")));
+        };
+        if signature.params.len() != args.len() {
+            return Err(Self::error(
+                span,
+                format!(
+                    "`{name}` takes {} arguments but {} were given",
+                    signature.params.len(),
+                    args.len()
+                ),
+            ));
+        }
+        let body = signature.body;
+
+        self.list(args, out)?;
+        out.push(meta::Call(body).into());
+        Ok(())
+    }
+}
+
+/// Value of expressions that do not depend on anything.
+fn constant(expr: &Expr) -> Option<Value> {
+    match &expr.kind {
+        ExprKind::Literal(value) => Some(value.clone()),
+        ExprKind::List(items) => items
+            .iter()
+            .map(constant)
+            .collect::<Option<_>>()
+            .map(Value::List),
+        ExprKind::Map(entries) => entries
+            .iter()
+            .map(|(key, value)| Some((key.clone(), constant(value)?)))
+            .collect::<Option<_>>()
+            .map(Value::Map),
+        _ => None,
+    }
+}
@@ -0,0 +1,294 @@
+use crate::asm::{ParseError, Position, Span};
+use std::{fmt, iter::Peekable, str::CharIndices};
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Token<'src> {
+    Ident(&'src str),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    LBracket,
+    RBracket,
+    Colon,
+    Comma,
+    Semicolon,
+    Assign,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Bang,
+    AndAnd,
+    OrOr,
+    Newline,
+    Eof,
+}
+
+impl fmt::Display for Token<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            Token::Ident(ident) => return write!(f, "`{ident}`"),
+            Token::Int(value) => return write!(f, "`{value}`"),
+            Token::Float(value) => return write!(f, "`{value:?}`"),
+            Token::Str(value) => return write!(f, "{value:?}"),
+            Token::Newline => return write!(f, "end of line"),
+            Token::Eof => return write!(f, "end of input"),
+            Token::LParen => "(",
+            Token::RParen => ")",
+            Token::LBrace => "{",
+            Token::RBrace => "}",
+            Token::LBracket => "[",
+            Token::RBracket => "]",
+            Token::Colon => ":",
+            Token::Comma => ",",
+            Token::Semicolon => ";",
+            Token::Assign => "=",
+            Token::Eq => "==",
+            Token::Ne => "!=",
+            Token::Lt => "<",
+            Token::Le => "<=",
+            Token::Gt => ">",
+            Token::Ge => ">=",
+            Token::Plus => "+",
+            Token::Minus => "-",
+            Token::Star => "*",
+            Token::Slash => "/",
+            Token::Bang => "!",
+            Token::AndAnd => "&&",
+            Token::OrOr => "||",
+        };
+        write!(f, "`{symbol}`")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Spanned<'src> {
+    pub token: Token<'src>,
+    pub span: Span,
+}
+
+pub(crate) struct Lexer<'src> {
+    src: &'src str,
+    chars: Peekable<CharIndices<'src>>,
+    position: Position,
+    /// Open `(` and `[`, line breaks inside of them do not end statements.
+    depth: usize,
+}
+
+impl<'src> Lexer<'src> {
+    pub fn new(src: &'src str) -> Self {
+        Self {
+            src,
+            chars: src.char_indices().peekable(),
+            position: Position { line: 1, column: 1 },
+            depth: 0,
+        }
+    }
+
+    /// Split the whole source into tokens, the last token is always [`Token::Eof`].
+    pub fn tokenize(mut self) -> Result<Vec<Spanned<'src>>, ParseError> {
+        let mut tokens = Vec::new();
+        loop {
+            let spanned = self.next_token()?;
+            let is_eof = spanned.token == Token::Eof;
+            tokens.push(spanned);
+            if is_eof {
+                break Ok(tokens);
+            }
+        }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let (_, c) = self.chars.next()?;
+        if c == '\n' {
+            self.position.line += 1;
+            self.position.column = 1;
+        } else {
+            self.position.column += 1;
+        }
+        Some(c)
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|&(_, c)| c)
+    }
+
+    fn offset(&mut self) -> usize {
+        self.chars.peek().map_or(self.src.len(), |&(i, _)| i)
+    }
+
+    fn eat_while(&mut self, pred: impl Fn(char) -> bool) -> &'src str {
+        let start = self.offset();
+        while self.peek().is_some_and(&pred) {
+            self.bump();
+        }
+        &self.src[start..self.offset()]
+    }
+
+    fn error(&self, start: Position, message: impl Into<String>) -> ParseError {
+        ParseError::new(
+            Span {
+                start,
+                end: self.position,
+            },
+            message,
+        )
+    }
+
+    fn next_token(&mut self) -> Result<Spanned<'src>, ParseError> {
+        let token = loop {
+            self.eat_while(|c| c.is_whitespace() && c != '\n');
+            match self.peek() {
+                Some('#') => {
+                    self.eat_while(|c| c != '\n');
+                }
+                Some('\n') if self.depth > 0 => {
+                    self.bump();
+                }
+                _ => break self.token()?,
+            }
+        };
+
+        match token.token {
+            Token::LParen | Token::LBracket => self.depth += 1,
+            Token::RParen | Token::RBracket => self.depth = self.depth.saturating_sub(1),
+            _ => (),
+        }
+        Ok(token)
+    }
+
+    fn token(&mut self) -> Result<Spanned<'src>, ParseError> {
+        let start = self.position;
+        let Some(c) = self.peek() else {
+            return Ok(Spanned {
+                token: Token::Eof,
+                span: Span { start, end: start },
+            });
+        };
+
+        let token = match c {
+            '"' => self.string(start)?,
+            '0'..='9' => self.number(start)?,
+            c if is_ident_start(c) => Token::Ident(self.eat_while(is_ident_continue)),
+            c => {
+                self.bump();
+                let next = self.peek();
+                let mut pair = |token| {
+                    self.bump();
+                    token
+                };
+                match (c, next) {
+                    ('=', Some('=')) => pair(Token::Eq),
+                    ('!', Some('=')) => pair(Token::Ne),
+                    ('<', Some('=')) => pair(Token::Le),
+                    ('>', Some('=')) => pair(Token::Ge),
+                    ('&', Some('&')) => pair(Token::AndAnd),
+                    ('|', Some('|')) => pair(Token::OrOr),
+                    ('\n', _) => Token::Newline,
+                    ('(', _) => Token::LParen,
+                    (')', _) => Token::RParen,
+                    ('{', _) => Token::LBrace,
+                    ('}', _) => Token::RBrace,
+                    ('[', _) => Token::LBracket,
+                    (']', _) => Token::RBracket,
+                    (':', _) => Token::Colon,
+                    (',', _) => Token::Comma,
+                    (';', _) => Token::Semicolon,
+                    ('=', _) => Token::Assign,
+                    ('<', _) => Token::Lt,
+                    ('>', _) => Token::Gt,
+                    ('+', _) => Token::Plus,
+                    ('-', _) => Token::Minus,
+                    ('*', _) => Token::Star,
+                    ('/', _) => Token::Slash,
+                    ('!', _) => Token::Bang,
+                    _ => return Err(self.error(start, format!("unexpected character {c:?}"))),
+                }
+            }
+        };
+
+        Ok(Spanned {
+            token,
+            span: Span {
+                start,
+                end: self.position,
+            },
+        })
+    }
+
+    fn string(&mut self, start: Position) -> Result<Token<'src>, ParseError> {
+        self.bump();
+        let mut value = String::new();
+        loop {
+            match self.bump() {
+                None | Some('\n') => return Err(self.error(start, "unterminated string")),
+                Some('"') => break Ok(Token::Str(value)),
+                Some('\\') => value.push(match self.bump() {
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('0') => '\0',
+                    Some(c @ ('\\' | '"' | '\'')) => c,
+                    Some(c) => return Err(self.error(start, format!("unknown escape `\\{c}`"))),
+                    None => return Err(self.error(start, "unterminated string")),
+                }),
+                Some(c) => value.push(c),
+            }
+        }
+    }
+
+    fn number(&mut self, start: Position) -> Result<Token<'src>, ParseError> {
+        let offset = self.offset();
+        let mut is_float = false;
+        self.eat_while(|c| c.is_ascii_digit());
+
+        if self.peek() == Some('.') {
+            is_float = true;
+            self.bump();
+            self.eat_while(|c| c.is_ascii_digit());
+        }
+        if let Some('e' | 'E') = self.peek() {
+            is_float = true;
+            self.bump();
+            if let Some('+' | '-') = self.peek() {
+                self.bump();
+            }
+            if self.eat_while(|c| c.is_ascii_digit()).is_empty() {
+                return Err(self.error(start, "expected exponent digits"));
+            }
+        }
+        if self.peek().is_some_and(is_ident_continue) {
+            self.eat_while(is_ident_continue);
+            return Err(self.error(start, "invalid number literal"));
+        }
+
+        let text = &self.src[offset..self.offset()];
+        if is_float {
+            text.parse()
+                .map(Token::Float)
+                .map_err(|_| self.error(start, "invalid float literal"))
+        } else {
+            text.parse()
+                .map(Token::Int)
+                .map_err(|_| self.error(start, "integer literal out of range"))
+        }
+    }
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_ident_continue(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
@@ -0,0 +1,528 @@
+use super::lexer::{Lexer, Spanned, Token};
+use crate::{
+    asm::{ParseError, Position, Span},
+    value::Value,
+};
+use std::sync::Arc;
+
+/// Words that cannot be used as names.
+const KEYWORDS: &[&str] = &[
+    "let", "fn", "if", "else", "while", "try", "catch", "throw", "return", "true", "false", "none",
+    "input",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Script {
+    pub functions: Vec<Function>,
+    pub statements: Vec<Stmt>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Function {
+    pub name: String,
+    pub span: Span,
+    pub params: Vec<String>,
+    pub body: Block,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Block(pub Vec<Stmt>);
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum StmtKind {
+    Let(String, Expr),
+    Assign {
+        name: String,
+        index: Option<Expr>,
+        value: Expr,
+    },
+    While(Expr, Block),
+    Throw(Expr),
+    /// Returns none without a value.
+    Return(Option<Expr>),
+    Expr(Expr),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ExprKind {
+    Literal(Value),
+    Var(String),
+    Input,
+    List(Vec<Expr>),
+    Map(Vec<(Arc<str>, Expr)>),
+    Index(Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    If(Box<Expr>, Block, Option<Block>),
+    /// Body, name of the error and handler.
+    Try(Block, String, Block),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+impl BinOp {
+    /// Binding strength, higher binds tighter.
+    fn precedence(self) -> u8 {
+        match self {
+            BinOp::Or => 1,
+            BinOp::And => 2,
+            BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => 3,
+            BinOp::Add | BinOp::Sub => 4,
+            BinOp::Mul | BinOp::Div => 5,
+        }
+    }
+
+    fn of(token: &Token) -> Option<Self> {
+        Some(match token {
+            Token::Plus => BinOp::Add,
+            Token::Minus => BinOp::Sub,
+            Token::Star => BinOp::Mul,
+            Token::Slash => BinOp::Div,
+            Token::Eq => BinOp::Eq,
+            Token::Ne => BinOp::Ne,
+            Token::Lt => BinOp::Lt,
+            Token::Le => BinOp::Le,
+            Token::Gt => BinOp::Gt,
+            Token::Ge => BinOp::Ge,
+            Token::AndAnd => BinOp::And,
+            Token::OrOr => BinOp::Or,
+            _ => return None,
+        })
+    }
+}
+
+pub(crate) fn parse(src: &str) -> Result<Script, ParseError> {
+    let mut parser = Parser::new(src)?;
+    let mut functions = Vec::new();
+    let mut statements = Vec::new();
+
+    loop {
+        parser.skip_separators();
+        match parser.peek().token {
+            Token::Eof => break,
+            Token::Ident("fn") => functions.push(parser.function()?),
+            _ => statements.push(parser.statement()?),
+        }
+        parser.end_of_statement()?;
+    }
+
+    Ok(Script {
+        functions,
+        statements,
+    })
+}
+
+struct Parser<'src> {
+    tokens: Vec<Spanned<'src>>,
+    pos: usize,
+}
+
+impl<'src> Parser<'src> {
+    fn new(src: &'src str) -> Result<Self, ParseError> {
+        Ok(Self {
+            tokens: Lexer::new(src).tokenize()?,
+            pos: 0,
+        })
+    }
+
+    fn peek(&self) -> &Spanned<'src> {
+        &self.tokens[self.pos.min(self.tokens.len() - 1)]
+    }
+
+    fn peek_nth(&self, n: usize) -> &Token<'src> {
+        &self.tokens[(self.pos + n).min(self.tokens.len() - 1)].token
+    }
+
+    fn next(&mut self) -> Spanned<'src> {
+        let spanned = self.peek().clone();
+        if spanned.token != Token::Eof {
+            self.pos += 1;
+        }
+        spanned
+    }
+
+    /// Span from `start` to the end of the last consumed token.
+    fn span_from(&self, start: Position) -> Span {
+        let end = self
+            .pos
+            .checked_sub(1)
+            .map_or(start, |last| self.tokens[last].span.end);
+        Span { start, end }
+    }
+
+    fn error(span: Span, message: impl Into<String>) -> ParseError {
+        ParseError::new(span, message)
+    }
+
+    fn unexpected(spanned: &Spanned, expected: &str) -> ParseError {
+        Self::error(
+            spanned.span,
+            format!("expected {expected}, found {}", spanned.token),
+        )
+    }
+
+    fn expect(&mut self, token: &Token) -> Result<Span, ParseError> {
+        let spanned = self.next();
+        if &spanned.token == token {
+            Ok(spanned.span)
+        } else {
+            Err(Self::unexpected(&spanned, &token.to_string()))
+        }
+    }
+
+    fn skip_separators(&mut self) {
+        while let Token::Newline | Token::Semicolon = self.peek().token {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while let Token::Newline = self.peek().token {
+            self.pos += 1;
+        }
+    }
+
+    fn end_of_statement(&mut self) -> Result<(), ParseError> {
+        match self.peek().token {
+            Token::Newline | Token::Semicolon => {
+                self.pos += 1;
+                Ok(())
+            }
+            Token::RBrace | Token::Eof => Ok(()),
+            _ => Err(Self::unexpected(self.peek(), "end of line")),
+        }
+    }
+
+    fn name(&mut self) -> Result<String, ParseError> {
+        let spanned = self.next();
+        match spanned.token {
+            Token::Ident(name) if KEYWORDS.contains(&name) => Err(Self::error(
+                spanned.span,
+                format!("`{name}` cannot be used as a name"),
+            )),
+            Token::Ident(name) => Ok(name.to_owned()),
+            _ => Err(Self::unexpected(&spanned, "a name")),
+        }
+    }
+
+    fn function(&mut self) -> Result<Function, ParseError> {
+        let start = self.expect(&Token::Ident("fn"))?.start;
+        let name = self.name()?;
+        let span = self.span_from(start);
+
+        self.expect(&Token::LParen)?;
+        let mut params = Vec::new();
+        while self.peek().token != Token::RParen {
+            params.push(self.name()?);
+            if self.peek().token != Token::RParen {
+                self.expect(&Token::Comma)?;
+            }
+        }
+        self.expect(&Token::RParen)?;
+
+        Ok(Function {
+            name,
+            span,
+            params,
+            body: self.block()?,
+        })
+    }
+
+    fn block(&mut self) -> Result<Block, ParseError> {
+        self.expect(&Token::LBrace)?;
+        let mut statements = Vec::new();
+        loop {
+            self.skip_separators();
+            match self.peek().token {
+                Token::RBrace => {
+                    self.pos += 1;
+                    break Ok(Block(statements));
+                }
+                Token::Ident("fn") => {
+                    break Err(Self::error(
+                        self.peek().span,
+                        "functions can only be defined at the top level",
+                    ))
+                }
+                _ => statements.push(self.statement()?),
+            }
+            self.end_of_statement()?;
+        }
+    }
+
+    fn statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.peek().span.start;
+        let kind = match (self.peek().token.clone(), self.peek_nth(1)) {
+            (Token::Ident("let"), _) => {
+                self.pos += 1;
+                let name = self.name()?;
+                self.expect(&Token::Assign)?;
+                StmtKind::Let(name, self.expr()?)
+            }
+            (Token::Ident("while"), _) => {
+                self.pos += 1;
+                StmtKind::While(self.expr()?, self.block()?)
+            }
+            (Token::Ident("throw"), _) => {
+                self.pos += 1;
+                StmtKind::Throw(self.expr()?)
+            }
+            (
+                Token::Ident("return"),
+                Token::Newline | Token::Semicolon | Token::RBrace | Token::Eof,
+            ) => {
+                self.pos += 1;
+                StmtKind::Return(None)
+            }
+            (Token::Ident("return"), _) => {
+                self.pos += 1;
+                StmtKind::Return(Some(self.expr()?))
+            }
+            (Token::Ident(name), Token::Assign | Token::LBracket) if !KEYWORDS.contains(&name) => {
+                self.assignment()?
+            }
+            _ => StmtKind::Expr(self.expr()?),
+        };
+
+        Ok(Stmt {
+            kind,
+            span: self.span_from(start),
+        })
+    }
+
+    /// Either an assignment or an expression starting with an indexed variable.
+    fn assignment(&mut self) -> Result<StmtKind, ParseError> {
+        let start = self.pos;
+        let name = self.name()?;
+        let index = if self.peek().token == Token::LBracket {
+            self.pos += 1;
+            let index = self.expr()?;
+            self.expect(&Token::RBracket)?;
+            Some(index)
+        } else {
+            None
+        };
+
+        if self.peek().token == Token::Assign {
+            self.pos += 1;
+            Ok(StmtKind::Assign {
+                name,
+                index,
+                value: self.expr()?,
+            })
+        } else {
+            self.pos = start;
+            Ok(StmtKind::Expr(self.expr()?))
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, ParseError> {
+        self.binary(0)
+    }
+
+    /// Operators of equal precedence associate to the left.
+    fn binary(&mut self, min_precedence: u8) -> Result<Expr, ParseError> {
+        let start = self.peek().span.start;
+        let mut lhs = self.unary()?;
+
+        while let Some(op) = BinOp::of(&self.peek().token) {
+            if op.precedence() <= min_precedence {
+                break;
+            }
+            self.pos += 1;
+            self.skip_newlines();
+            let rhs = self.binary(op.precedence())?;
+            lhs = Expr {
+                kind: ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)),
+                span: self.span_from(start),
+            };
+        }
+
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        let start = self.peek().span.start;
+        let kind = match self.peek().token {
+            Token::Minus => {
+                self.pos += 1;
+                match self.unary()? {
+                    Expr {
+                        kind: ExprKind::Literal(Value::Int(value)),
+                        ..
+                    } => ExprKind::Literal(Value::Int(-value)),
+                    Expr {
+                        kind: ExprKind::Literal(Value::Float(value)),
+                        ..
+                    } => ExprKind::Literal(Value::Float(-value)),
+                    expr => ExprKind::Neg(Box::new(expr)),
+                }
+            }
+            Token::Bang => {
+                self.pos += 1;
+                ExprKind::Not(Box::new(self.unary()?))
+            }
+            _ => return self.postfix(),
+        };
+
+        Ok(Expr {
+            kind,
+            span: self.span_from(start),
+        })
+    }
+
+    fn postfix(&mut self) -> Result<Expr, ParseError> {
+        let start = self.peek().span.start;
+        let mut expr = self.primary()?;
+
+        loop {
+            let kind = match self.peek().token {
+                Token::LBracket => {
+                    self.pos += 1;
+                    let index = self.expr()?;
+                    self.expect(&Token::RBracket)?;
+                    ExprKind::Index(Box::new(expr), Box::new(index))
+                }
+                Token::LParen => {
+                    let ExprKind::Var(name) = expr.kind else {
+                        return Err(Self::error(expr.span, "only functions can be called"));
+                    };
+                    self.pos += 1;
+                    ExprKind::Call(name, self.items(&Token::RParen, Self::expr)?)
+                }
+                _ => break Ok(expr),
+            };
+            expr = Expr {
+                kind,
+                span: self.span_from(start),
+            };
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParseError> {
+        let spanned = self.next();
+        let kind = match spanned.token {
+            Token::Int(value) => ExprKind::Literal(Value::Int(value)),
+            Token::Float(value) => ExprKind::Literal(Value::Float(value)),
+            Token::Str(value) => ExprKind::Literal(Value::string(value)),
+            Token::Ident("true") => ExprKind::Literal(Value::Bool(true)),
+            Token::Ident("false") => ExprKind::Literal(Value::Bool(false)),
+            Token::Ident("none") => ExprKind::Literal(Value::None),
+            Token::Ident("input") => ExprKind::Input,
+            Token::Ident("if") => self.if_else()?,
+            Token::Ident("try") => self.try_catch()?,
+            Token::Ident(name) if KEYWORDS.contains(&name) => {
+                return Err(Self::unexpected(&spanned, "an expression"))
+            }
+            Token::Ident(name) => ExprKind::Var(name.to_owned()),
+            Token::LParen => {
+                let expr = self.expr()?;
+                self.expect(&Token::RParen)?;
+                expr.kind
+            }
+            Token::LBracket => ExprKind::List(self.items(&Token::RBracket, Self::expr)?),
+            Token::LBrace => ExprKind::Map(self.items(&Token::RBrace, Self::entry)?),
+            _ => return Err(Self::unexpected(&spanned, "an expression")),
+        };
+
+        Ok(Expr {
+            kind,
+            span: self.span_from(spanned.span.start),
+        })
+    }
+
+    fn if_else(&mut self) -> Result<ExprKind, ParseError> {
+        let condition = self.expr()?;
+        let if_true = self.block()?;
+
+        // `else` may be on the line after the closing brace
+        let after_block = self.pos;
+        self.skip_newlines();
+        let if_false = if self.peek().token == Token::Ident("else") {
+            self.pos += 1;
+            if self.peek().token == Token::Ident("if") {
+                let stmt = self.statement()?;
+                Some(Block(vec![stmt]))
+            } else {
+                Some(self.block()?)
+            }
+        } else {
+            self.pos = after_block;
+            None
+        };
+
+        Ok(ExprKind::If(Box::new(condition), if_true, if_false))
+    }
+
+    fn try_catch(&mut self) -> Result<ExprKind, ParseError> {
+        let body = self.block()?;
+        self.skip_newlines();
+        self.expect(&Token::Ident("catch"))?;
+        let name = self.name()?;
+        let handler = self.block()?;
+        Ok(ExprKind::Try(body, name, handler))
+    }
+
+    fn entry(&mut self) -> Result<(Arc<str>, Expr), ParseError> {
+        let spanned = self.next();
+        let Token::Str(key) = spanned.token else {
+            return Err(Self::unexpected(&spanned, "a string key"));
+        };
+        self.skip_newlines();
+        self.expect(&Token::Colon)?;
+        self.skip_newlines();
+        Ok((key.into(), self.expr()?))
+    }
+
+    /// Comma separated items up to `close`, a trailing comma is allowed.
+    fn items<T>(
+        &mut self,
+        close: &Token,
+        mut item: impl FnMut(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<Vec<T>, ParseError> {
+        let mut items = Vec::new();
+        loop {
+            self.skip_newlines();
+            if &self.peek().token == close {
+                self.pos += 1;
+                break Ok(items);
+            }
+            items.push(item(self)?);
+            self.skip_newlines();
+            let spanned = self.next();
+            match spanned.token {
+                Token::Comma => (),
+                ref token if token == close => break Ok(items),
+                _ => return Err(Self::unexpected(&spanned, &format!("`,` or {close}"))),
+            }
+        }
+    }
+}
@@ -8,6 +8,7 @@ use std::{
     borrow::Cow,
     cmp,
     collections::{BTreeMap, HashMap},
+    mem,
     ops::{Add, Div, Mul, Sub},
     sync::Arc,
 };
@@ -65,6 +66,32 @@ impl Value {
         Self::String(value.into())
     }
 
+    /// Estimate of the number of bytes the value owns on the heap. Strings and programs are
+    /// counted for every value referring to them, even though they are shared.
+    #[must_use]
+    pub fn heap_size(&self) -> usize {
+        match self {
+            Value::String(value) => value.len(),
+            Value::Instruction(instr) => mem::size_of::<Instruction>() + instr.heap_size(),
+            Value::List(list) => {
+                list.capacity() * mem::size_of::<Value>()
+                    + list.iter().map(Value::heap_size).sum::<usize>()
+            }
+            Value::Map(map) => map
+                .iter()
+                .map(|(key, value)| {
+                    mem::size_of::<(Arc<str>, Value)>() + key.len() + value.heap_size()
+                })
+                .sum(),
+            Value::Bool(_)
+            | Value::Int(_)
+            | Value::Float(_)
+            | Value::Id(_)
+            | Value::Type(_)
+            | Value::None => 0,
+        }
+    }
+
     pub fn cast(self, to: Type) -> Result<Self> {
         use Value::{Bool, Float, Instruction, Int, List, Map, String};
 
@@ -230,12 +257,12 @@ impl Add for Value {
                 .pipe(Instruction::from)
                 .pipe(Box::new)
                 .pipe(Value::Instruction),
-            [Value::List(lhs), Value::List(rhs)] => lhs
-                .tap_mut(|lhs| lhs.extend(rhs.into_iter()))
-                .pipe(Value::List),
-            [Value::Map(lhs), Value::Map(rhs)] => lhs
-                .tap_mut(|lhs| lhs.extend(rhs.into_iter()))
-                .pipe(Value::Map),
+            [Value::List(lhs), Value::List(rhs)] => {
+                lhs.tap_mut(|lhs| lhs.extend(rhs)).pipe(Value::List)
+            }
+            [Value::Map(lhs), Value::Map(rhs)] => {
+                lhs.tap_mut(|lhs| lhs.extend(rhs)).pipe(Value::Map)
+            }
             [lhs, rhs] => return Error::UnsuppurtedOperation(Operation::Add, lhs, rhs).pipe(Err),
         }
         .pipe(Ok)
@@ -255,7 +282,7 @@ impl Sub for Value {
                     rhs: rhs.into(),
                 })?
                 .pipe(Value::Int),
-            [Value::Float(lhs), Value::Float(rhs)] => Value::Float(rhs - lhs),
+            [Value::Float(lhs), Value::Float(rhs)] => Value::Float(lhs - rhs),
             [lhs, rhs] => return Error::UnsuppurtedOperation(Operation::Sub, lhs, rhs).pipe(Err),
         }
         .pipe(Ok)
@@ -275,7 +302,7 @@ impl Mul for Value {
                     rhs: rhs.into(),
                 })?
                 .pipe(Value::Int),
-            [Value::Float(lhs), Value::Float(rhs)] => Value::Float(rhs * lhs),
+            [Value::Float(lhs), Value::Float(rhs)] => Value::Float(lhs * rhs),
             [lhs, rhs] => return Error::UnsuppurtedOperation(Operation::Mul, lhs, rhs).pipe(Err),
         }
         .pipe(Ok)
@@ -298,7 +325,7 @@ impl Div for Value {
                     rhs: rhs.into(),
                 })?
                 .pipe(Value::Int),
-            [Value::Float(lhs), Value::Float(rhs)] => Value::Float(rhs / lhs),
+            [Value::Float(lhs), Value::Float(rhs)] => Value::Float(lhs / rhs),
             [lhs, rhs] => return Error::UnsuppurtedOperation(Operation::Div, lhs, rhs).pipe(Err),
         }
         .pipe(Ok)
@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{fmt, mem, sync::Arc};
 
 use crate::{value::Value, Error, Result};
 use serde::{Deserialize, Serialize};
@@ -13,12 +13,54 @@ enum IdInternal {
     Ro(usize),
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
-pub struct Map(Box<[Value]>, Arc<[Value]>);
+impl Id {
+    pub(crate) fn rw(index: usize) -> Self {
+        Self(IdInternal::Rw(index))
+    }
+
+    pub(crate) fn ro(index: usize) -> Self {
+        Self(IdInternal::Ro(index))
+    }
+
+    /// Index of the variable among the variables of the same kind.
+    #[must_use]
+    pub fn index(&self) -> usize {
+        match self.0 {
+            IdInternal::Rw(index) | IdInternal::Ro(index) => index,
+        }
+    }
+
+    #[must_use]
+    pub fn is_read_only(&self) -> bool {
+        matches!(self.0, IdInternal::Ro(_))
+    }
+}
+
+/// Read-write and read-only variables. The heap sizes of read-write variables are remembered,
+/// until they are borrowed mutably, for [`update_heap_size`][Self::update_heap_size].
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Map(
+    Box<[Value]>,
+    Arc<[Value]>,
+    #[serde(skip)] Vec<Option<usize>>,
+);
 
 impl Default for Map {
     fn default() -> Self {
-        Self(Box::default(), Arc::new([]))
+        Self(Box::default(), Arc::from([]), Vec::new())
+    }
+}
+
+// the remembered heap sizes only save measuring them again
+impl PartialEq for Map {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0 && self.1 == other.1
+    }
+}
+
+impl fmt::Debug for Map {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Map").field(&self.0).field(&self.1).finish()
     }
 }
 
@@ -32,11 +74,54 @@ impl Map {
 
     pub fn read_mut(&mut self, id: Id) -> Result<&mut Value> {
         match id.0 {
-            IdInternal::Rw(id_index) => self.0.get_mut(id_index).ok_or(Error::UnknownVariable(id)),
+            IdInternal::Rw(id_index) => {
+                if let Some(size) = self.2.get_mut(id_index) {
+                    *size = None;
+                }
+                self.0.get_mut(id_index).ok_or(Error::UnknownVariable(id))
+            }
             IdInternal::Ro(_) => Err(Error::WriteToReadOnly(id)),
         }
     }
 
+    /// Estimate of the number of bytes the variables own on the heap, see [`Value::heap_size`].
+    /// Read-only variables are shared with the program and only counted by the size of their
+    /// slots.
+    #[must_use]
+    pub fn heap_size(&self) -> usize {
+        (self.0.len() + self.1.len()) * mem::size_of::<Value>()
+            + self.0.iter().map(Value::heap_size).sum::<usize>()
+    }
+
+    /// [`heap_size`][Self::heap_size], measuring only the variables that were borrowed mutably
+    /// since it was last called.
+    pub(crate) fn update_heap_size(&mut self) -> usize {
+        let Self(rw, ro, sizes) = self;
+        sizes.resize(rw.len(), None);
+        (rw.len() + ro.len()) * mem::size_of::<Value>()
+            + rw.iter()
+                .zip(sizes)
+                .map(|(value, size)| *size.get_or_insert_with(|| value.heap_size()))
+                .sum::<usize>()
+    }
+
+    /// Set variables back to values saved from them, the value saved first is restored last.
+    /// Read-only and unknown variables are skipped, values cannot have been saved from them.
+    pub fn restore(&mut self, saved: Vec<(Id, Value)>) {
+        for (id, value) in saved.into_iter().rev() {
+            if let Ok(variable) = self.read_mut(id) {
+                *variable = value;
+            }
+        }
+    }
+
+    /// Iterate over all variables, read-write variables come first.
+    pub fn iter(&self) -> impl Iterator<Item = (Id, &Value)> {
+        let rw = self.0.iter().enumerate().map(|(i, v)| (Id::rw(i), v));
+        let ro = self.1.iter().enumerate().map(|(i, v)| (Id::ro(i), v));
+        rw.chain(ro)
+    }
+
     pub fn maybe_read(&self, value: Value) -> Result<Value> {
         if let Value::Id(id) = value {
             self.read(id).cloned()
@@ -49,6 +134,14 @@ impl Map {
 #[derive(Debug, Clone, Default)]
 pub struct MapBuilder(Vec<Value>, Vec<Value>);
 
+/// Allows more variables to be added to a map that is already in use.
+impl From<Map> for MapBuilder {
+    fn from(value: Map) -> Self {
+        let Map(rw, ro, _) = value;
+        Self(rw.into_vec(), ro.to_vec())
+    }
+}
+
 impl MapBuilder {
     #[must_use]
     pub fn new() -> Self {
@@ -93,6 +186,7 @@ impl MapBuilder {
         Map(
             self.0.into_boxed_slice(),
             self.1.into_boxed_slice().pipe(Arc::from),
+            Vec::new(),
         )
     }
 }